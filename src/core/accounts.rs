@@ -0,0 +1,453 @@
+use crate::core::fstree::FsTree;
+
+use std::fmt;
+use std::fs;
+use std::io;
+
+// UIDs below this value are considered as service accounts
+const FIRST_USER_UID: u32 = 1000;
+
+// Shells that do not give an interactive session
+const NON_INTERACTIVE_SHELLS: [&str; 5] = ["nologin", "false", "sync", "shutdown", "halt"];
+
+#[derive(Debug, Clone)]
+pub struct PasswdEntry {
+    pub name: String,
+    pub password: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub gecos: String,
+    pub home: String,
+    pub shell: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShadowEntry {
+    pub name: String,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupEntry {
+    pub name: String,
+    pub password: String,
+    pub gid: u32,
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashType {
+    Empty,
+    Locked,
+    Des,
+    Md5,
+    Blowfish,
+    Sha256,
+    Sha512,
+    Yescrypt,
+    Unknown,
+}
+
+impl HashType {
+    pub fn from_hash(hash: &str) -> Self {
+        if hash.is_empty() {
+            HashType::Empty
+        } else if hash.starts_with('!') || hash.starts_with('*') {
+            HashType::Locked
+        } else if hash.starts_with("$1$") {
+            HashType::Md5
+        } else if hash.starts_with("$2a$") || hash.starts_with("$2b$") || hash.starts_with("$2y$") {
+            HashType::Blowfish
+        } else if hash.starts_with("$5$") {
+            HashType::Sha256
+        } else if hash.starts_with("$6$") {
+            HashType::Sha512
+        } else if hash.starts_with("$y$") {
+            HashType::Yescrypt
+        } else if hash.len() == 13 && !hash.starts_with('$') {
+            HashType::Des
+        } else {
+            HashType::Unknown
+        }
+    }
+
+    pub fn is_weak(&self) -> bool {
+        matches!(self, HashType::Des | HashType::Md5)
+    }
+}
+
+#[derive(Debug)]
+pub enum AccountIssue {
+    // The password field is empty, no password is needed to log in
+    EmptyPassword { user: String },
+    // The password is stored in the shadow file but the user has no shadow entry
+    UnsetPassword { user: String },
+    // The password is hashed with DES or MD5
+    WeakHash { user: String, hash_type: HashType },
+    // More than one account has the UID 0
+    DuplicateRoot { users: Vec<String> },
+    // A service account has an interactive shell
+    ServiceShell { user: String, shell: String },
+    // The home directory of the account is not in the tree
+    MissingHome { user: String, home: String },
+    // The password was found in the wordlist
+    CrackedPassword { user: String, password: String },
+}
+
+impl fmt::Display for AccountIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccountIssue::EmptyPassword { user } => write!(f, "{}: empty password", user),
+            AccountIssue::UnsetPassword { user } => write!(f, "{}: password not set in shadow", user),
+            AccountIssue::WeakHash { user, hash_type } => write!(f, "{}: weak {:?} hash", user, hash_type),
+            AccountIssue::DuplicateRoot { users } => write!(f, "UID 0 accounts: {}", users.join(", ")),
+            AccountIssue::ServiceShell { user, shell } => {
+                write!(f, "{}: service account with interactive shell {}", user, shell)
+            }
+            AccountIssue::MissingHome { user, home } => write!(f, "{}: home {} not found", user, home),
+            AccountIssue::CrackedPassword { user, password } => {
+                write!(f, "{}: password cracked ({})", user, password)
+            }
+        }
+    }
+}
+
+pub struct AccountAudit {
+    pub passwd: Vec<PasswdEntry>,
+    pub shadow: Vec<ShadowEntry>,
+    pub groups: Vec<GroupEntry>,
+    pub issues: Vec<AccountIssue>,
+}
+
+impl AccountAudit {
+    pub fn new(fstree: &FsTree) -> Self {
+        let passwd = read_tree_file(fstree, "/etc/passwd")
+            .map(|content| parse_passwd(&content))
+            .unwrap_or_default();
+        let shadow = read_tree_file(fstree, "/etc/shadow")
+            .map(|content| parse_shadow(&content))
+            .unwrap_or_default();
+        let groups = read_tree_file(fstree, "/etc/group")
+            .map(|content| parse_group(&content))
+            .unwrap_or_default();
+
+        let mut audit = Self {
+            passwd,
+            shadow,
+            groups,
+            issues: Vec::new(),
+        };
+        audit.check(fstree);
+        audit
+    }
+
+    /*
+        Get the password hash of a user, from the shadow file if needed
+    */
+    pub fn password_hash(&self, user: &PasswdEntry) -> Option<String> {
+        if user.password == "x" {
+            self.shadow
+                .iter()
+                .find(|s| s.name == user.name)
+                .map(|s| s.hash.clone())
+        } else {
+            Some(user.password.clone())
+        }
+    }
+
+    fn check(&mut self, fstree: &FsTree) {
+        let mut issues = Vec::new();
+        let mut root_users = Vec::new();
+
+        for user in &self.passwd {
+            match self.password_hash(user) {
+                Some(hash) => {
+                    let hash_type = HashType::from_hash(&hash);
+                    if hash_type == HashType::Empty {
+                        issues.push(AccountIssue::EmptyPassword { user: user.name.clone() });
+                    } else if hash_type.is_weak() {
+                        issues.push(AccountIssue::WeakHash {
+                            user: user.name.clone(),
+                            hash_type,
+                        });
+                    }
+                }
+                None => issues.push(AccountIssue::UnsetPassword { user: user.name.clone() }),
+            }
+
+            if user.uid == 0 {
+                root_users.push(user.name.clone());
+            } else if user.uid < FIRST_USER_UID && is_interactive_shell(&user.shell) {
+                issues.push(AccountIssue::ServiceShell {
+                    user: user.name.clone(),
+                    shell: user.shell.clone(),
+                });
+            }
+
+            if !user.home.is_empty() && fstree.get(&user.home).is_none() {
+                issues.push(AccountIssue::MissingHome {
+                    user: user.name.clone(),
+                    home: user.home.clone(),
+                });
+            }
+        }
+
+        if root_users.len() > 1 {
+            issues.push(AccountIssue::DuplicateRoot { users: root_users });
+        }
+
+        self.issues.append(&mut issues);
+    }
+
+    /*
+        Try every password of a local wordlist against the crackable hashes
+    */
+    pub fn crack_passwords(&mut self, wordlist_path: &str) -> io::Result<()> {
+        let content = fs::read(wordlist_path)?;
+        let words: Vec<String> = content
+            .split(|b| *b == b'\n')
+            .map(|word| String::from_utf8_lossy(word).trim_end_matches('\r').to_string())
+            .filter(|word| !word.is_empty())
+            .collect();
+
+        let mut cracked = Vec::new();
+
+        for user in &self.passwd {
+            let hash = match self.password_hash(user) {
+                Some(hash) => hash,
+                None => continue,
+            };
+
+            match HashType::from_hash(&hash) {
+                HashType::Empty | HashType::Locked | HashType::Unknown => continue,
+                _ => {}
+            }
+
+            if let Some(word) = words.iter().find(|word| pwhash::unix::verify(word.as_str(), &hash)) {
+                cracked.push(AccountIssue::CrackedPassword {
+                    user: user.name.clone(),
+                    password: word.clone(),
+                });
+            }
+        }
+
+        self.issues.append(&mut cracked);
+        Ok(())
+    }
+
+    pub fn display_issues(&self) {
+        for issue in &self.issues {
+            println!("{}", issue);
+        }
+    }
+}
+
+fn read_tree_file(fstree: &FsTree, path: &str) -> Option<String> {
+    let bytes = fstree.read_file(path)?;
+    Some(String::from_utf8_lossy(&bytes).to_string())
+}
+
+fn is_interactive_shell(shell: &str) -> bool {
+    // An empty shell field defaults to /bin/sh
+    let shell_name = shell.rsplit('/').next().unwrap_or("");
+    !NON_INTERACTIVE_SHELLS.contains(&shell_name)
+}
+
+fn fields(line: &str) -> Option<Vec<&str>> {
+    let line = line.trim_end_matches('\r');
+    if line.is_empty() || line.starts_with('#') {
+        None
+    } else {
+        Some(line.split(':').collect())
+    }
+}
+
+pub fn parse_passwd(content: &str) -> Vec<PasswdEntry> {
+    content
+        .lines()
+        .filter_map(fields)
+        .filter(|f| f.len() >= 7)
+        .filter_map(|f| {
+            Some(PasswdEntry {
+                name: f[0].to_string(),
+                password: f[1].to_string(),
+                uid: f[2].parse().ok()?,
+                gid: f[3].parse().ok()?,
+                gecos: f[4].to_string(),
+                home: f[5].to_string(),
+                shell: f[6].to_string(),
+            })
+        })
+        .collect()
+}
+
+pub fn parse_shadow(content: &str) -> Vec<ShadowEntry> {
+    content
+        .lines()
+        .filter_map(fields)
+        .filter(|f| f.len() >= 2)
+        .map(|f| ShadowEntry {
+            name: f[0].to_string(),
+            hash: f[1].to_string(),
+        })
+        .collect()
+}
+
+pub fn parse_group(content: &str) -> Vec<GroupEntry> {
+    content
+        .lines()
+        .filter_map(fields)
+        .filter(|f| f.len() >= 4)
+        .filter_map(|f| {
+            Some(GroupEntry {
+                name: f[0].to_string(),
+                password: f[1].to_string(),
+                gid: f[2].parse().ok()?,
+                members: f[3]
+                    .split(',')
+                    .filter(|m| !m.is_empty())
+                    .map(|m| m.to_string())
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::{image, remove, tree};
+
+    const PASSWD: &str = "\
+# comment
+
+root:x:0:0:root:/root:/bin/sh
+daemon:*:1:1:daemon:/usr/sbin:/usr/sbin/nologin
+bad:x:notanumber:0::/:/bin/sh
+short:x:2
+admin:x:1000:1000:Admin User,,,:/home/admin:/bin/bash\r
+";
+
+    #[test]
+    fn parse_files() {
+        let passwd = parse_passwd(PASSWD);
+        let names: Vec<&str> = passwd.iter().map(|user| user.name.as_str()).collect();
+        assert_eq!(names, vec!["root", "daemon", "admin"]);
+        let admin = &passwd[2];
+        assert_eq!((admin.uid, admin.gid), (1000, 1000));
+        assert_eq!(admin.gecos, "Admin User,,,");
+        assert_eq!(admin.home, "/home/admin");
+        assert_eq!(admin.shell, "/bin/bash");
+
+        let shadow = parse_shadow("root:$6$salt$hash:19000:0:99999:7:::\ndaemon:*:19000::::::\nbroken\n");
+        assert_eq!(shadow.len(), 2);
+        assert_eq!(shadow[0].name, "root");
+        assert_eq!(shadow[0].hash, "$6$salt$hash");
+        assert_eq!(shadow[1].hash, "*");
+
+        let groups = parse_group("root:x:0:\nwheel:x:10:root,admin\nbad:x:gid:\n");
+        assert_eq!(groups.len(), 2);
+        assert!(groups[0].members.is_empty());
+        assert_eq!(groups[1].gid, 10);
+        assert_eq!(groups[1].members, vec!["root", "admin"]);
+    }
+
+    #[test]
+    fn hash_types() {
+        assert_eq!(HashType::from_hash(""), HashType::Empty);
+        assert_eq!(HashType::from_hash("!$6$salt$hash"), HashType::Locked);
+        assert_eq!(HashType::from_hash("*"), HashType::Locked);
+        assert_eq!(HashType::from_hash("abJnggxhB/yWI"), HashType::Des);
+        assert_eq!(HashType::from_hash("$1$salt$hash"), HashType::Md5);
+        assert_eq!(HashType::from_hash("$2b$10$hash"), HashType::Blowfish);
+        assert_eq!(HashType::from_hash("$5$salt$hash"), HashType::Sha256);
+        assert_eq!(HashType::from_hash("$6$salt$hash"), HashType::Sha512);
+        assert_eq!(HashType::from_hash("$y$j9T$salt$hash"), HashType::Yescrypt);
+        assert_eq!(HashType::from_hash("$9$hash"), HashType::Unknown);
+        assert!(HashType::Des.is_weak() && HashType::Md5.is_weak());
+        assert!(!HashType::Sha512.is_weak());
+    }
+
+    #[test]
+    fn issues() {
+        let passwd = "\
+root:x:0:0:root:/root:/bin/sh
+toor:x:0:0::/root:/bin/sh
+open::1000:1000::/home/open:/bin/sh
+noshadow:x:1001:1001::/home/open:/bin/sh
+old:x:1002:1002::/home/open:/bin/sh
+www:x:33:33::/var/www:/bin/bash
+ftp:x:21:21::/srv/ftp:/sbin/nologin
+";
+        let shadow = "\
+root:$6$salt$hash:19000::::::
+toor:$6$salt$hash:19000::::::
+old:$1$salt$hash:19000::::::
+www:*:19000::::::
+ftp:*:19000::::::
+";
+        let root = image(
+            "accounts_issues",
+            &[
+                ("etc/passwd", passwd.as_bytes()),
+                ("etc/shadow", shadow.as_bytes()),
+                ("etc/group", b"root:x:0:\n"),
+                ("root/.profile", b"# profile\n"),
+                ("home/open/.profile", b"# profile\n"),
+                ("var/www/index.html", b"<html></html>\n"),
+            ],
+        );
+        let audit = AccountAudit::new(&tree(&root));
+        assert_eq!(audit.passwd.len(), 7);
+        assert_eq!(audit.groups.len(), 1);
+
+        let issues: Vec<String> = audit.issues.iter().map(|issue| issue.to_string()).collect();
+        assert_eq!(
+            issues,
+            vec![
+                "open: empty password",
+                "noshadow: password not set in shadow",
+                "old: weak Md5 hash",
+                "www: service account with interactive shell /bin/bash",
+                "ftp: home /srv/ftp not found",
+                "UID 0 accounts: root, toor",
+            ]
+        );
+        assert!(matches!(
+            &audit.issues[2],
+            AccountIssue::WeakHash { hash_type: HashType::Md5, .. }
+        ));
+
+        remove(&root);
+    }
+
+    #[test]
+    fn cracked_passwords() {
+        // openssl passwd -1 -salt saltsalt secret
+        let hash = "$1$saltsalt$9xy1btjgzLYfb7hivXtC//";
+        let root = image(
+            "accounts_cracked",
+            &[
+                ("etc/passwd", b"admin:x:1000:1000::/:/bin/sh\nlocked:x:1001:1001::/:/bin/sh\n"),
+                ("etc/shadow", format!("admin:{}:::::::\nlocked:!{}:::::::\n", hash, hash).as_bytes()),
+                ("wordlist", b"admin\nsecret\r\n"),
+            ],
+        );
+        let mut audit = AccountAudit::new(&tree(&root));
+        audit.crack_passwords(root.join("wordlist").to_str().unwrap()).unwrap();
+        assert!(matches!(
+            audit.issues.last(),
+            Some(AccountIssue::CrackedPassword { user, password }) if user == "admin" && password == "secret"
+        ));
+        assert_eq!(
+            audit
+                .issues
+                .iter()
+                .filter(|issue| matches!(issue, AccountIssue::CrackedPassword { .. }))
+                .count(),
+            1
+        );
+
+        remove(&root);
+    }
+}
@@ -1,6 +0,0 @@
-
-use crate::core::tree::TreeData;
-
-pub fn from_path(path: &str) -> TreeData {
-    TreeData::new(path)
-}
\ No newline at end of file
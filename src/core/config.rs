@@ -0,0 +1,33 @@
+/*
+    Parsers for the config file formats shared by the analyses
+*/
+
+/*
+    Values of an option in an UCI config (option <name> '<value>' or list <name> '<value>')
+*/
+pub fn uci_values(content: &str, option: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+        .filter(|words| words.len() >= 3 && (words[0] == "option" || words[0] == "list") && words[1] == option)
+        .map(|words| words[2].trim_matches(|c| c == '\'' || c == '"').to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uci_options_and_lists() {
+        let config = "config uhttpd 'main'\n\
+                      \tlist listen_http '0.0.0.0:80'\n\
+                      \tlist listen_http \"[::]:80\"\n\
+                      \toption home /www\n\
+                      \t# option home '/old'\n\
+                      \toption home_dir '/x'\n";
+        assert_eq!(uci_values(config, "listen_http"), vec!["0.0.0.0:80", "[::]:80"]);
+        assert_eq!(uci_values(config, "home"), vec!["/www"]);
+        assert!(uci_values(config, "cgi_prefix").is_empty());
+    }
+}
@@ -0,0 +1,594 @@
+use super::moves::moved;
+use super::text::{self, Op, TextDiffOptions};
+use super::{changes, compare_with, DiffEntry, DiffOptions, FsUpdate, TreeCmpResult};
+use crate::core::fstree::iter::NodeIter;
+use crate::core::fstree::node::{name, Node};
+use crate::core::fstree::FsTree;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub enum MergeStatus {
+    Unchanged,
+    // Changed in the upstream tree only, the upstream version can be taken
+    Upstream,
+    // Changed in the local tree only, the local version is kept
+    Local,
+    // Changed differently on both sides
+    Conflict,
+    // Changed on both sides with the same result
+    Identical,
+}
+
+impl MergeStatus {
+    pub const ALL: [MergeStatus; 5] = [
+        MergeStatus::Unchanged,
+        MergeStatus::Upstream,
+        MergeStatus::Local,
+        MergeStatus::Conflict,
+        MergeStatus::Identical,
+    ];
+}
+
+/*
+    Three-way merge of the lines of a text file, the conflicts are written
+    with diff3 markers
+*/
+#[derive(Debug, Clone)]
+pub struct MergePreview {
+    pub text: String,
+    pub conflicts: usize,
+}
+
+/*
+    A path of the base tree, or a path added by one of the other trees.
+    The nodes are the versions of the path in each tree.
+*/
+#[derive(Clone)]
+pub struct MergeEntry {
+    // Escaped path
+    pub fs_path: String,
+    pub status: MergeStatus,
+    pub base: Option<Node>,
+    pub upstream: Option<Node>,
+    pub local: Option<Node>,
+    // Changes from the base, None if the side did not change the path
+    pub upstream_diff: Option<DiffEntry>,
+    pub local_diff: Option<DiffEntry>,
+    // Merge of the text files changed on both sides
+    pub preview: Option<MergePreview>,
+}
+
+impl MergeEntry {
+    /*
+        A conflict on a text file which the line merge resolves
+    */
+    pub fn is_auto_mergeable(&self) -> bool {
+        match &self.preview {
+            Some(preview) => self.status == MergeStatus::Conflict && preview.conflicts == 0,
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for MergeEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.fs_path)?;
+        for (side, diff) in [("upstream", &self.upstream_diff), ("local", &self.local_diff)] {
+            if let Some(diff) = diff {
+                let changes: Vec<String> = diff.changes.iter().map(|change| change.to_string()).collect();
+                write!(f, " {} {:?}", side, diff.update)?;
+                if !changes.is_empty() {
+                    write!(f, " [{}]", changes.join(", "))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/*
+    Classification of every path of three trees, sorted by path
+*/
+#[derive(Clone, Default)]
+pub struct MergeResult {
+    pub entries: Vec<MergeEntry>,
+}
+
+impl MergeResult {
+    pub fn entries(&self, status: MergeStatus) -> impl Iterator<Item = &MergeEntry> {
+        self.entries.iter().filter(move |entry| entry.status == status)
+    }
+
+    pub fn count(&self, status: MergeStatus) -> usize {
+        self.entries(status).count()
+    }
+
+    pub fn conflicts(&self) -> impl Iterator<Item = &MergeEntry> {
+        self.entries(MergeStatus::Conflict)
+    }
+
+    pub fn display_data(&self) {
+        for status in MergeStatus::ALL.iter().filter(|status| **status != MergeStatus::Unchanged) {
+            println!("{:?}", status);
+            for entry in self.entries(*status) {
+                println!("    {}", entry);
+                if let Some(preview) = &entry.preview {
+                    println!("        {} conflicting hunks", preview.conflicts);
+                    for line in preview.text.lines() {
+                        println!("        {}", line);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn display_count(&self) {
+        for status in MergeStatus::ALL {
+            println!("{:?} {}", status, self.count(status));
+        }
+        let auto = self.conflicts().filter(|entry| entry.is_auto_mergeable()).count();
+        println!("{} conflicts can be merged line by line", auto);
+    }
+}
+
+// Path in the base tree, or new path and true for an added node
+type IndexKey = (PathBuf, bool);
+
+fn index_key(entry: &DiffEntry) -> Option<IndexKey> {
+    match entry.update {
+        FsUpdate::New => entry.new.as_ref().map(|node| (node.fs_path_os(), true)),
+        _ => entry.old.as_ref().map(|node| (node.fs_path_os(), false)),
+    }
+}
+
+/*
+    Changes of a diff indexed by path, the entries with the same key are
+    all kept. A moved directory has a Moved entry for each node under it,
+    so the paths of the base tree under the directory are mapped to their
+    new path even when the node did not change.
+*/
+fn index(result: TreeCmpResult, other: &FsTree) -> BTreeMap<IndexKey, Vec<DiffEntry>> {
+    let dir_moves: Vec<(Node, Node)> = result
+        .entries(FsUpdate::Moved)
+        .filter_map(|entry| match (&entry.old, &entry.new) {
+            (Some(old), Some(new)) if old.is_dir() && new.is_dir() => Some((old.clone(), new.clone())),
+            _ => None,
+        })
+        .collect();
+
+    let mut index: BTreeMap<IndexKey, Vec<DiffEntry>> = BTreeMap::new();
+    for entry in result.entries {
+        if let Some(key) = index_key(&entry) {
+            index.entry(key).or_default().push(entry);
+        }
+    }
+
+    for (old_dir, new_dir) in dir_moves {
+        let (old_dir_path, new_dir_path) = (old_dir.fs_path_os(), new_dir.fs_path_os());
+        for node in NodeIter::new(old_dir).skip(1) {
+            let fs_path = node.fs_path_os();
+            if index.contains_key(&(fs_path.clone(), false)) {
+                continue;
+            }
+            let new_path = match fs_path.strip_prefix(&old_dir_path) {
+                Ok(relative) => new_dir_path.join(relative),
+                Err(_) => continue,
+            };
+            if let Some(new_node) = other.get_os(&new_path) {
+                index.insert((fs_path, false), vec![moved(&node, &new_node, 100)]);
+            }
+        }
+    }
+    index
+}
+
+/*
+    Compare base with an upstream and a local version of it. A path changed
+    on one side only takes the change of this side, a path changed on both
+    sides is Identical if both lead to the same node (same path, type,
+    content and metadata) and a Conflict otherwise.
+*/
+pub fn compare_three(base: &FsTree, upstream: &FsTree, local: &FsTree, options: &DiffOptions) -> MergeResult {
+    let upstream_diffs = index(compare_with(base, upstream, options), upstream);
+    let local_diffs = index(compare_with(base, local, options), local);
+
+    let mut keys: Vec<IndexKey> = base.iter().map(|node| (node.fs_path_os(), false)).collect();
+    keys.extend(upstream_diffs.keys().cloned());
+    keys.extend(local_diffs.keys().cloned());
+    keys.sort();
+    keys.dedup();
+
+    let mut entries = Vec::with_capacity(keys.len());
+    for key in keys {
+        let upstream_list = upstream_diffs.get(&key).map(Vec::as_slice).unwrap_or_default();
+        let local_list = local_diffs.get(&key).map(Vec::as_slice).unwrap_or_default();
+        for i in 0..upstream_list.len().max(local_list.len()).max(1) {
+            let (upstream_diff, local_diff) = (upstream_list.get(i).cloned(), local_list.get(i).cloned());
+            entries.push(merge_entry(&key, upstream_diff, local_diff, base, upstream, local));
+        }
+    }
+
+    if let Some(text_options) = &options.text {
+        for entry in entries.iter_mut().filter(|entry| entry.status == MergeStatus::Conflict) {
+            entry.preview = preview(entry, text_options);
+        }
+    }
+
+    MergeResult { entries }
+}
+
+/*
+    Entry of a path from its change on each side (None if the side did not
+    change it)
+*/
+fn merge_entry(
+    key: &IndexKey,
+    upstream_diff: Option<DiffEntry>,
+    local_diff: Option<DiffEntry>,
+    base: &FsTree,
+    upstream: &FsTree,
+    local: &FsTree,
+) -> MergeEntry {
+    let status = match (&upstream_diff, &local_diff) {
+        (None, None) => MergeStatus::Unchanged,
+        (Some(_), None) => MergeStatus::Upstream,
+        (None, Some(_)) => MergeStatus::Local,
+        (Some(upstream_diff), Some(local_diff)) => {
+            if same_result(upstream_diff, local_diff) {
+                MergeStatus::Identical
+            } else {
+                MergeStatus::Conflict
+            }
+        }
+    };
+
+    // An added node has no version in the base, nor in the side which did not add it
+    let (path, added) = key;
+    let unchanged = |tree: &FsTree| if *added { None } else { tree.get_os(path) };
+    let upstream_node = match &upstream_diff {
+        Some(diff) => diff.new.clone(),
+        None => unchanged(upstream),
+    };
+    let local_node = match &local_diff {
+        Some(diff) => diff.new.clone(),
+        None => unchanged(local),
+    };
+
+    MergeEntry {
+        fs_path: name::escape_path(path),
+        status,
+        base: unchanged(base),
+        upstream: upstream_node,
+        local: local_node,
+        upstream_diff,
+        local_diff,
+        preview: None,
+    }
+}
+
+fn same_result(upstream_diff: &DiffEntry, local_diff: &DiffEntry) -> bool {
+    match (&upstream_diff.new, &local_diff.new) {
+        // Removed on both sides
+        (None, None) => true,
+        (Some(upstream), Some(local)) => {
+            upstream.fs_path_os() == local.fs_path_os()
+                && upstream.posix_file_type() == local.posix_file_type()
+                && changes(upstream, local).is_empty()
+        }
+        _ => false,
+    }
+}
+
+/*
+    Line merge of a text file changed on both sides (the base is empty if
+    the file was added on both sides)
+*/
+fn preview(entry: &MergeEntry, options: &TextDiffOptions) -> Option<MergePreview> {
+    let (upstream, local) = (entry.upstream.as_ref()?, entry.local.as_ref()?);
+    let base_text = match &entry.base {
+        Some(base) => text::read_text(base, options.max_size)?,
+        None => String::new(),
+    };
+    let upstream_text = text::read_text(upstream, options.max_size)?;
+    let local_text = text::read_text(local, options.max_size)?;
+
+    merge_text(&base_text, &upstream_text, &local_text, options)
+}
+
+/*
+    diff3 merge: the lines matched in the three versions are kept, between
+    them a chunk changed on one side takes this side, a chunk changed
+    identically on both sides is taken once and the other chunks are
+    conflicts. None if a side has more than options.max_edits changed lines.
+*/
+pub fn merge_text(
+    base_text: &str,
+    upstream_text: &str,
+    local_text: &str,
+    options: &TextDiffOptions,
+) -> Option<MergePreview> {
+    let base = text::split_lines(base_text);
+    let upstream = text::split_lines(upstream_text);
+    let local = text::split_lines(local_text);
+
+    let keys = |lines: &[&str]| -> Vec<String> { lines.iter().map(|line| options.normalize(line)).collect() };
+    let (base_keys, upstream_keys, local_keys) = (keys(&base), keys(&upstream), keys(&local));
+
+    // Line of each side matched with each base line
+    let matches = |other: &[String]| -> Option<Vec<Option<usize>>> {
+        let mut matches = vec![None; base.len()];
+        for op in text::diff_ops(&base_keys, other, options.max_edits)? {
+            if let Op::Equal(i, j) = op {
+                matches[i] = Some(j);
+            }
+        }
+        Some(matches)
+    };
+    let (upstream_matches, local_matches) = (matches(&upstream_keys)?, matches(&local_keys)?);
+
+    let mut merged: Vec<String> = Vec::new();
+    let mut conflicts = 0;
+    let (mut i, mut j, mut k) = (0, 0, 0);
+
+    loop {
+        // Next base line matched on both sides
+        let stable = (i..base.len()).find_map(|ii| match (upstream_matches[ii], local_matches[ii]) {
+            (Some(jj), Some(kk)) => Some((ii, jj, kk)),
+            _ => None,
+        });
+        let (ii, jj, kk) = stable.unwrap_or((base.len(), upstream.len(), local.len()));
+
+        if (ii, jj, kk) != (i, j, k) {
+            let base_chunk = &base_keys[i..ii];
+            let upstream_chunk = &upstream_keys[j..jj];
+            let local_chunk = &local_keys[k..kk];
+
+            if upstream_chunk == base_chunk {
+                merged.extend(local[k..kk].iter().map(|line| line.to_string()));
+            } else if local_chunk == base_chunk || upstream_chunk == local_chunk {
+                merged.extend(upstream[j..jj].iter().map(|line| line.to_string()));
+            } else {
+                conflicts += 1;
+                merged.push("<<<<<<< local".to_string());
+                merged.extend(local[k..kk].iter().map(|line| line.to_string()));
+                merged.push("||||||| base".to_string());
+                merged.extend(base[i..ii].iter().map(|line| line.to_string()));
+                merged.push("=======".to_string());
+                merged.extend(upstream[j..jj].iter().map(|line| line.to_string()));
+                merged.push(">>>>>>> upstream".to_string());
+            }
+        }
+
+        if stable.is_none() {
+            break;
+        }
+        // The stable line, local version (it may differ in the ignored parts)
+        merged.push(local[kk].to_string());
+        i = ii + 1;
+        j = jj + 1;
+        k = kk + 1;
+    }
+
+    let mut text = merged.join("\n");
+    if !merged.is_empty() {
+        text.push('\n');
+    }
+    Some(MergePreview { text, conflicts })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::{image, remove, tree};
+
+    fn merge(base: &str, upstream: &str, local: &str) -> Option<MergePreview> {
+        merge_text(base, upstream, local, &TextDiffOptions::default())
+    }
+
+    #[test]
+    fn line_merge() {
+        // Changes on different lines, or the same change on both sides
+        let preview = merge("1\n2\n3\n4\n5\n", "U\n2\n3\n4\n5\n", "1\n2\n3\n4\nL\n").unwrap();
+        assert_eq!((preview.text.as_str(), preview.conflicts), ("U\n2\n3\n4\nL\n", 0));
+        let preview = merge("1\n2\n3\n", "1\nx\n3\n", "1\nx\n3\n").unwrap();
+        assert_eq!((preview.text.as_str(), preview.conflicts), ("1\nx\n3\n", 0));
+
+        // Lines added at the end by one side and removed by the other
+        let preview = merge("1\n2\n3\n", "1\n2\n3\n4\n", "2\n3\n").unwrap();
+        assert_eq!((preview.text.as_str(), preview.conflicts), ("2\n3\n4\n", 0));
+    }
+
+    #[test]
+    fn conflict_markers() {
+        let preview = merge("a\nb\nc\n", "a\nB\nc\n", "a\nX\nc\n").unwrap();
+        assert_eq!(preview.conflicts, 1);
+        assert_eq!(
+            preview.text,
+            "a\n<<<<<<< local\nX\n||||||| base\nb\n=======\nB\n>>>>>>> upstream\nc\n"
+        );
+
+        // Added on both sides: the base is empty
+        let preview = merge("", "u\n", "l\n").unwrap();
+        assert_eq!(preview.conflicts, 1);
+        assert_eq!(preview.text, "<<<<<<< local\nl\n||||||| base\n=======\nu\n>>>>>>> upstream\n");
+
+        let options = TextDiffOptions {
+            max_edits: 1,
+            ..TextDiffOptions::default()
+        };
+        assert!(merge_text("1\n2\n3\n", "a\nb\nc\n", "1\n2\n3\n", &options).is_none());
+    }
+
+    #[test]
+    fn three_trees() {
+        let conf = b"a\nb\nc\nd\ne\n";
+        let base = image(
+            "merge_base",
+            &[
+                ("same.txt", b"s\n"),
+                ("up.txt", b"u\n"),
+                ("loc.txt", b"l\n"),
+                ("both.txt", b"b\n"),
+                ("conf.txt", conf),
+                ("clash.txt", b"x\n"),
+                ("gone.txt", b"g\n"),
+            ],
+        );
+        let upstream = image(
+            "merge_upstream",
+            &[
+                ("same.txt", b"s\n"),
+                ("up.txt", b"U\n"),
+                ("loc.txt", b"l\n"),
+                ("both.txt", b"B\n"),
+                ("conf.txt", b"A\nb\nc\nd\ne\n"),
+                ("clash.txt", b"y\n"),
+                ("added.txt", b"new\n"),
+            ],
+        );
+        let local = image(
+            "merge_local",
+            &[
+                ("same.txt", b"s\n"),
+                ("up.txt", b"u\n"),
+                ("loc.txt", b"L\n"),
+                ("both.txt", b"B\n"),
+                ("conf.txt", b"a\nb\nc\nd\nE\n"),
+                ("clash.txt", b"z\n"),
+            ],
+        );
+        let result = compare_three(&tree(&base), &tree(&upstream), &tree(&local), &DiffOptions::default());
+
+        let statuses: Vec<(&str, MergeStatus)> =
+            result.entries.iter().map(|entry| (entry.fs_path.as_str(), entry.status)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("/", MergeStatus::Unchanged),
+                ("/added.txt", MergeStatus::Upstream),
+                ("/both.txt", MergeStatus::Identical),
+                ("/clash.txt", MergeStatus::Conflict),
+                ("/conf.txt", MergeStatus::Conflict),
+                ("/gone.txt", MergeStatus::Identical),
+                ("/loc.txt", MergeStatus::Local),
+                ("/same.txt", MergeStatus::Unchanged),
+                ("/up.txt", MergeStatus::Upstream),
+            ]
+        );
+
+        let conflicts: Vec<&MergeEntry> = result.conflicts().collect();
+        assert!(!conflicts[0].is_auto_mergeable());
+        assert_eq!(conflicts[0].preview.as_ref().unwrap().conflicts, 1);
+        assert!(conflicts[1].is_auto_mergeable());
+        assert_eq!(conflicts[1].preview.as_ref().unwrap().text, "A\nb\nc\nd\nE\n");
+
+        // The upstream version of an added path, no base version
+        let added = &result.entries[1];
+        assert!(added.base.is_none() && added.upstream.is_some() && added.local.is_none());
+
+        remove(&base);
+        remove(&upstream);
+        remove(&local);
+    }
+    #[test]
+    fn directory_moved_upstream() {
+        let base = image(
+            "merge_move_base",
+            &[
+                ("opt/app/bin", b"binary\n"),
+                ("opt/app/app.conf", b"a\nb\nc\n"),
+                ("opt/app/lib/libx.so", b"library\n"),
+                ("opt/legacy", b"legacy\n"),
+            ],
+        );
+        let upstream = image(
+            "merge_move_upstream",
+            &[
+                ("usr/app/bin", b"binary\n"),
+                ("usr/app/app.conf", b"a\nb\nc\n"),
+                ("usr/app/lib/libx.so", b"library\n"),
+                ("opt/legacy", b"legacy\n"),
+            ],
+        );
+        let local = image(
+            "merge_move_local",
+            &[
+                ("opt/app/bin", b"binary\n"),
+                ("opt/app/app.conf", b"a\nb\nC\n"),
+                ("opt/app/lib/libx.so", b"library\n"),
+                ("opt/legacy", b"legacy\n"),
+            ],
+        );
+        let result = compare_three(&tree(&base), &tree(&upstream), &tree(&local), &DiffOptions::default());
+
+        let statuses: Vec<(&str, MergeStatus)> =
+            result.entries.iter().map(|entry| (entry.fs_path.as_str(), entry.status)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("/", MergeStatus::Unchanged),
+                ("/opt", MergeStatus::Unchanged),
+                ("/opt/app", MergeStatus::Upstream),
+                ("/opt/app/app.conf", MergeStatus::Conflict),
+                ("/opt/app/bin", MergeStatus::Upstream),
+                ("/opt/app/lib", MergeStatus::Upstream),
+                ("/opt/app/lib/libx.so", MergeStatus::Upstream),
+                ("/opt/legacy", MergeStatus::Unchanged),
+                ("/usr", MergeStatus::Upstream),
+            ]
+        );
+
+        // The unchanged files of the moved directory are mapped to their new path
+        let libx = &result.entries[6];
+        assert_eq!(libx.upstream.as_ref().unwrap().fs_path(), "/usr/app/lib/libx.so");
+        assert_eq!(libx.upstream_diff.as_ref().unwrap().update, FsUpdate::Moved);
+        assert_eq!(libx.local.as_ref().unwrap().fs_path(), "/opt/app/lib/libx.so");
+
+        // Moved upstream and edited locally: the line merge takes the local edit
+        let conf = &result.entries[3];
+        assert_eq!(conf.upstream.as_ref().unwrap().fs_path(), "/usr/app/app.conf");
+        assert!(conf.is_auto_mergeable());
+        assert_eq!(conf.preview.as_ref().unwrap().text, "a\nb\nC\n");
+
+        remove(&base);
+        remove(&upstream);
+        remove(&local);
+    }
+
+    #[test]
+    fn entries_with_the_same_key() {
+        let root = image("merge_keys", &[("etc/motd", b"hello\n"), ("etc/issue", b"welcome\n")]);
+        let fstree = tree(&root);
+        let (motd, issue) = (fstree.get("/etc/motd").unwrap(), fstree.get("/etc/issue").unwrap());
+
+        // The path of a node moved away, taken by an added node
+        let mut result = TreeCmpResult::default();
+        result.push(moved(&motd, &issue, 100));
+        result.push(DiffEntry {
+            update: FsUpdate::New,
+            old: None,
+            new: Some(motd.clone()),
+            changes: Vec::new(),
+            text_diff: None,
+        });
+        result.push(DiffEntry {
+            update: FsUpdate::Removed,
+            old: Some(motd.clone()),
+            new: None,
+            changes: Vec::new(),
+            text_diff: None,
+        });
+
+        let index = index(result, &fstree);
+        let updates = |added: bool| -> Vec<FsUpdate> {
+            index[&(PathBuf::from("/etc/motd"), added)].iter().map(|entry| entry.update).collect()
+        };
+        assert_eq!(updates(false), vec![FsUpdate::Moved, FsUpdate::Removed]);
+        assert_eq!(updates(true), vec![FsUpdate::New]);
+
+        remove(&root);
+    }
+}
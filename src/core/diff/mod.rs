@@ -0,0 +1,786 @@
+pub mod merge;
+mod moves;
+pub mod series;
+pub mod text;
+
+use crate::core::fstree::iter::NodeIter;
+use crate::core::fstree::node::metadata::PosixFileType;
+use crate::core::fstree::node::Node;
+use crate::core::fstree::FsTree;
+
+use text::{TextDiff, TextDiffOptions};
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub enum FsUpdate {
+    New,
+    Removed,
+    Moved,
+    // The content of the file changed (its metadata may have changed too)
+    Modified,
+    // Same content, only the metadata or the symlink target changed
+    MetadataChanged,
+    // The path exists in both trees with another type (ex: file to symlink)
+    TypeChanged,
+}
+
+impl FsUpdate {
+    pub const ALL: [FsUpdate; 6] = [
+        FsUpdate::New,
+        FsUpdate::Removed,
+        FsUpdate::Moved,
+        FsUpdate::Modified,
+        FsUpdate::MetadataChanged,
+        FsUpdate::TypeChanged,
+    ];
+}
+
+/*
+    Category of a Change, used to filter the results
+*/
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub enum ChangeKind {
+    Content,
+    Type,
+    Target,
+    Mode,
+    Owner,
+    Group,
+    Capabilities,
+    SeLinux,
+    Device,
+    Moved,
+}
+
+impl ChangeKind {
+    // Changes which keep the content
+    pub const METADATA: [ChangeKind; 7] = [
+        ChangeKind::Target,
+        ChangeKind::Mode,
+        ChangeKind::Owner,
+        ChangeKind::Group,
+        ChangeKind::Capabilities,
+        ChangeKind::SeLinux,
+        ChangeKind::Device,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChangeKind::Content => "content",
+            ChangeKind::Type => "type",
+            ChangeKind::Target => "target",
+            ChangeKind::Mode => "mode",
+            ChangeKind::Owner => "owner",
+            ChangeKind::Group => "group",
+            ChangeKind::Capabilities => "capabilities",
+            ChangeKind::SeLinux => "selinux",
+            ChangeKind::Device => "device",
+            ChangeKind::Moved => "moved",
+        }
+    }
+
+    pub fn is_metadata(&self) -> bool {
+        ChangeKind::METADATA.contains(self)
+    }
+}
+
+/*
+    What changed on a node present in both trees
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    // Content of a file (digests, or bytes if the files were not hashed)
+    Content,
+    // Type of the node (file, dir, symlink...)
+    Type { old: PosixFileType, new: PosixFileType },
+    // Symlink target (escaped)
+    Target { old: String, new: String },
+    // Permission bits, SUID/SGID/sticky included
+    Mode { old: u32, new: u32 },
+    Owner { old: u32, new: u32 },
+    Group { old: u32, new: u32 },
+    Capabilities,
+    SeLinux { old: Option<String>, new: Option<String> },
+    // Major/minor of a device
+    Device { old: (u32, u32), new: (u32, u32) },
+    // Escaped paths, similarity is 100 for an identical content
+    Moved { from: String, to: String, similarity: u32 },
+}
+
+impl Change {
+    pub fn kind(&self) -> ChangeKind {
+        match self {
+            Change::Content => ChangeKind::Content,
+            Change::Type { .. } => ChangeKind::Type,
+            Change::Target { .. } => ChangeKind::Target,
+            Change::Mode { .. } => ChangeKind::Mode,
+            Change::Owner { .. } => ChangeKind::Owner,
+            Change::Group { .. } => ChangeKind::Group,
+            Change::Capabilities => ChangeKind::Capabilities,
+            Change::SeLinux { .. } => ChangeKind::SeLinux,
+            Change::Device { .. } => ChangeKind::Device,
+            Change::Moved { .. } => ChangeKind::Moved,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Content => write!(f, "content"),
+            Change::Type { old, new } => write!(f, "type {} -> {}", old.name(), new.name()),
+            Change::Target { old, new } => write!(f, "target {} -> {}", old, new),
+            Change::Mode { old, new } => {
+                write!(f, "mode {:04o} -> {:04o}", old, new)?;
+                // Special bits gained
+                let gained = new & !old;
+                for (bit, name) in [(0o4000, "suid"), (0o2000, "sgid"), (0o0002, "world-writable")] {
+                    if gained & bit != 0 {
+                        write!(f, " +{}", name)?;
+                    }
+                }
+                Ok(())
+            }
+            Change::Owner { old, new } => write!(f, "owner {} -> {}", old, new),
+            Change::Group { old, new } => write!(f, "group {} -> {}", old, new),
+            Change::Capabilities => write!(f, "capabilities"),
+            Change::SeLinux { old, new } => write!(
+                f,
+                "selinux {} -> {}",
+                old.as_deref().unwrap_or("none"),
+                new.as_deref().unwrap_or("none")
+            ),
+            Change::Device { old, new } => write!(f, "device {}:{} -> {}:{}", old.0, old.1, new.0, new.1),
+            Change::Moved { from, to, similarity } => write!(f, "moved {} -> {} ({}%)", from, to, similarity),
+        }
+    }
+}
+
+/*
+    One difference between the trees. old is the node in the first tree,
+    new the node in the second one (only one of them for New and Removed).
+    Every node of a new or removed subtree has its own entry.
+*/
+#[derive(Clone)]
+pub struct DiffEntry {
+    pub update: FsUpdate,
+    pub old: Option<Node>,
+    pub new: Option<Node>,
+    pub changes: Vec<Change>,
+    // Line diff of a text file whose content changed
+    pub text_diff: Option<TextDiff>,
+}
+
+impl DiffEntry {
+    /*
+        Escaped path in the fs, the new one if the node exists in the second tree
+    */
+    pub fn fs_path(&self) -> String {
+        match (&self.new, &self.old) {
+            (Some(node), _) | (None, Some(node)) => node.fs_path(),
+            (None, None) => String::new(),
+        }
+    }
+
+    pub fn has_change(&self, kind: ChangeKind) -> bool {
+        self.changes.iter().any(|change| change.kind() == kind)
+    }
+
+    fn fs_path_os(&self) -> PathBuf {
+        match (&self.new, &self.old) {
+            (Some(node), _) | (None, Some(node)) => node.fs_path_os(),
+            (None, None) => PathBuf::new(),
+        }
+    }
+}
+
+impl fmt::Display for DiffEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.fs_path())?;
+        if !self.changes.is_empty() {
+            let changes: Vec<String> = self.changes.iter().map(|change| change.to_string()).collect();
+            write!(f, " [{}]", changes.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/*
+    Options of a comparison
+*/
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    // Report a removed and a new node with the same content as a move
+    pub detect_moves: bool,
+    // Also match moved files and directories whose similarity (0 to 100) is
+    // at least this value, None to only match identical contents
+    pub similarity_threshold: Option<u32>,
+    // Line diffs of the modified text files, None to disable them
+    pub text: Option<TextDiffOptions>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            detect_moves: true,
+            similarity_threshold: None,
+            text: Some(TextDiffOptions::default()),
+        }
+    }
+}
+
+/*
+    Differences between two trees, sorted by path
+*/
+#[derive(Clone, Default)]
+pub struct TreeCmpResult {
+    pub entries: Vec<DiffEntry>,
+}
+
+impl TreeCmpResult {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, entry: DiffEntry) {
+        self.entries.push(entry);
+    }
+
+    /*
+        Source and destination of the moves, with their similarity
+    */
+    pub fn moves(&self) -> Vec<(String, String, u32)> {
+        self.entries(FsUpdate::Moved)
+            .flat_map(|entry| entry.changes.iter())
+            .filter_map(|change| match change {
+                Change::Moved { from, to, similarity } => Some((from.clone(), to.clone(), *similarity)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn entries(&self, update: FsUpdate) -> impl Iterator<Item = &DiffEntry> {
+        self.entries.iter().filter(move |entry| entry.update == update)
+    }
+
+    pub fn count(&self, update: FsUpdate) -> usize {
+        self.entries(update).count()
+    }
+
+    /*
+        Entries with a change of this kind, whatever their FsUpdate (ex: the
+        mode changes of the modified and of the moved files too)
+    */
+    pub fn with_change(&self, kind: ChangeKind) -> impl Iterator<Item = &DiffEntry> {
+        self.entries.iter().filter(move |entry| entry.has_change(kind))
+    }
+
+    pub fn count_change(&self, kind: ChangeKind) -> usize {
+        self.with_change(kind).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /*
+        Keep only the entries of the given kinds
+    */
+    pub fn filter(&self, updates: &[FsUpdate]) -> TreeCmpResult {
+        self.filter_by(|entry| updates.contains(&entry.update))
+    }
+
+    /*
+        Keep only the entries with at least one change of the given kinds
+    */
+    pub fn filter_changes(&self, kinds: &[ChangeKind]) -> TreeCmpResult {
+        self.filter_by(|entry| kinds.iter().any(|kind| entry.has_change(*kind)))
+    }
+
+    pub fn filter_by<F: Fn(&DiffEntry) -> bool>(&self, predicate: F) -> TreeCmpResult {
+        TreeCmpResult {
+            entries: self.entries.iter().filter(|entry| predicate(entry)).cloned().collect(),
+        }
+    }
+
+    fn sort(&mut self) {
+        self.entries.sort_by_key(|entry| (entry.fs_path_os(), entry.update));
+    }
+
+    pub fn display_data(&self) {
+        for update in FsUpdate::ALL {
+            println!("{:?}", update);
+            for entry in self.entries(update) {
+                println!("    {}", entry);
+                if let Some(text_diff) = entry.text_diff.as_ref().filter(|text_diff| !text_diff.is_empty()) {
+                    let old_path = entry.old.as_ref().map(|node| node.fs_path()).unwrap_or_default();
+                    for line in text_diff.unified(&old_path, &entry.fs_path()).lines() {
+                        println!("        {}", line);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn display_count(&self) {
+        for update in FsUpdate::ALL {
+            println!("{:?} {}", update, self.count(update));
+        }
+        for kind in ChangeKind::METADATA {
+            let count = self.count_change(kind);
+            if count > 0 {
+                println!("    {} {}", kind.name(), count);
+            }
+        }
+    }
+}
+
+pub fn compare(old: &FsTree, new: &FsTree) -> TreeCmpResult {
+    compare_with(old, new, &DiffOptions::default())
+}
+
+/*
+    Compare two trees. Nodes are matched by their raw path, a file is
+    Modified if its content changed, a node is MetadataChanged if only its
+    metadata or its symlink target changed.
+    A path which changes of type is TypeChanged, the content of a directory
+    on one side only is reported as New or Removed. Removed and new nodes
+    are then matched as moves (see DiffOptions).
+*/
+pub fn compare_with(old: &FsTree, new: &FsTree, options: &DiffOptions) -> TreeCmpResult {
+    let mut result = TreeCmpResult::new();
+
+    let root_changes = changes(&old.head_node, &new.head_node);
+    if !root_changes.is_empty() {
+        result.push(modified(&old.head_node, &new.head_node, root_changes));
+    }
+    compare_dirs(&mut result, &old.head_node, &new.head_node);
+
+    if options.detect_moves {
+        moves::detect(&mut result, options.similarity_threshold);
+    }
+    if let Some(text_options) = &options.text {
+        old.install(|| text::add_text_diffs(&mut result, text_options));
+    }
+
+    result.sort();
+    result
+}
+
+/*
+    Compare the subtrees of two directories with the same path
+*/
+fn compare_dirs(result: &mut TreeCmpResult, old: &Node, new: &Node) {
+    let mut stack = vec![(old.clone(), new.clone())];
+    while let Some((old_dir, new_dir)) = stack.pop() {
+        let mut pairs: BTreeMap<OsString, (Option<Node>, Option<Node>)> = BTreeMap::new();
+        for child in old_dir.childrens() {
+            let key = child.name_os();
+            pairs.entry(key).or_default().0 = Some(child);
+        }
+        for child in new_dir.childrens() {
+            let key = child.name_os();
+            pairs.entry(key).or_default().1 = Some(child);
+        }
+
+        for (_, pair) in pairs {
+            match pair {
+                (Some(old_node), None) => push_removed(result, &old_node),
+                (None, Some(new_node)) => push_new(result, &new_node),
+                (Some(old_node), Some(new_node)) => {
+                    let (old_type, new_type) = (old_node.posix_file_type(), new_node.posix_file_type());
+                    if old_type != new_type {
+                        result.push(DiffEntry {
+                            update: FsUpdate::TypeChanged,
+                            old: Some(old_node.clone()),
+                            new: Some(new_node.clone()),
+                            changes: vec![Change::Type {
+                                old: old_type,
+                                new: new_type,
+                            }],
+                            text_diff: None,
+                        });
+                        // The content of a directory replaced by a file is removed (and the opposite)
+                        for child in old_node.childrens() {
+                            push_removed(result, &child);
+                        }
+                        for child in new_node.childrens() {
+                            push_new(result, &child);
+                        }
+                        continue;
+                    }
+
+                    let node_changes = changes(&old_node, &new_node);
+                    if !node_changes.is_empty() {
+                        result.push(modified(&old_node, &new_node, node_changes));
+                    }
+                    if old_node.is_dir() {
+                        stack.push((old_node, new_node));
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+/*
+    Changes between two nodes of the same type
+*/
+pub fn changes(old: &Node, new: &Node) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    if old.is_file() && !same_content(old, new) {
+        changes.push(Change::Content);
+    }
+
+    if let (Some(old_link), Some(new_link)) = (old.symlink_data(), new.symlink_data()) {
+        if old_link.target != new_link.target {
+            changes.push(Change::Target {
+                old: old_link.target,
+                new: new_link.target,
+            });
+        }
+    }
+
+    let (old_metadata, new_metadata) = (old.metadata(), new.metadata());
+    let device_type = matches!(old_metadata.file_type, PosixFileType::CharDevice | PosixFileType::BlockDevice);
+    if device_type && old_metadata.rdev != new_metadata.rdev {
+        changes.push(Change::Device {
+            old: (old_metadata.major(), old_metadata.minor()),
+            new: (new_metadata.major(), new_metadata.minor()),
+        });
+    }
+
+    // The mode of a symlink is not used
+    if !old.is_symlink() && old_metadata.permissions() != new_metadata.permissions() {
+        changes.push(Change::Mode {
+            old: old_metadata.permissions(),
+            new: new_metadata.permissions(),
+        });
+    }
+    if old_metadata.uid != new_metadata.uid {
+        changes.push(Change::Owner {
+            old: old_metadata.uid,
+            new: new_metadata.uid,
+        });
+    }
+    if old_metadata.gid != new_metadata.gid {
+        changes.push(Change::Group {
+            old: old_metadata.gid,
+            new: new_metadata.gid,
+        });
+    }
+
+    if old.capabilities() != new.capabilities() {
+        changes.push(Change::Capabilities);
+    }
+    let (old_label, new_label) = (old.selinux_label(), new.selinux_label());
+    if old_label != new_label {
+        changes.push(Change::SeLinux {
+            old: old_label,
+            new: new_label,
+        });
+    }
+
+    changes
+}
+
+/*
+    Compare the digests when both files were hashed, the bytes otherwise
+*/
+pub fn same_content(old: &Node, new: &Node) -> bool {
+    if old.len() != new.len() {
+        return false;
+    }
+    if let Some(same) = old.digests().same_content(&new.digests()) {
+        return same;
+    }
+    match (fs::read(old.local_path()), fs::read(new.local_path())) {
+        (Ok(old_bytes), Ok(new_bytes)) => old_bytes == new_bytes,
+        _ => false,
+    }
+}
+
+/*
+    A new node and its whole subtree
+*/
+fn push_new(result: &mut TreeCmpResult, node: &Node) {
+    for node in NodeIter::new(node.clone()) {
+        result.push(DiffEntry {
+            update: FsUpdate::New,
+            old: None,
+            new: Some(node),
+            changes: Vec::new(),
+            text_diff: None,
+        });
+    }
+}
+
+fn push_removed(result: &mut TreeCmpResult, node: &Node) {
+    for node in NodeIter::new(node.clone()) {
+        result.push(DiffEntry {
+            update: FsUpdate::Removed,
+            old: Some(node),
+            new: None,
+            changes: Vec::new(),
+            text_diff: None,
+        });
+    }
+}
+
+fn modified(old: &Node, new: &Node, changes: Vec<Change>) -> DiffEntry {
+    let content = changes.iter().any(|change| change.kind() == ChangeKind::Content);
+    DiffEntry {
+        update: if content { FsUpdate::Modified } else { FsUpdate::MetadataChanged },
+        old: Some(old.clone()),
+        new: Some(new.clone()),
+        changes,
+        text_diff: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::{image, remove, tree};
+
+    use std::os::unix::fs::PermissionsExt;
+
+    fn paths(result: &TreeCmpResult, update: FsUpdate) -> Vec<String> {
+        result.entries(update).map(|entry| entry.fs_path()).collect()
+    }
+
+    #[test]
+    fn new_removed_and_modified() {
+        let old_root = image(
+            "diff_old",
+            &[("etc/passwd", b"root:x:0:0::/root:/bin/sh\n"), ("etc/motd", b"hello\n"), ("bin/tool", b"v1\n")],
+        );
+        let new_root = image(
+            "diff_new",
+            &[("etc/passwd", b"root:x:0:0::/root:/bin/sh\n"), ("etc/motd", b"welcome\n"), ("usr/bin/tool", b"v2\n")],
+        );
+        let result = tree(&old_root).compare(&tree(&new_root));
+
+        // Every node of a new or removed subtree has an entry
+        assert_eq!(paths(&result, FsUpdate::New), vec!["/usr", "/usr/bin", "/usr/bin/tool"]);
+        assert_eq!(paths(&result, FsUpdate::Removed), vec!["/bin", "/bin/tool"]);
+        assert_eq!(paths(&result, FsUpdate::Modified), vec!["/etc/motd"]);
+        assert_eq!(result.count(FsUpdate::Moved), 0);
+
+        let motd = result.entries(FsUpdate::Modified).next().unwrap();
+        assert_eq!(motd.changes, vec![Change::Content]);
+        assert_eq!(motd.old.as_ref().unwrap().fs_path(), "/etc/motd");
+        assert!(result.entries(FsUpdate::New).all(|entry| entry.old.is_none() && entry.new.is_some()));
+        assert!(result.entries(FsUpdate::Removed).all(|entry| entry.old.is_some() && entry.new.is_none()));
+
+        // Sorted by path
+        let all: Vec<String> = result.entries.iter().map(|entry| entry.fs_path()).collect();
+        let mut sorted = all.clone();
+        sorted.sort();
+        assert_eq!(all, sorted);
+
+        remove(&old_root);
+        remove(&new_root);
+    }
+
+    #[test]
+    fn root_entry() {
+        let old_root = image("diff_root_old", &[("etc/motd", b"hello\n")]);
+        let new_root = image("diff_root_new", &[("etc/motd", b"hello\n")]);
+        fs::set_permissions(&old_root, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(&new_root, fs::Permissions::from_mode(0o700)).unwrap();
+
+        let result = tree(&old_root).compare(&tree(&new_root));
+        assert_eq!(result.entries.len(), 1);
+        let entry = &result.entries[0];
+        assert_eq!(entry.update, FsUpdate::MetadataChanged);
+        assert_eq!(entry.fs_path(), "/");
+        assert_eq!(entry.changes, vec![Change::Mode { old: 0o755, new: 0o700 }]);
+        assert_eq!(entry.to_string(), "/ [mode 0755 -> 0700]");
+
+        // Identical trees
+        fs::set_permissions(&new_root, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(tree(&old_root).compare(&tree(&new_root)).is_empty());
+
+        remove(&old_root);
+        remove(&new_root);
+    }
+
+    #[test]
+    fn recursive_subtrees() {
+        let old_root = image(
+            "diff_subtrees_old",
+            &[
+                ("etc/motd", b"hello\n"),
+                ("opt/app/bin", b"binary"),
+                ("opt/app/lib/libx.so", b"library"),
+                ("opt/legacy", b"legacy\n"),
+            ],
+        );
+        let new_root = image(
+            "diff_subtrees_new",
+            &[
+                ("etc/motd", b"hello\n"),
+                ("srv/www/index.html", b"<html></html>\n"),
+                ("srv/www/cgi/run.sh", b"#!/bin/sh\n"),
+                ("usr/app/bin", b"binary"),
+                ("usr/app/lib/libx.so", b"library"),
+            ],
+        );
+        let (old_tree, new_tree) = (tree(&old_root), tree(&new_root));
+
+        let options = DiffOptions {
+            detect_moves: false,
+            ..DiffOptions::default()
+        };
+        let result = old_tree.compare_with(&new_tree, &options);
+        assert_eq!(
+            paths(&result, FsUpdate::New),
+            vec![
+                "/srv",
+                "/srv/www",
+                "/srv/www/cgi",
+                "/srv/www/cgi/run.sh",
+                "/srv/www/index.html",
+                "/usr",
+                "/usr/app",
+                "/usr/app/bin",
+                "/usr/app/lib",
+                "/usr/app/lib/libx.so",
+            ]
+        );
+        assert_eq!(
+            paths(&result, FsUpdate::Removed),
+            vec!["/opt", "/opt/app", "/opt/app/bin", "/opt/app/lib", "/opt/app/lib/libx.so", "/opt/legacy"]
+        );
+
+        // A directory moved under a new directory is found in the subtree
+        let result = old_tree.compare(&new_tree);
+        assert_eq!(result.moves(), vec![("/opt/app".to_string(), "/usr/app".to_string(), 100)]);
+        assert_eq!(
+            paths(&result, FsUpdate::New),
+            vec!["/srv", "/srv/www", "/srv/www/cgi", "/srv/www/cgi/run.sh", "/srv/www/index.html", "/usr"]
+        );
+        assert_eq!(paths(&result, FsUpdate::Removed), vec!["/opt", "/opt/legacy"]);
+
+        remove(&old_root);
+        remove(&new_root);
+    }
+
+    #[test]
+    fn type_changes() {
+        // file -> dir, dir -> symlink, symlink -> file
+        let old_root = image("diff_types_old", &[("a", b"file\n"), ("b/inner", b"old inner\n")]);
+        std::os::unix::fs::symlink("a", old_root.join("c")).unwrap();
+        let new_root = image("diff_types_new", &[("a/inner", b"new inner\n"), ("c", b"file\n")]);
+        std::os::unix::fs::symlink("a", new_root.join("b")).unwrap();
+
+        let result = tree(&old_root).compare(&tree(&new_root));
+        let type_changes: Vec<(String, Vec<Change>)> = result
+            .entries(FsUpdate::TypeChanged)
+            .map(|entry| (entry.fs_path(), entry.changes.clone()))
+            .collect();
+        assert_eq!(
+            type_changes,
+            vec![
+                ("/a".to_string(), vec![Change::Type { old: PosixFileType::Regular, new: PosixFileType::Dir }]),
+                ("/b".to_string(), vec![Change::Type { old: PosixFileType::Dir, new: PosixFileType::SymLink }]),
+                ("/c".to_string(), vec![Change::Type { old: PosixFileType::SymLink, new: PosixFileType::Regular }]),
+            ]
+        );
+        assert!(result.entries(FsUpdate::TypeChanged).all(|entry| entry.old.is_some() && entry.new.is_some()));
+        assert_eq!(result.entries(FsUpdate::TypeChanged).next().unwrap().to_string(), "/a [type file -> dir]");
+
+        // The content of the directories is on one side only
+        assert_eq!(paths(&result, FsUpdate::New), vec!["/a/inner"]);
+        assert_eq!(paths(&result, FsUpdate::Removed), vec!["/b/inner"]);
+        assert_eq!(result.count(FsUpdate::Modified), 0);
+
+        remove(&old_root);
+        remove(&new_root);
+    }
+
+    #[test]
+    fn metadata_changes() {
+        let files: &[(&str, &[u8])] = &[
+            ("bin/tool", b"tool\n"),
+            ("etc/owner", b"owner\n"),
+            ("etc/group", b"group\n"),
+            ("etc/label", b"label\n"),
+            ("etc/motd", b"hello\n"),
+        ];
+        let old_root = image("diff_metadata_old", files);
+        let new_root = image("diff_metadata_new", &[files, &[("etc/motd", b"welcome\n")]].concat());
+        std::os::unix::fs::symlink("a", old_root.join("link")).unwrap();
+        std::os::unix::fs::symlink("b", new_root.join("link")).unwrap();
+
+        for root in [&old_root, &new_root] {
+            for (path, _) in files {
+                fs::set_permissions(root.join(path), fs::Permissions::from_mode(0o755)).unwrap();
+            }
+        }
+        fs::set_permissions(new_root.join("bin/tool"), fs::Permissions::from_mode(0o4755)).unwrap();
+        fs::set_permissions(new_root.join("etc/motd"), fs::Permissions::from_mode(0o644)).unwrap();
+        // Owners and labels can only be set with privileges
+        let has_owner = std::os::unix::fs::chown(new_root.join("etc/owner"), Some(1000), None).is_ok();
+        let has_group = std::os::unix::fs::lchown(new_root.join("etc/group"), None, Some(1000)).is_ok();
+        let label = "system_u:object_r:bin_t:s0";
+        let has_label =
+            ::xattr::set(new_root.join("etc/label"), "security.selinux", format!("{}\0", label).as_bytes()).is_ok();
+
+        let (old_tree, new_tree) = (tree(&old_root), tree(&new_root));
+        let result = old_tree.compare(&new_tree);
+        let changes = |path: &str| {
+            let entry = result.entries.iter().find(|entry| entry.fs_path() == path);
+            entry.map(|entry| entry.changes.clone())
+        };
+
+        let tool = result.entries.iter().find(|entry| entry.fs_path() == "/bin/tool").unwrap();
+        assert_eq!(tool.update, FsUpdate::MetadataChanged);
+        assert_eq!(tool.changes, vec![Change::Mode { old: 0o755, new: 0o4755 }]);
+        assert_eq!(tool.to_string(), "/bin/tool [mode 0755 -> 4755 +suid]");
+
+        assert_eq!(changes("/link"), Some(vec![Change::Target { old: "a".to_string(), new: "b".to_string() }]));
+
+        let uid = old_tree.get("/etc/owner").unwrap().metadata().uid;
+        if has_owner {
+            assert_eq!(changes("/etc/owner"), Some(vec![Change::Owner { old: uid, new: 1000 }]));
+        }
+        let gid = old_tree.get("/etc/group").unwrap().metadata().gid;
+        if has_group {
+            assert_eq!(changes("/etc/group"), Some(vec![Change::Group { old: gid, new: 1000 }]));
+        }
+        if has_label {
+            assert_eq!(
+                changes("/etc/label"),
+                Some(vec![Change::SeLinux { old: None, new: Some(label.to_string()) }])
+            );
+        }
+
+        // Content and mode changes together are a modification
+        let motd = result.entries.iter().find(|entry| entry.fs_path() == "/etc/motd").unwrap();
+        assert_eq!(motd.update, FsUpdate::Modified);
+        assert_eq!(motd.changes, vec![Change::Content, Change::Mode { old: 0o755, new: 0o644 }]);
+
+        let paths_with = |kinds: &[ChangeKind]| -> Vec<String> {
+            result.filter_changes(kinds).entries.iter().map(|entry| entry.fs_path()).collect()
+        };
+        assert_eq!(paths_with(&[ChangeKind::Mode]), vec!["/bin/tool", "/etc/motd"]);
+        assert_eq!(paths_with(&[ChangeKind::Content]), vec!["/etc/motd"]);
+        assert_eq!(paths_with(&[ChangeKind::Target, ChangeKind::Content]), vec!["/etc/motd", "/link"]);
+        assert_eq!(result.count_change(ChangeKind::Mode), 2);
+        let metadata_only = result.count(FsUpdate::MetadataChanged);
+        assert_eq!(metadata_only, 2 + has_owner as usize + has_group as usize + has_label as usize);
+        assert_eq!(result.filter_changes(&ChangeKind::METADATA).entries.len(), metadata_only + 1);
+
+        remove(&old_root);
+        remove(&new_root);
+    }
+}
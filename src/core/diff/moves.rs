@@ -0,0 +1,362 @@
+use super::{changes, compare_dirs, Change, DiffEntry, FsUpdate, TreeCmpResult};
+use crate::core::fstree::iter::NodeIter;
+use crate::core::fstree::node::Node;
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+// (relative path, digest) of the files of a directory
+type DirSignature = Vec<(PathBuf, String)>;
+
+/*
+    Replace the Removed and New entries which are the same node at two paths
+    by Moved entries. Directories are matched first, so the files of a moved
+    directory are compared with their old version instead of being matched
+    one by one. Files are then matched by digest and, if a threshold is
+    given, by the similarity of their fuzzy digests.
+*/
+pub(super) fn detect(result: &mut TreeCmpResult, threshold: Option<u32>) {
+    let dir_moves = match_dirs(result, threshold);
+    if !dir_moves.is_empty() {
+        let old_dirs: Vec<PathBuf> = dir_moves.iter().map(|(old, _, _)| old.fs_path_os()).collect();
+        let new_dirs: Vec<PathBuf> = dir_moves.iter().map(|(_, new, _)| new.fs_path_os()).collect();
+
+        result.entries.retain(|entry| match entry.update {
+            FsUpdate::Removed => !is_under(entry.old.as_ref(), &old_dirs),
+            FsUpdate::New => !is_under(entry.new.as_ref(), &new_dirs),
+            _ => true,
+        });
+
+        for (old, new, similarity) in dir_moves {
+            result.push(moved(&old, &new, similarity));
+            compare_dirs(result, &old, &new);
+        }
+    }
+
+    let file_moves = match_files(result, threshold);
+    if !file_moves.is_empty() {
+        let old_files: HashSet<PathBuf> = file_moves.iter().map(|(old, _, _)| old.fs_path_os()).collect();
+        let new_files: HashSet<PathBuf> = file_moves.iter().map(|(_, new, _)| new.fs_path_os()).collect();
+
+        result.entries.retain(|entry| match (entry.update, &entry.old, &entry.new) {
+            (FsUpdate::Removed, Some(node), _) => !old_files.contains(&node.fs_path_os()),
+            (FsUpdate::New, _, Some(node)) => !new_files.contains(&node.fs_path_os()),
+            _ => true,
+        });
+
+        for (old, new, similarity) in file_moves {
+            result.push(moved(&old, &new, similarity));
+        }
+    }
+}
+
+pub(super) fn moved(old: &Node, new: &Node, similarity: u32) -> DiffEntry {
+    let mut node_changes = vec![Change::Moved {
+        from: old.fs_path(),
+        to: new.fs_path(),
+        similarity,
+    }];
+    node_changes.extend(changes(old, new));
+
+    DiffEntry {
+        update: FsUpdate::Moved,
+        old: Some(old.clone()),
+        new: Some(new.clone()),
+        changes: node_changes,
+        text_diff: None,
+    }
+}
+
+fn is_under(node: Option<&Node>, dirs: &[PathBuf]) -> bool {
+    match node {
+        Some(node) => {
+            let fs_path = node.fs_path_os();
+            dirs.iter().any(|dir| fs_path.starts_with(dir))
+        }
+        None => false,
+    }
+}
+
+fn removed_and_new(result: &TreeCmpResult, filter: fn(&Node) -> bool) -> (Vec<Node>, Vec<Node>) {
+    let removed = result.entries(FsUpdate::Removed).filter_map(|entry| entry.old.clone()).filter(filter).collect();
+    let new = result.entries(FsUpdate::New).filter_map(|entry| entry.new.clone()).filter(filter).collect();
+    (removed, new)
+}
+
+/*
+    Directories with the same files (relative path and digest), or with at
+    least threshold percent of their files in common
+*/
+fn match_dirs(result: &TreeCmpResult, threshold: Option<u32>) -> Vec<(Node, Node, u32)> {
+    let (removed, new) = removed_and_new(result, |node| node.is_dir());
+
+    // Directories without hashed files can't be compared
+    let removed: Vec<(Node, DirSignature)> = removed
+        .into_iter()
+        .map(|dir| {
+            let signature = signature(&dir);
+            (dir, signature)
+        })
+        .filter(|(_, signature)| !signature.is_empty())
+        .collect();
+    let new: Vec<(Node, DirSignature)> = new
+        .into_iter()
+        .map(|dir| {
+            let signature = signature(&dir);
+            (dir, signature)
+        })
+        .filter(|(_, signature)| !signature.is_empty())
+        .collect();
+
+    let mut candidates: Vec<(u32, usize, usize)> = Vec::new();
+    for (i, (old_dir, old_signature)) in removed.iter().enumerate() {
+        for (j, (new_dir, new_signature)) in new.iter().enumerate() {
+            let similarity = match threshold {
+                _ if old_signature == new_signature => 100,
+                Some(threshold) => {
+                    let similarity = signature_similarity(old_signature, new_signature);
+                    if similarity >= threshold {
+                        similarity
+                    } else {
+                        0
+                    }
+                }
+                None => 0,
+            };
+            if similarity > 0 {
+                // Prefer a directory with the same name, then the first one in path order
+                let bonus = (old_dir.name_os() == new_dir.name_os()) as u32;
+                candidates.push((similarity * 2 + bonus, i, j));
+            }
+        }
+    }
+
+    // Parents first: a directory inside a moved directory is compared with it
+    let mut pairs = assign(candidates);
+    pairs.sort_by_key(|(_, i, _)| removed[*i].0.fs_path_os());
+
+    let mut moves: Vec<(Node, Node, u32)> = Vec::new();
+    for (score, i, j) in pairs {
+        let (old_dir, new_dir) = (&removed[i].0, &new[j].0);
+        let nested = moves.iter().any(|(old, new, _)| {
+            old_dir.fs_path_os().starts_with(old.fs_path_os()) || new_dir.fs_path_os().starts_with(new.fs_path_os())
+        });
+        if !nested {
+            moves.push((old_dir.clone(), new_dir.clone(), score / 2));
+        }
+    }
+    moves
+}
+
+fn signature(dir: &Node) -> DirSignature {
+    let dir_path = dir.fs_path_os();
+
+    let mut signature: DirSignature = NodeIter::new(dir.clone())
+        .filter(|node| node.is_file())
+        .filter_map(|node| {
+            let digest = node.hash()?;
+            let relative = node.fs_path_os().strip_prefix(&dir_path).ok()?.to_path_buf();
+            Some((relative, digest))
+        })
+        .collect();
+    signature.sort();
+    signature
+}
+
+/*
+    Percentage of the files found in both directories
+*/
+fn signature_similarity(signature1: &DirSignature, signature2: &DirSignature) -> u32 {
+    let files1: HashSet<&(PathBuf, String)> = signature1.iter().collect();
+    let common = signature2.iter().filter(|file| files1.contains(file)).count();
+    (100 * common / signature1.len().max(signature2.len())) as u32
+}
+
+/*
+    Files with the same digest, then files whose fuzzy digests are similar
+    enough. Empty files all have the same digest and are never matched.
+*/
+fn match_files(result: &TreeCmpResult, threshold: Option<u32>) -> Vec<(Node, Node, u32)> {
+    let (removed, new) = removed_and_new(result, |node| node.is_file() && !node.is_empty());
+
+    let mut by_digest: HashMap<String, Vec<usize>> = HashMap::new();
+    for (j, file) in new.iter().enumerate() {
+        if let Some(digest) = file.hash() {
+            by_digest.entry(digest).or_default().push(j);
+        }
+    }
+
+    let mut candidates: Vec<(u32, usize, usize)> = Vec::new();
+    for (i, old_file) in removed.iter().enumerate() {
+        let exact = match old_file.hash().and_then(|digest| by_digest.get(&digest)) {
+            Some(exact) => exact,
+            None => continue,
+        };
+        for &j in exact {
+            let bonus = (old_file.name_os() == new[j].name_os()) as u32;
+            candidates.push((200 + bonus, i, j));
+        }
+    }
+    let mut pairs = assign(candidates);
+
+    if let Some(threshold) = threshold {
+        let matched_old: HashSet<usize> = pairs.iter().map(|(_, i, _)| *i).collect();
+        let matched_new: HashSet<usize> = pairs.iter().map(|(_, _, j)| *j).collect();
+
+        let mut candidates: Vec<(u32, usize, usize)> = Vec::new();
+        for (i, old_file) in removed.iter().enumerate().filter(|(i, _)| !matched_old.contains(i)) {
+            let old_digests = old_file.digests();
+            for (j, new_file) in new.iter().enumerate().filter(|(j, _)| !matched_new.contains(j)) {
+                // Sizes too different to reach a useful similarity
+                let (min, max) = (old_file.len().min(new_file.len()), old_file.len().max(new_file.len()));
+                if min * 2 < max {
+                    continue;
+                }
+                if let Some(similarity) = old_digests.similarity(&new_file.digests()) {
+                    if similarity >= threshold && similarity > 0 {
+                        let bonus = (old_file.name_os() == new_file.name_os()) as u32;
+                        candidates.push((similarity * 2 + bonus, i, j));
+                    }
+                }
+            }
+        }
+        pairs.extend(assign(candidates));
+    }
+
+    pairs
+        .into_iter()
+        .map(|(score, i, j)| (removed[i].clone(), new[j].clone(), score / 2))
+        .collect()
+}
+
+/*
+    Greedy assignment of (score, old, new) candidates, best scores first
+    and in path order for equal scores, each node is used once
+*/
+fn assign(mut candidates: Vec<(u32, usize, usize)>) -> Vec<(u32, usize, usize)> {
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then((a.1, a.2).cmp(&(b.1, b.2))));
+
+    let mut used_old = HashSet::new();
+    let mut used_new = HashSet::new();
+    let mut pairs = Vec::new();
+
+    for (score, i, j) in candidates {
+        if used_old.contains(&i) || used_new.contains(&j) {
+            continue;
+        }
+        used_old.insert(i);
+        used_new.insert(j);
+        pairs.push((score, i, j));
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{compare_with, Change, DiffOptions};
+    use super::*;
+    use crate::core::testutil::{image, remove, tree};
+
+    fn options(threshold: Option<u32>) -> DiffOptions {
+        DiffOptions {
+            detect_moves: true,
+            similarity_threshold: threshold,
+            text: None,
+        }
+    }
+
+    // Deterministic content, big enough for meaningful fuzzy digests
+    fn content(len: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345) & 0x7fffffff;
+                (x >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn paths(result: &TreeCmpResult, update: FsUpdate) -> Vec<String> {
+        result.entries(update).map(|entry| entry.fs_path()).collect()
+    }
+
+    #[test]
+    fn renamed_files_and_dirs() {
+        let old = image(
+            "moves_old",
+            &[
+                ("etc/a.conf", b"option a 1\n"),
+                ("etc/empty1", b""),
+                ("opt/app/bin", b"binary"),
+                ("opt/app/lib/libx.so", b"library"),
+                ("opt/other", b"other"),
+            ],
+        );
+        let new = image(
+            "moves_new",
+            &[
+                ("etc/b.conf", b"option a 1\n"),
+                ("etc/empty2", b""),
+                ("opt/app-2.0/bin", b"binary"),
+                ("opt/app-2.0/lib/libx.so", b"library"),
+                ("opt/other", b"other"),
+            ],
+        );
+        let (old_tree, new_tree) = (tree(&old), tree(&new));
+
+        let result = compare_with(&old_tree, &new_tree, &options(None));
+        assert_eq!(
+            result.moves(),
+            vec![
+                ("/etc/a.conf".to_string(), "/etc/b.conf".to_string(), 100),
+                ("/opt/app".to_string(), "/opt/app-2.0".to_string(), 100),
+            ]
+        );
+        // The files of the moved directory are not reported one by one, empty files are never matched
+        assert_eq!(paths(&result, FsUpdate::Removed), vec!["/etc/empty1"]);
+        assert_eq!(paths(&result, FsUpdate::New), vec!["/etc/empty2"]);
+
+        let result = compare_with(&old_tree, &new_tree, &DiffOptions { detect_moves: false, ..options(None) });
+        assert!(result.moves().is_empty());
+        assert_eq!(result.count(FsUpdate::Removed), 6);
+        assert_eq!(result.count(FsUpdate::New), 6);
+
+        remove(&old);
+        remove(&new);
+    }
+
+    #[test]
+    fn similar_files() {
+        let bytes = content(65536, 7);
+        let mut changed = bytes.clone();
+        changed[30000..30100].fill(0);
+        let other = content(65536, 8);
+
+        let old = image("moves_similar_old", &[("lib/libold.so", &bytes), ("lib/unrelated", &other)]);
+        let new = image("moves_similar_new", &[("lib/libnew.so", &changed), ("lib/unrelated2", &content(65536, 9))]);
+        let (old_tree, new_tree) = (tree(&old), tree(&new));
+
+        // Only identical contents without a threshold
+        assert!(compare_with(&old_tree, &new_tree, &options(None)).moves().is_empty());
+
+        let result = compare_with(&old_tree, &new_tree, &options(Some(50)));
+        let moves = result.moves();
+        assert_eq!(moves.len(), 1);
+        assert_eq!((moves[0].0.as_str(), moves[0].1.as_str()), ("/lib/libold.so", "/lib/libnew.so"));
+        assert!(moves[0].2 >= 50 && moves[0].2 < 100);
+
+        // The moved file is also compared with its old version
+        let entry = result.entries(FsUpdate::Moved).next().unwrap();
+        assert!(entry.changes.contains(&Change::Content));
+
+        remove(&old);
+        remove(&new);
+    }
+
+    #[test]
+    fn greedy_assignment() {
+        // Best score first, then path order, each node used once
+        let pairs = assign(vec![(200, 0, 0), (201, 1, 0), (200, 0, 1), (200, 1, 1)]);
+        assert_eq!(pairs, vec![(201, 1, 0), (200, 0, 1)]);
+    }
+}
@@ -0,0 +1,497 @@
+use super::{compare_with, DiffOptions, FsUpdate, TreeCmpResult};
+use crate::core::fstree::node::{name, Node};
+use crate::core::fstree::FsTree;
+use crate::core::query::Query;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/*
+    What happened to a node between two consecutive trees of a series
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Added,
+    Removed,
+    Modified,
+    MetadataChanged,
+    TypeChanged,
+    // Escaped path in the previous tree
+    Moved { from: String },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Added => write!(f, "added"),
+            Event::Removed => write!(f, "removed"),
+            Event::Modified => write!(f, "modified"),
+            Event::MetadataChanged => write!(f, "metadata changed"),
+            Event::TypeChanged => write!(f, "type changed"),
+            Event::Moved { from } => write!(f, "moved from {}", from),
+        }
+    }
+}
+
+/*
+    A node in one tree of the series
+*/
+#[derive(Clone)]
+pub struct NodeState {
+    pub node: Node,
+    // Escaped path
+    pub fs_path: String,
+    // Main digest of a file
+    pub hash: Option<String>,
+}
+
+impl NodeState {
+    fn new(node: &Node) -> Self {
+        Self {
+            node: node.clone(),
+            fs_path: node.fs_path(),
+            hash: node.hash(),
+        }
+    }
+}
+
+/*
+    History of a node across the series, moves included
+*/
+#[derive(Clone)]
+pub struct Timeline {
+    // One state per tree, None where the node does not exist
+    pub states: Vec<Option<NodeState>>,
+    // (index of the tree, event), the event happened between the trees index - 1 and index
+    pub events: Vec<(usize, Event)>,
+}
+
+impl Timeline {
+    fn new(nb_trees: usize) -> Self {
+        Self {
+            states: vec![None; nb_trees],
+            events: Vec::new(),
+        }
+    }
+
+    pub fn first_seen(&self) -> Option<usize> {
+        self.states.iter().position(|state| state.is_some())
+    }
+
+    pub fn last_seen(&self) -> Option<usize> {
+        self.states.iter().rposition(|state| state.is_some())
+    }
+
+    /*
+        Escaped path in the last tree where the node exists
+    */
+    pub fn fs_path(&self) -> String {
+        match self.last_seen() {
+            Some(i) => self.states[i].as_ref().map(|state| state.fs_path.clone()).unwrap_or_default(),
+            None => String::new(),
+        }
+    }
+
+    pub fn event_at(&self, index: usize) -> Option<&Event> {
+        self.events.iter().find(|(i, _)| *i == index).map(|(_, event)| event)
+    }
+
+    /*
+        True if the node is not the same in all the trees
+    */
+    pub fn has_changed(&self) -> bool {
+        !self.events.is_empty()
+    }
+
+    /*
+        Node of an event, in the tree before it for a removal
+    */
+    fn event_node(&self, index: usize, event: &Event) -> Option<&Node> {
+        let state = match event {
+            Event::Removed => self.states[index - 1].as_ref(),
+            _ => self.states[index].as_ref(),
+        };
+        state.map(|state| &state.node)
+    }
+}
+
+/*
+    Comparison of an ordered series of trees (ex: successive firmware versions)
+*/
+pub struct SeriesCmpResult {
+    // Name of each tree (ex: "v1.0")
+    pub labels: Vec<String>,
+    // Differences between each tree and the next one
+    pub steps: Vec<TreeCmpResult>,
+    // Sorted by the path where the node is first seen
+    pub timelines: Vec<Timeline>,
+}
+
+impl SeriesCmpResult {
+    pub fn changed(&self) -> impl Iterator<Item = &Timeline> {
+        self.timelines.iter().filter(|timeline| timeline.has_changed())
+    }
+
+    /*
+        Number of events per directory, directories are cut after depth
+        components (0 for the full path). Sorted by decreasing churn.
+    */
+    pub fn churn_by_dir(&self, depth: usize) -> Vec<(String, usize)> {
+        let mut churn: HashMap<PathBuf, usize> = HashMap::new();
+        for timeline in &self.timelines {
+            for (index, event) in &timeline.events {
+                if let Some(node) = timeline.event_node(*index, event) {
+                    let fs_path = node.fs_path_os();
+                    let dir = fs_path.parent().unwrap_or(Path::new("/"));
+                    *churn.entry(truncate(dir, depth)).or_insert(0) += 1;
+                }
+            }
+        }
+        sorted(churn.into_iter().map(|(dir, count)| (name::escape_path(&dir), count)).collect())
+    }
+
+    /*
+        Number of events per component. A component is a named query (ex:
+        ("web", "path~:^/www/")), a node counts for the first component it
+        matches and for "other" if it matches none.
+    */
+    pub fn churn_by_component(&self, components: &[(String, Query)]) -> Vec<(String, usize)> {
+        let mut churn: HashMap<String, usize> = HashMap::new();
+        for timeline in &self.timelines {
+            for (index, event) in &timeline.events {
+                if let Some(node) = timeline.event_node(*index, event) {
+                    let component = components
+                        .iter()
+                        .find(|(_, query)| query.matches(node))
+                        .map(|(name, _)| name.as_str())
+                        .unwrap_or("other");
+                    *churn.entry(component.to_string()).or_insert(0) += 1;
+                }
+            }
+        }
+        sorted(churn.into_iter().collect())
+    }
+
+    pub fn display_timelines(&self) {
+        for timeline in self.changed() {
+            println!("{}", timeline.fs_path());
+            for (i, label) in self.labels.iter().enumerate() {
+                let event = timeline.event_at(i).map(|event| event.to_string()).unwrap_or_default();
+                match &timeline.states[i] {
+                    Some(state) => println!(
+                        "    {} {} {} {}",
+                        label,
+                        state.hash.as_deref().unwrap_or("-"),
+                        state.fs_path,
+                        event
+                    ),
+                    None => println!("    {} absent {}", label, event),
+                }
+            }
+        }
+    }
+
+    pub fn display_churn(&self, depth: usize) {
+        for (i, step) in self.steps.iter().enumerate() {
+            let counts: Vec<String> = FsUpdate::ALL
+                .iter()
+                .map(|update| format!("{:?} {}", update, step.count(*update)))
+                .collect();
+            println!("{} -> {}: {}", self.labels[i], self.labels[i + 1], counts.join(", "));
+        }
+        println!("Churn per directory");
+        for (dir, count) in self.churn_by_dir(depth) {
+            println!("    {} {}", count, dir);
+        }
+    }
+}
+
+fn truncate(dir: &Path, depth: usize) -> PathBuf {
+    if depth == 0 {
+        return dir.to_path_buf();
+    }
+    let mut truncated = PathBuf::from("/");
+    truncated.extend(dir.components().skip(1).take(depth));
+    truncated
+}
+
+fn sorted(mut churn: Vec<(String, usize)>) -> Vec<(String, usize)> {
+    churn.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    churn
+}
+
+/*
+    Compare each tree of the series with the next one and follow every node
+    across the series. A node keeps its timeline when it is modified, moved
+    or changes of type.
+*/
+pub fn compare_series(trees: &[(&str, &FsTree)], options: &DiffOptions) -> SeriesCmpResult {
+    let nb_trees = trees.len();
+    let mut timelines: Vec<Timeline> = Vec::new();
+    let mut steps = Vec::new();
+
+    // Timeline of each node of the current tree, by raw path
+    let mut current: HashMap<PathBuf, usize> = HashMap::new();
+    if let Some((_, first)) = trees.first() {
+        for node in first.iter() {
+            let mut timeline = Timeline::new(nb_trees);
+            timeline.states[0] = Some(NodeState::new(&node));
+            current.insert(node.fs_path_os(), timelines.len());
+            timelines.push(timeline);
+        }
+    }
+
+    for i in 1..nb_trees {
+        let (previous, tree) = (trees[i - 1].1, trees[i].1);
+        let step = compare_with(previous, tree, options);
+
+        let mut next: HashMap<PathBuf, usize> = HashMap::new();
+        let mut dir_moves: Vec<(PathBuf, PathBuf)> = Vec::new();
+
+        for entry in &step.entries {
+            let event = match entry.update {
+                FsUpdate::New => Event::Added,
+                FsUpdate::Removed => Event::Removed,
+                FsUpdate::Modified => Event::Modified,
+                FsUpdate::MetadataChanged => Event::MetadataChanged,
+                FsUpdate::TypeChanged => Event::TypeChanged,
+                FsUpdate::Moved => Event::Moved {
+                    from: entry.old.as_ref().map(|node| node.fs_path()).unwrap_or_default(),
+                },
+            };
+
+            let known = match (&entry.old, entry.update) {
+                (Some(old), update) if update != FsUpdate::New => current.get(&old.fs_path_os()).copied(),
+                _ => None,
+            };
+            let index = known.unwrap_or_else(|| {
+                timelines.push(Timeline::new(nb_trees));
+                timelines.len() - 1
+            });
+            timelines[index].events.push((i, event));
+
+            if let Some(new) = &entry.new {
+                next.insert(new.fs_path_os(), index);
+                if entry.update == FsUpdate::Moved && new.is_dir() {
+                    if let Some(old) = &entry.old {
+                        dir_moves.push((old.fs_path_os(), new.fs_path_os()));
+                    }
+                }
+            }
+        }
+
+        for node in tree.iter() {
+            let fs_path = node.fs_path_os();
+            let index = match next.get(&fs_path) {
+                Some(index) => Some(*index),
+                // Unchanged node, at the same path or inside a moved directory
+                None => current.get(&old_path(&fs_path, &dir_moves)).copied(),
+            };
+            let index = index.unwrap_or_else(|| {
+                timelines.push(Timeline::new(nb_trees));
+                timelines.len() - 1
+            });
+            timelines[index].states[i] = Some(NodeState::new(&node));
+            next.insert(fs_path, index);
+        }
+
+        current = next;
+        steps.push(step);
+    }
+
+    timelines.sort_by_key(|timeline| {
+        let first = timeline.first_seen().and_then(|i| timeline.states[i].as_ref());
+        (first.map(|state| state.node.fs_path_os()), timeline.first_seen())
+    });
+
+    SeriesCmpResult {
+        labels: trees.iter().map(|(label, _)| label.to_string()).collect(),
+        steps,
+        timelines,
+    }
+}
+
+/*
+    Path in the previous tree of a node whose directory was moved (the
+    deepest moved directory containing it)
+*/
+fn old_path(fs_path: &Path, dir_moves: &[(PathBuf, PathBuf)]) -> PathBuf {
+    let dir_move = dir_moves
+        .iter()
+        .filter(|(_, new_dir)| fs_path.starts_with(new_dir))
+        .max_by_key(|(_, new_dir)| new_dir.components().count());
+
+    match dir_move {
+        Some((old_dir, new_dir)) => match fs_path.strip_prefix(new_dir) {
+            Ok(relative) if relative.as_os_str().is_empty() => old_dir.clone(),
+            Ok(relative) => old_dir.join(relative),
+            Err(_) => fs_path.to_path_buf(),
+        },
+        None => fs_path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::{image, remove, tree};
+
+    fn timeline<'a>(result: &'a SeriesCmpResult, fs_path: &str) -> &'a Timeline {
+        result.timelines.iter().find(|timeline| timeline.fs_path() == fs_path).unwrap()
+    }
+
+    fn state_paths(timeline: &Timeline) -> Vec<Option<String>> {
+        timeline.states.iter().map(|state| state.as_ref().map(|state| state.fs_path.clone())).collect()
+    }
+
+    /*
+        v1 -> v2: /etc/app.conf modified, /opt/app moved to /usr/app, a new
+        /usr/share subtree, /www/index.html renamed, /bin/old replaced by
+        /bin/new. v2 -> v3: /www/home.html modified.
+    */
+    fn series(name: &str) -> (Vec<PathBuf>, SeriesCmpResult) {
+        let v1 = image(
+            &format!("{}_v1", name),
+            &[
+                ("etc/app.conf", b"conf 1\n"),
+                ("opt/app/bin", b"binary\n"),
+                ("opt/app/lib/libx.so", b"library\n"),
+                ("opt/legacy", b"legacy\n"),
+                ("www/index.html", b"<html>1</html>\n"),
+                ("bin/old", b"old\n"),
+            ],
+        );
+        let v2 = image(
+            &format!("{}_v2", name),
+            &[
+                ("etc/app.conf", b"conf 2\n"),
+                ("usr/app/bin", b"binary\n"),
+                ("usr/app/lib/libx.so", b"library\n"),
+                ("usr/share/doc/app/README", b"readme\n"),
+                ("opt/legacy", b"legacy\n"),
+                ("www/home.html", b"<html>1</html>\n"),
+                ("bin/new", b"new\n"),
+            ],
+        );
+        let v3 = image(
+            &format!("{}_v3", name),
+            &[
+                ("etc/app.conf", b"conf 2\n"),
+                ("usr/app/bin", b"binary\n"),
+                ("usr/app/lib/libx.so", b"library\n"),
+                ("usr/share/doc/app/README", b"readme\n"),
+                ("opt/legacy", b"legacy\n"),
+                ("www/home.html", b"<html>3</html>\n"),
+                ("bin/new", b"new\n"),
+            ],
+        );
+        let (t1, t2, t3) = (tree(&v1), tree(&v2), tree(&v3));
+        let result = compare_series(&[("v1", &t1), ("v2", &t2), ("v3", &t3)], &DiffOptions::default());
+        (vec![v1, v2, v3], result)
+    }
+
+    #[test]
+    fn timelines_follow_moves() {
+        let (roots, result) = series("series_moves");
+        assert_eq!(result.labels, vec!["v1", "v2", "v3"]);
+        assert_eq!(result.steps.len(), 2);
+
+        // File move then modification
+        let home = timeline(&result, "/www/home.html");
+        assert_eq!(
+            home.events,
+            vec![(1, Event::Moved { from: "/www/index.html".to_string() }), (2, Event::Modified)]
+        );
+        assert_eq!(
+            state_paths(home),
+            vec![Some("/www/index.html".to_string()), Some("/www/home.html".to_string()), Some("/www/home.html".to_string())]
+        );
+
+        // Directory move, the unchanged files inside it keep their timeline
+        let app = timeline(&result, "/usr/app");
+        assert_eq!(app.events, vec![(1, Event::Moved { from: "/opt/app".to_string() })]);
+        let lib = timeline(&result, "/usr/app/lib/libx.so");
+        assert!(!lib.has_changed());
+        assert_eq!(lib.first_seen(), Some(0));
+        assert_eq!(lib.states[0].as_ref().unwrap().fs_path, "/opt/app/lib/libx.so");
+        assert_eq!(lib.states[2].as_ref().unwrap().fs_path, "/usr/app/lib/libx.so");
+        assert_eq!(
+            result.timelines.iter().filter(|timeline| timeline.fs_path().ends_with("/libx.so")).count(),
+            1
+        );
+
+        let conf = timeline(&result, "/etc/app.conf");
+        assert_eq!(conf.events, vec![(1, Event::Modified)]);
+        assert_eq!(conf.event_at(1), Some(&Event::Modified));
+        assert_eq!(conf.event_at(2), None);
+        assert!(!timeline(&result, "/opt/legacy").has_changed());
+
+        roots.iter().for_each(|root| remove(root));
+    }
+
+    #[test]
+    fn added_and_removed() {
+        let (roots, result) = series("series_added");
+
+        let old = timeline(&result, "/bin/old");
+        assert_eq!(old.events, vec![(1, Event::Removed)]);
+        assert_eq!((old.first_seen(), old.last_seen()), (Some(0), Some(0)));
+        assert_eq!(state_paths(old), vec![Some("/bin/old".to_string()), None, None]);
+
+        let new = timeline(&result, "/bin/new");
+        assert_eq!(new.events, vec![(1, Event::Added)]);
+        assert_eq!((new.first_seen(), new.last_seen()), (Some(1), Some(2)));
+
+        // Every node of a new subtree is added
+        let readme = timeline(&result, "/usr/share/doc/app/README");
+        assert_eq!(readme.events, vec![(1, Event::Added)]);
+        assert_eq!(timeline(&result, "/usr/share").events, vec![(1, Event::Added)]);
+
+        roots.iter().for_each(|root| remove(root));
+    }
+
+    #[test]
+    fn churn() {
+        let (roots, result) = series("series_churn");
+        let churn = |list: &[(&str, usize)]| -> Vec<(String, usize)> {
+            list.iter().map(|(name, count)| (name.to_string(), *count)).collect()
+        };
+
+        assert_eq!(
+            result.churn_by_dir(0),
+            churn(&[
+                ("/bin", 2),
+                ("/usr", 2),
+                ("/www", 2),
+                ("/", 1),
+                ("/etc", 1),
+                ("/usr/share", 1),
+                ("/usr/share/doc", 1),
+                ("/usr/share/doc/app", 1),
+            ])
+        );
+        // Directories cut after the first component
+        assert_eq!(
+            result.churn_by_dir(1),
+            churn(&[("/usr", 5), ("/bin", 2), ("/www", 2), ("/", 1), ("/etc", 1)])
+        );
+
+        // A node counts for its first matching component, "/usr" itself for none
+        let components = vec![
+            ("web".to_string(), Query::parse("path~:^/www/").unwrap()),
+            ("apps".to_string(), Query::parse("path~:^/usr/").unwrap()),
+            ("all".to_string(), Query::parse("path~:^/").unwrap()),
+        ];
+        assert_eq!(
+            result.churn_by_component(&components[..2]),
+            churn(&[("apps", 5), ("other", 4), ("web", 2)])
+        );
+        assert_eq!(
+            result.churn_by_component(&components),
+            churn(&[("apps", 5), ("all", 4), ("web", 2)])
+        );
+
+        roots.iter().for_each(|root| remove(root));
+    }
+}
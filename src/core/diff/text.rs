@@ -0,0 +1,548 @@
+use super::{ChangeKind, DiffEntry, FsUpdate, TreeCmpResult};
+use crate::core::fstree::node::Node;
+use crate::core::secrets::looks_like_text;
+
+use regex::Regex;
+
+use std::fmt;
+use std::fs;
+
+use rayon::prelude::*;
+
+// Text files bigger than this are not diffed
+pub const DEFAULT_MAX_TEXT_SIZE: u64 = 1000000;
+
+// Files with more changed lines than this are only reported as different
+pub const DEFAULT_MAX_EDITS: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Whitespace {
+    // Lines must be identical
+    Exact,
+    // Trailing whitespace is ignored
+    IgnoreTrailing,
+    // Runs of whitespace are equal, leading and trailing whitespace is ignored (diff -b)
+    IgnoreChanges,
+    // All whitespace is ignored (diff -w)
+    IgnoreAll,
+}
+
+/*
+    Options of the line diffs. The normalization is only used to compare
+    the lines, the diff shows the lines of the files as they are.
+*/
+#[derive(Debug, Clone)]
+pub struct TextDiffOptions {
+    // Number of unchanged lines around the changes
+    pub context: usize,
+    pub whitespace: Whitespace,
+    // "\r\n" and "\n" are equal
+    pub ignore_line_endings: bool,
+    // Matches are removed before comparing (ex: timestamps, build ids)
+    pub ignore_patterns: Vec<Regex>,
+    pub max_size: u64,
+    // The memory of the diff grows with the square of the number of changed lines
+    pub max_edits: usize,
+}
+
+impl Default for TextDiffOptions {
+    fn default() -> Self {
+        Self {
+            context: 3,
+            whitespace: Whitespace::Exact,
+            ignore_line_endings: false,
+            ignore_patterns: Vec::new(),
+            max_size: DEFAULT_MAX_TEXT_SIZE,
+            max_edits: DEFAULT_MAX_EDITS,
+        }
+    }
+}
+
+impl TextDiffOptions {
+    pub(super) fn normalize(&self, line: &str) -> String {
+        let line = if self.ignore_line_endings {
+            line.strip_suffix('\r').unwrap_or(line)
+        } else {
+            line
+        };
+
+        let mut line = line.to_string();
+        for pattern in &self.ignore_patterns {
+            line = pattern.replace_all(&line, "").into_owned();
+        }
+
+        match self.whitespace {
+            Whitespace::Exact => line,
+            Whitespace::IgnoreTrailing => line.trim_end().to_string(),
+            Whitespace::IgnoreChanges => line.split_whitespace().collect::<Vec<&str>>().join(" "),
+            Whitespace::IgnoreAll => line.chars().filter(|c| !c.is_whitespace()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Context(String),
+    Removed(String),
+    Added(String),
+}
+
+impl fmt::Display for DiffLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiffLine::Context(line) => write!(f, " {}", line),
+            DiffLine::Removed(line) => write!(f, "-{}", line),
+            DiffLine::Added(line) => write!(f, "+{}", line),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    // First line (1-based) and number of lines in the old and the new file
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<DiffLine>,
+    // The hunk ends the old (new) file and this file has no final newline
+    pub old_no_newline: bool,
+    pub new_no_newline: bool,
+}
+
+impl fmt::Display for Hunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "@@ -{},{} +{},{} @@", self.old_start, self.old_len, self.new_start, self.new_len)?;
+        let (mut old_count, mut new_count) = (0, 0);
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+            let (old_end, new_end) = match line {
+                DiffLine::Context(_) => {
+                    old_count += 1;
+                    new_count += 1;
+                    (old_count == self.old_len, new_count == self.new_len)
+                }
+                DiffLine::Removed(_) => {
+                    old_count += 1;
+                    (old_count == self.old_len, false)
+                }
+                DiffLine::Added(_) => {
+                    new_count += 1;
+                    (false, new_count == self.new_len)
+                }
+            };
+            if (old_end && self.old_no_newline) || (new_end && self.new_no_newline) {
+                writeln!(f, "\\ No newline at end of file")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/*
+    Line diff of two versions of a text file. No hunks means the files are
+    equal once normalized.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct TextDiff {
+    pub hunks: Vec<Hunk>,
+}
+
+impl TextDiff {
+    pub fn is_empty(&self) -> bool {
+        self.hunks.is_empty()
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &DiffLine> {
+        self.hunks.iter().flat_map(|hunk| hunk.lines.iter())
+    }
+
+    /*
+        Unified diff with its ---/+++ header
+    */
+    pub fn unified(&self, old_path: &str, new_path: &str) -> String {
+        let mut unified = format!("--- a{}\n+++ b{}\n", old_path, new_path);
+        for hunk in &self.hunks {
+            unified.push_str(&hunk.to_string());
+        }
+        unified
+    }
+}
+
+/*
+    Diff the text files whose content changed (modified and moved files)
+*/
+pub(super) fn add_text_diffs(result: &mut TreeCmpResult, options: &TextDiffOptions) {
+    result
+        .entries
+        .par_iter_mut()
+        .filter(|entry| matches!(entry.update, FsUpdate::Modified | FsUpdate::Moved))
+        .filter(|entry| entry.has_change(ChangeKind::Content))
+        .for_each(|entry| entry.text_diff = node_text_diff(entry, options));
+}
+
+fn node_text_diff(entry: &DiffEntry, options: &TextDiffOptions) -> Option<TextDiff> {
+    let (old, new) = (entry.old.as_ref()?, entry.new.as_ref()?);
+    let old_text = read_text(old, options.max_size)?;
+    let new_text = read_text(new, options.max_size)?;
+    diff_text(&old_text, &new_text, options)
+}
+
+/*
+    Content of a text file, None for binary files. Files without a text
+    type (ex: /etc/passwd) are sniffed.
+*/
+pub(super) fn read_text(node: &Node, max_size: u64) -> Option<String> {
+    if !node.is_file() || node.is_elf() || node.len() > max_size {
+        return None;
+    }
+    let bytes = fs::read(node.local_path()).ok()?;
+    if !node.is_text() && !looks_like_text(&bytes) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+pub(super) fn split_lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').map(|line| line.strip_suffix('\n').unwrap_or(line)).collect()
+}
+
+fn no_final_newline(text: &str) -> bool {
+    !text.is_empty() && !text.ends_with('\n')
+}
+
+/*
+    None if the files have more than options.max_edits changed lines
+*/
+pub fn diff_text(old_text: &str, new_text: &str, options: &TextDiffOptions) -> Option<TextDiff> {
+    let old_lines = split_lines(old_text);
+    let new_lines = split_lines(new_text);
+
+    let (old_no_newline, new_no_newline) = (no_final_newline(old_text), no_final_newline(new_text));
+
+    let mut old_keys: Vec<String> = old_lines.iter().map(|line| options.normalize(line)).collect();
+    let mut new_keys: Vec<String> = new_lines.iter().map(|line| options.normalize(line)).collect();
+    // A last line that only gains or loses its newline is a change
+    if old_no_newline != new_no_newline {
+        let keys = if old_no_newline { &mut old_keys } else { &mut new_keys };
+        if let Some(last) = keys.last_mut() {
+            last.push('\0');
+        }
+    }
+
+    let ops = diff_ops(&old_keys, &new_keys, options.max_edits)?;
+    let mut hunks = hunks(&ops, &old_lines, &new_lines, options.context);
+    for hunk in &mut hunks {
+        hunk.old_no_newline = old_no_newline && hunk.old_start + hunk.old_len > old_lines.len();
+        hunk.new_no_newline = new_no_newline && hunk.new_start + hunk.new_len > new_lines.len();
+    }
+    Some(TextDiff { hunks })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Op {
+    // Indexes of the line in the old and in the new file
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/*
+    Shortest edit script (Myers). The common prefix and suffix are removed
+    first, the other lines are compared with the O(ND) algorithm. None if
+    more than max_edits lines are inserted or deleted.
+*/
+pub(super) fn diff_ops(a: &[String], b: &[String], max_edits: usize) -> Option<Vec<Op>> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let mut ops: Vec<Op> = (0..prefix).map(|i| Op::Equal(i, i)).collect();
+    let middle = myers(&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix], max_edits)?;
+    ops.extend(middle.into_iter().map(|op| match op {
+        Op::Equal(i, j) => Op::Equal(i + prefix, j + prefix),
+        Op::Delete(i) => Op::Delete(i + prefix),
+        Op::Insert(j) => Op::Insert(j + prefix),
+    }));
+    ops.extend((0..suffix).map(|i| Op::Equal(a.len() - suffix + i, b.len() - suffix + i)));
+    Some(ops)
+}
+
+fn myers(a: &[String], b: &[String], max_edits: usize) -> Option<Vec<Op>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    // The edit distance is at least the difference of lengths
+    if (n - m).unsigned_abs() > max_edits {
+        return None;
+    }
+    let max = (n + m).min(max_edits as isize);
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+
+    // trace[d] holds v[-d..=d] after the step d
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let mut found = false;
+    'search: for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+
+            if x >= n && y >= m {
+                found = true;
+                trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+                break 'search;
+            }
+        }
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+    }
+    // More than max_edits changed lines
+    if !found {
+        return None;
+    }
+
+    // Walk back from (n, m)
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (1..trace.len() as isize).rev() {
+        let prev = &trace[d as usize - 1];
+        let get = |k: isize| prev[(k + d - 1) as usize];
+
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) { k + 1 } else { k - 1 };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            ops.push(Op::Equal(x as usize, y as usize));
+        }
+        if prev_k == k + 1 {
+            ops.push(Op::Insert(prev_y as usize));
+        } else {
+            ops.push(Op::Delete(prev_x as usize));
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    while x > 0 && y > 0 {
+        x -= 1;
+        y -= 1;
+        ops.push(Op::Equal(x as usize, y as usize));
+    }
+
+    ops.reverse();
+    Some(ops)
+}
+
+/*
+    Group the changes with context lines around them, changes separated by
+    at most 2 * context unchanged lines are in the same hunk
+*/
+fn hunks(ops: &[Op], old_lines: &[&str], new_lines: &[&str], context: usize) -> Vec<Hunk> {
+    let is_equal = |op: &Op| matches!(op, Op::Equal(..));
+
+    // Position in the old and the new file before each op
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for op in ops {
+        positions.push((old_pos, new_pos));
+        match op {
+            Op::Equal(..) => {
+                old_pos += 1;
+                new_pos += 1;
+            }
+            Op::Delete(_) => old_pos += 1,
+            Op::Insert(_) => new_pos += 1,
+        }
+    }
+    positions.push((old_pos, new_pos));
+
+    let mut hunks = Vec::new();
+    let mut i = 0;
+    while i < ops.len() {
+        if is_equal(&ops[i]) {
+            i += 1;
+            continue;
+        }
+
+        let start = i.saturating_sub(context);
+        let mut j = i;
+        let end = loop {
+            while j < ops.len() && !is_equal(&ops[j]) {
+                j += 1;
+            }
+            let mut next = j;
+            while next < ops.len() && is_equal(&ops[next]) {
+                next += 1;
+            }
+            if next < ops.len() && next - j <= 2 * context {
+                j = next;
+            } else {
+                break (j + context).min(ops.len());
+            }
+        };
+
+        let lines = ops[start..end]
+            .iter()
+            .map(|op| match *op {
+                Op::Equal(old, _) => DiffLine::Context(old_lines[old].to_string()),
+                Op::Delete(old) => DiffLine::Removed(old_lines[old].to_string()),
+                Op::Insert(new) => DiffLine::Added(new_lines[new].to_string()),
+            })
+            .collect();
+
+        let (old_begin, new_begin) = positions[start];
+        let (old_end, new_end) = positions[end];
+        let (old_len, new_len) = (old_end - old_begin, new_end - new_begin);
+        hunks.push(Hunk {
+            // An empty range starts at the line before it
+            old_start: if old_len == 0 { old_begin } else { old_begin + 1 },
+            old_len,
+            new_start: if new_len == 0 { new_begin } else { new_begin + 1 },
+            new_len,
+            lines,
+            old_no_newline: false,
+            new_no_newline: false,
+        });
+        i = end;
+    }
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::diff::{compare_with, DiffOptions};
+    use crate::core::testutil::{image, remove, tree};
+
+    #[test]
+    fn unified_output() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\n";
+        let text_diff = diff_text(old, new, &TextDiffOptions::default()).unwrap();
+
+        assert_eq!(text_diff.hunks.len(), 2);
+        assert_eq!(
+            text_diff.unified("/etc/f", "/etc/f"),
+            "--- a/etc/f\n+++ b/etc/f\n\
+             @@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n\
+             @@ -8,3 +8,4 @@\n h\n i\n j\n+k\n"
+        );
+    }
+
+    #[test]
+    fn missing_final_newline() {
+        let text_diff = diff_text("a\nb", "a\nc\n", &TextDiffOptions::default()).unwrap();
+        assert_eq!(text_diff.hunks[0].to_string(), "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n");
+
+        // Only the newline changes
+        let text_diff = diff_text("a\nb\n", "a\nb", &TextDiffOptions::default()).unwrap();
+        assert_eq!(text_diff.hunks[0].to_string(), "@@ -1,2 +1,2 @@\n a\n-b\n+b\n\\ No newline at end of file\n");
+
+        // Both sides without one
+        let text_diff = diff_text("a\nb", "A\nb", &TextDiffOptions::default()).unwrap();
+        assert_eq!(text_diff.hunks[0].to_string(), "@@ -1,2 +1,2 @@\n-a\n+A\n b\n\\ No newline at end of file\n");
+        assert!(diff_text("a\nb", "a\nb", &TextDiffOptions::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn close_changes_share_a_hunk() {
+        let mut options = TextDiffOptions {
+            context: 1,
+            ..TextDiffOptions::default()
+        };
+        assert_eq!(diff_text("1\n2\n3\n4\n5\n", "0\n2\n3\n4\n6\n", &options).unwrap().hunks.len(), 2);
+
+        options.context = 2;
+        let text_diff = diff_text("1\n2\n3\n4\n5\n", "0\n2\n3\n4\n6\n", &options).unwrap();
+        assert_eq!(text_diff.hunks.len(), 1);
+        assert_eq!(text_diff.hunks[0].to_string(), "@@ -1,5 +1,5 @@\n-1\n+0\n 2\n 3\n 4\n-5\n+6\n");
+    }
+
+    #[test]
+    fn empty_sides() {
+        let text_diff = diff_text("", "a\nb\n", &TextDiffOptions::default()).unwrap();
+        assert_eq!(text_diff.hunks[0].to_string(), "@@ -0,0 +1,2 @@\n+a\n+b\n");
+
+        let text_diff = diff_text("a\n", "", &TextDiffOptions::default()).unwrap();
+        assert_eq!(text_diff.hunks[0].to_string(), "@@ -1,1 +0,0 @@\n-a\n");
+
+        assert!(diff_text("", "", &TextDiffOptions::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn normalization() {
+        let mut options = TextDiffOptions {
+            whitespace: Whitespace::IgnoreChanges,
+            ignore_line_endings: true,
+            ..TextDiffOptions::default()
+        };
+        assert!(diff_text("a  b\r\nc\n", "a b\n  c \n", &options).unwrap().is_empty());
+
+        options.ignore_patterns = vec![Regex::new(r"\d{2}:\d{2}").unwrap()];
+        assert!(diff_text("built 12:30\n", "built 18:05\n", &options).unwrap().is_empty());
+
+        // The lines are shown as they are in the files
+        let text_diff = diff_text("x 12:30\n", "y 18:05\n", &options).unwrap();
+        let lines: Vec<String> = text_diff.lines().map(|line| line.to_string()).collect();
+        assert_eq!(lines, ["-x 12:30", "+y 18:05"]);
+    }
+
+    #[test]
+    fn too_many_edits() {
+        let old: String = (0..100).map(|i| format!("old {}\n", i)).collect();
+        let new: String = (0..100).map(|i| format!("new {}\n", i)).collect();
+        let options = TextDiffOptions {
+            max_edits: 50,
+            ..TextDiffOptions::default()
+        };
+        assert!(diff_text(&old, &new, &options).is_none());
+        assert!(diff_text(&old, &new, &TextDiffOptions::default()).is_some());
+
+        // A long common prefix does not count
+        let new = format!("{}extra\n", old);
+        assert_eq!(diff_text(&old, &new, &options).unwrap().lines().count(), 4);
+    }
+
+    #[test]
+    fn minimal_edit_script() {
+        let keys = |text: &str| -> Vec<String> { text.chars().map(|c| c.to_string()).collect() };
+        let ops = diff_ops(&keys("abcabba"), &keys("cbabac"), 100).unwrap();
+        let edits = ops.iter().filter(|op| !matches!(op, Op::Equal(..))).count();
+        assert_eq!(edits, 5);
+    }
+
+    #[test]
+    fn extensionless_config_files() {
+        let old = image("text_old", &[("/etc/passwd", b"root:x:0:0::/root:/bin/sh\n"), ("/bin/blob", b"\x00\x01")]);
+        let new = image(
+            "text_new",
+            &[("/etc/passwd", b"root:x:0:0::/root:/bin/sh\nadmin:x:0:0::/:/bin/sh\n"), ("/bin/blob", b"\x00\x02")],
+        );
+        let result = compare_with(&tree(&old), &tree(&new), &DiffOptions::default());
+
+        let passwd = result.entries.iter().find(|entry| entry.fs_path() == "/etc/passwd").unwrap();
+        let lines: Vec<String> = passwd.text_diff.as_ref().unwrap().lines().map(|line| line.to_string()).collect();
+        assert_eq!(lines, [" root:x:0:0::/root:/bin/sh", "+admin:x:0:0::/:/bin/sh"]);
+
+        let blob = result.entries.iter().find(|entry| entry.fs_path() == "/bin/blob").unwrap();
+        assert!(blob.text_diff.is_none());
+
+        remove(&old);
+        remove(&new);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::core::fstree::node::name;
+use crate::core::fstree::pass::PassError;
+use crate::core::query::QueryError;
+use crate::core::secrets::RuleError;
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    // I/O error on a path of the local system (escaped)
+    Io { path: String, err: Arc<io::Error> },
+    NotADirectory(String),
+    // Malformed ELF
+    Elf { path: String, msg: String },
+    Pass(Arc<PassError>),
+    Query(Arc<QueryError>),
+    Rules(Arc<RuleError>),
+    ThreadPool(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn io<P: AsRef<Path>>(path: P, err: io::Error) -> Self {
+        Error::Io {
+            path: name::escape_path(path.as_ref()),
+            err: Arc::new(err),
+        }
+    }
+
+    pub fn elf(path: &str, msg: &str) -> Self {
+        Error::Elf {
+            path: path.to_string(),
+            msg: msg.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path, err } => write!(f, "{}: {}", path, err),
+            Error::NotADirectory(path) => write!(f, "{} is not a directory", path),
+            Error::Elf { path, msg } => write!(f, "{}: malformed ELF: {}", path, msg),
+            Error::Pass(err) => write!(f, "{}", err),
+            Error::Query(err) => write!(f, "{}", err),
+            Error::Rules(err) => write!(f, "{}", err),
+            Error::ThreadPool(msg) => write!(f, "can't create the thread pool: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { err, .. } => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<PassError> for Error {
+    fn from(err: PassError) -> Self {
+        Error::Pass(Arc::new(err))
+    }
+}
+
+impl From<QueryError> for Error {
+    fn from(err: QueryError) -> Self {
+        Error::Query(Arc::new(err))
+    }
+}
+
+impl From<RuleError> for Error {
+    fn from(err: RuleError) -> Self {
+        Error::Rules(Arc::new(err))
+    }
+}
+
+/*
+    A node which could not be (fully) analysed
+*/
+#[derive(Debug, Clone)]
+pub struct NodeError {
+    // Path in the fs
+    pub fs_path: String,
+    // Step which failed (build, a pass name...)
+    pub stage: String,
+    pub error: Error,
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.stage, self.fs_path, self.error)
+    }
+}
+
+/*
+    Errors recorded while building and analysing a tree, shared by the threads
+*/
+#[derive(Debug, Default)]
+pub struct ErrorList {
+    errors: Mutex<Vec<NodeError>>,
+}
+
+impl ErrorList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, fs_path: &str, stage: &str, error: Error) {
+        let mut errors = self.errors.lock().unwrap();
+        errors.push(NodeError {
+            fs_path: fs_path.to_string(),
+            stage: stage.to_string(),
+            error,
+        });
+    }
+
+    pub fn append(&self, mut node_errors: Vec<NodeError>) {
+        let mut errors = self.errors.lock().unwrap();
+        errors.append(&mut node_errors);
+    }
+
+    /*
+        Errors sorted by path, the threads record them in any order
+    */
+    pub fn to_vec(&self) -> Vec<NodeError> {
+        let errors = self.errors.lock().unwrap();
+        let mut errors = errors.clone();
+        errors.sort_by(|a, b| (&a.fs_path, &a.stage).cmp(&(&b.fs_path, &b.stage)));
+        errors
+    }
+
+    pub fn len(&self) -> usize {
+        self.errors.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
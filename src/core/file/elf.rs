@@ -1,23 +1,23 @@
-use crate::core::node::TreeNode;
-
 use crate::core::fstree::node::Node;
+use crate::core::fstree::{normalize_path, FsTree};
 
 use std::collections::HashMap;
-use std::fs;
-use std::fs::File;
 
 use crate::core::file::FileType;
-use crate::core::tree::TreeData;
 
-use xmas_elf::symbol_table::Binding;
-use xmas_elf::symbol_table::DynEntry32;
 use xmas_elf::symbol_table::Entry;
+use xmas_elf::dynamic::Tag;
 use xmas_elf::ElfFile;
-use xmas_elf::{header, program, program::Flags, sections, sections::SectionHeader};
-
-use std::sync::{Arc, Mutex, RwLock};
+use xmas_elf::{header, program, sections, sections::SectionHeader};
 
 use log::warn;
+
+// Directories searched after DT_RPATH, DT_RUNPATH and /etc/ld.so.conf
+const DEFAULT_LIB_DIRS: [&str; 5] = ["/lib", "/usr/lib", "/lib64", "/usr/lib64", "/usr/local/lib"];
+
+// Nesting limit of the include lines of ld.so.conf
+const MAX_LD_CONF_DEPTH: usize = 8;
+
 // #[repr(C)]
 // pub struct ElfHeader {
 //     // Magic value 0x7fELF
@@ -35,10 +35,20 @@ use log::warn;
 
 pub struct ElfCmpResult {}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ElfData {
     //pub libs: Vec<String>,
     pub size: u64,
+    // Target architecture (ex: Arm, X86_64, Mips)
+    pub machine: String,
+    // Position independent executable
+    pub pie: bool,
+    // DT_NEEDED entries, in load order
+    pub needed: Vec<String>,
+    // Search directories of DT_RPATH and DT_RUNPATH, $ORIGIN is not expanded
+    pub rpath: Vec<String>,
+    pub runpath: Vec<String>,
+    // Libraries of needed found in the tree
     pub dyn_libs: HashMap<String, Node>,
     pub dyn_funcs: Vec<String>,
 }
@@ -47,12 +57,23 @@ impl ElfData {
     pub fn new() -> Self {
         Self {
             size: 0,
+            machine: String::new(),
+            pie: false,
+            needed: Vec::new(),
+            rpath: Vec::new(),
+            runpath: Vec::new(),
             dyn_libs: HashMap::new(),
             dyn_funcs: Vec::new(),
         }
     }
 }
 
+impl Default for ElfData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct ElfHeader32 {
@@ -213,23 +234,16 @@ pub struct ElfHeader {
     pub sh_str_index: u16,
 }
 
-pub fn check_elf(ptr: *const u8, file_type: &mut FileType) {
-    unsafe {
-        if *(ptr as *const u32) == 0x464c457f {
-            let elf_header = ptr as *const ElfHeader;
-
-            // Valid the elf file
-            if (*elf_header).class <= 2
-                && (*elf_header).class >= 0
-                && (*elf_header).endianess <= 2
-                && (*elf_header).endianess >= 0
-            {
-                match file_type {
-                    FileType::Data => {
-                        *file_type = FileType::Elf(None);
-                    }
-                    _ => {}
-                }
+pub fn check_elf(content: &[u8], file_type: &mut FileType) {
+    if content.get(..4) != Some(b"\x7fELF") {
+        return;
+    }
+
+    // Valid the elf file (class and endianess of the identification bytes)
+    if let (Some(&class), Some(&endianess)) = (content.get(4), content.get(5)) {
+        if class <= 2 && endianess <= 2 {
+            if let FileType::Data = file_type {
+                *file_type = FileType::Elf(None);
             }
         }
     }
@@ -240,7 +254,6 @@ pub fn simple_elf_cmp(ptr1: *const u8, ptr2: *const u8) -> bool {
     let mut res = false;
 
     let elf_header1 = ptr1 as *const ElfHeader;
-    let elf_header2 = ptr2 as *const ElfHeader;
 
     // unsafe { println!("{}", (*elf_header1).class) };
 
@@ -280,117 +293,86 @@ pub fn simple_elf_cmp(ptr1: *const u8, ptr2: *const u8) -> bool {
     res
 }
 
-pub fn analyse_dynamic(
-    tree_data: &TreeData,
-    elf: &ElfFile,
-    section: SectionHeader,
-    elf_data: &mut ElfData,
-) {
-    //println!("Analyse ELF");
-    // Get data from dynamic table
-    let data = section.get_data(&elf).unwrap();
-
-    match data {
-        sections::SectionData::Dynamic32(entries) => {
-            // Check all entries
-            for entry in entries {
-                // If the type is needed check if a string is found
-                if entry.get_tag().unwrap() == xmas_elf::dynamic::Tag::Needed {
-                    let res = elf.get_dyn_string(entry.get_val().unwrap());
-
-                    match res {
-                        Ok(v) => {
-                            //let nodes = tree_data.find_nodes_by_name(v);
-                            //elf_data.libs.insert(v.to_string());
-                        }
-                        Err(_) => {}
-                    }
-                    //println!("{:?}", res);
-                }
-            }
-        }
-        _ => {}
-    }
-}
-
-pub fn analyse_elf(tree_data: &TreeData, path: &str) -> ElfData {
-    let mut elf_data = ElfData::new();
-
-    let file = File::open(path).unwrap();
-    let file_size = file.metadata().unwrap().len();
-    elf_data.size = file_size;
-
-    let mut binary_data = std::fs::read(path).unwrap();
-    let elf = ElfFile::new(&mut binary_data).unwrap();
-
-    for section in elf.section_iter() {
-        match section.get_type().unwrap() {
-            sections::ShType::DynSym => {
-                //analyse_dyn_sym(tree_data, &elf, section, &mut node_elf_data)
-            }
-            sections::ShType::Dynamic => analyse_dynamic(tree_data, &elf, section, &mut elf_data),
-            _ => {}
-        }
-    }
-
-    elf_data
-}
-
-pub fn get_dynamic_libs(elf: &ElfFile, section: SectionHeader) -> Vec<String> {
-    let mut libs: Vec<String> = Vec::new();
-
-    let data_section = section.get_data(&elf).unwrap();
+/*
+    DT_NEEDED, DT_RPATH and DT_RUNPATH entries of the dynamic section
+*/
+fn read_dynamic(elf: &ElfFile, section: SectionHeader, elf_data: &mut ElfData) {
+    let data_section = match section.get_data(elf) {
+        Ok(data_section) => data_section,
+        Err(_) => return,
+    };
 
     match data_section {
         sections::SectionData::Dynamic32(entries) => {
             for entry in entries {
-                if entry.get_tag().unwrap() == xmas_elf::dynamic::Tag::Needed {
-                    let res = elf.get_dyn_string(entry.get_val().unwrap());
-                    match res {
-                        Ok(v) => libs.push(v.to_string()),
-                        Err(_) => {}
-                    }
+                if let (Ok(tag), Ok(val)) = (entry.get_tag(), entry.get_val()) {
+                    add_dynamic_string(elf, &tag, val as u64, elf_data);
                 }
             }
         }
         sections::SectionData::Dynamic64(entries) => {
             for entry in entries {
-                if entry.get_tag().unwrap() == xmas_elf::dynamic::Tag::Needed {
-                    let res = elf.get_dyn_string(entry.get_val().unwrap() as u32);
-                    match res {
-                        Ok(v) => libs.push(v.to_string()),
-                        Err(_) => {}
-                    }
+                if let (Ok(tag), Ok(val)) = (entry.get_tag(), entry.get_val()) {
+                    add_dynamic_string(elf, &tag, val, elf_data);
                 }
             }
         }
         _ => {}
     };
+}
+
+fn add_dynamic_string<P>(elf: &ElfFile, tag: &Tag<P>, val: u64, elf_data: &mut ElfData) {
+    if !matches!(tag, Tag::Needed | Tag::RPath | Tag::RunPath) {
+        return;
+    }
+    let value = match dyn_string(elf, val) {
+        Some(value) => value,
+        None => return,
+    };
+
+    match tag {
+        Tag::Needed => elf_data.needed.push(value),
+        Tag::RPath => elf_data.rpath.extend(split_search_path(&value)),
+        Tag::RunPath => elf_data.runpath.extend(split_search_path(&value)),
+        _ => {}
+    }
+}
 
-    //println!("{:?}", libs);
-    libs
+/*
+    String of the .dynstr section, None if the offset is out of the section
+*/
+fn dyn_string(elf: &ElfFile, offset: u64) -> Option<String> {
+    let dynstr = elf.find_section_by_name(".dynstr")?.raw_data(elf);
+    let bytes = dynstr.get(offset as usize..)?;
+    let end = bytes.iter().position(|b| *b == 0)?;
+    Some(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+fn split_search_path(value: &str) -> Vec<String> {
+    value.split(':').filter(|dir| !dir.is_empty()).map(|dir| dir.to_string()).collect()
 }
 
 fn get_dyn_func(elf: &ElfFile, section: SectionHeader) -> Vec<String> {
     let mut dyn_funcs: Vec<String> = Vec::new();
 
-    let data = section.get_data(&elf).unwrap();
+    let data = match section.get_data(elf) {
+        Ok(data) => data,
+        Err(_) => return dyn_funcs,
+    };
 
     match data {
         sections::SectionData::DynSymbolTable32(entries) => {
             for entry in entries {
-                let name = entry.get_name(&elf).unwrap();
-                let binding = entry.get_binding().unwrap();
-                let entry_type = entry.get_type().unwrap();
-
-                //println!("{} {:?} {:?}", name, binding, entry_type);
-                
-                /*match binding {
-                    Binding::Global => {
-                        println!("{} {:?} {:?}", name, binding, entry_type);
-                    }
-                    _ => {}
-                }*/
+                if let Some(name) = imported_symbol(elf, entry) {
+                    dyn_funcs.push(name);
+                }
+            }
+        }
+        sections::SectionData::DynSymbolTable64(entries) => {
+            for entry in entries {
+                if let Some(name) = imported_symbol(elf, entry) {
+                    dyn_funcs.push(name);
+                }
             }
         }
         _ => {}
@@ -399,22 +381,49 @@ fn get_dyn_func(elf: &ElfFile, section: SectionHeader) -> Vec<String> {
     dyn_funcs
 }
 
-pub fn analyse_elf2(head_node: Node, path: &str) -> ElfData {
+/*
+    Undefined dynamic symbols are the ones imported from the dynamic libraries
+*/
+fn imported_symbol<E: Entry>(elf: &ElfFile, entry: &E) -> Option<String> {
+    if entry.shndx() != 0 {
+        return None;
+    }
+    
+    match entry.get_name(elf) {
+        Ok(name) if !name.is_empty() => Some(name.to_string()),
+        _ => None,
+    }
+}
+
+/*
+    Analyse an ELF from its content (mapped by the file pipeline), malformed
+    headers are returned as errors. The libraries are found afterwards by
+    resolve_libraries.
+*/
+pub fn analyse_elf2(binary_data: &[u8]) -> Result<ElfData, &'static str> {
     let mut elf_data = ElfData::new();
 
-    // Get size of file
-    let metadata = fs::metadata(path).unwrap();
-    elf_data.size = metadata.len();
+    elf_data.size = binary_data.len() as u64;
 
     // Open file using xmas_elf
-    let mut binary_data = std::fs::read(path).unwrap();
-    let elf = ElfFile::new(&mut binary_data).unwrap();
+    let elf = ElfFile::new(binary_data)?;
+    header::sanity_check(&elf)?;
+
+    elf_data.machine = format!("{:?}", elf.header.pt2.machine().as_machine());
+
+    /*
+        A PIE is a shared object with an interpreter
+    */
+    let is_shared_object = matches!(elf.header.pt2.type_().as_type(), header::Type::SharedObject);
+    let has_interpreter = elf
+        .program_iter()
+        .any(|ph| matches!(ph.get_type(), Ok(program::Type::Interp)));
+    elf_data.pie = is_shared_object && has_interpreter;
 
-    let mut dyn_libs: Vec<String> = Vec::new();
     let mut dyn_funcs: Vec<String> = Vec::new();
 
     for section in elf.section_iter() {
-        match section.get_type().unwrap() {
+        match section.get_type()? {
             /*
                 Get list of dynamic symbols
             */
@@ -422,61 +431,203 @@ pub fn analyse_elf2(head_node: Node, path: &str) -> ElfData {
                 dyn_funcs = get_dyn_func(&elf, section);
             }
             sections::ShType::SymTab => {
-                let data = section.get_data(&elf).unwrap();
-                match data {
-                    sections::SectionData::SymbolTable32(entries) => {
-                        /*for entry in entries {
-
-                            let name = entry.get_name(&elf).unwrap();
-                            let binding = entry.get_binding().unwrap();
-                            let entry_type = entry.get_type().unwrap();
-                            //println!("{} {:?}", name, binding);
-
-                            match binding {
-                                Binding::Global => {
-                                    println!("{} {:?} {:?}", name, binding, entry_type);
-                                }
-                                _ => {
-                                }
+                let data = section.get_data(&elf)?;
+                if let sections::SectionData::SymbolTable32(_entries) = data {
+                    /*for entry in entries {
+
+                        let name = entry.get_name(&elf).unwrap();
+                        let binding = entry.get_binding().unwrap();
+                        let entry_type = entry.get_type().unwrap();
+                        //println!("{} {:?}", name, binding);
+
+                        match binding {
+                            Binding::Global => {
+                                println!("{} {:?} {:?}", name, binding, entry_type);
+                            }
+                            _ => {
                             }
-                            /*let res = entry.get_name(&elf);
-                            match res {
-                                Ok(v) => println!("{:?}", res),
-                                Err(_) => {}
-                            }*/
+                        }
+                        /*let res = entry.get_name(&elf);
+                        match res {
+                            Ok(v) => println!("{:?}", res),
+                            Err(_) => {}
                         }*/
-                    }
-                    _ => {}
+                    }*/
                 }
             }
             /*
                 Get list of dynamic libraries
             */
             sections::ShType::Dynamic => {
-                dyn_libs = get_dynamic_libs(&elf, section);
+                read_dynamic(&elf, section, &mut elf_data);
             }
             _ => {}
         }
     }
     
-    /*
-        Create dynamic libraries hash map
-    */
-    let mut dyn_libs_map: HashMap<String, Node> = HashMap::new();
-    for dyn_lib in dyn_libs {
-        let node_list = head_node.find_node_by_name_rec(&dyn_lib);
-        //println!("{} {}", dyn_lib, node_list.len());
-        if node_list.len() == 1 {
-            //println!("{} {}", dyn_lib, node_list.len());
-            dyn_libs_map.insert(dyn_lib, node_list[0].clone());
+    elf_data.dyn_funcs = dyn_funcs;
+    
+    Ok(elf_data)
+}
+
+/*
+    Directories of /etc/ld.so.conf (and of the files it includes) followed
+    by the default directories of the loader
+*/
+pub fn library_dirs(fstree: &FsTree) -> Vec<String> {
+    let mut dirs = Vec::new();
+    read_ld_conf(fstree, "/etc/ld.so.conf", &mut dirs, 0);
+    for dir in DEFAULT_LIB_DIRS {
+        if !dirs.iter().any(|d| d == dir) {
+            dirs.push(dir.to_string());
         }
-        else if node_list.len() == 0 {
-            warn!("Dynamic library {} not found", dyn_lib);
+    }
+    dirs
+}
+
+fn read_ld_conf(fstree: &FsTree, path: &str, dirs: &mut Vec<String>, depth: usize) {
+    if depth > MAX_LD_CONF_DEPTH {
+        return;
+    }
+    let content = match fstree.read_file(path) {
+        Some(content) => String::from_utf8_lossy(&content).into_owned(),
+        None => return,
+    };
+
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if let Some(pattern) = line.strip_prefix("include") {
+            for included in conf_includes(fstree, pattern.trim()) {
+                read_ld_conf(fstree, &included, dirs, depth + 1);
+            }
+        } else if line.starts_with('/') {
+            let dir = normalize_path(line);
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
         }
     }
-    
-    elf_data.dyn_libs = dyn_libs_map;
-    elf_data.dyn_funcs = dyn_funcs;
-    
-    elf_data
+}
+
+/*
+    Files of an include line of ld.so.conf, a '*' is only supported in the
+    file name (ex: include ld.so.conf.d/<glob>.conf)
+*/
+fn conf_includes(fstree: &FsTree, pattern: &str) -> Vec<String> {
+    let pattern = if pattern.starts_with('/') {
+        pattern.to_string()
+    } else {
+        format!("/etc/{}", pattern)
+    };
+    let (dir, file_pattern) = match pattern.rsplit_once('/') {
+        Some((dir, file_pattern)) if file_pattern.contains('*') => (dir, file_pattern),
+        _ => return vec![pattern.clone()],
+    };
+    let (prefix, suffix) = file_pattern.split_once('*').unwrap_or((file_pattern, ""));
+
+    let mut files: Vec<String> = match fstree.get_follow(dir) {
+        Some(dir_node) => dir_node
+            .childrens()
+            .iter()
+            .map(|child| child.name())
+            .filter(|name| name.len() >= prefix.len() + suffix.len())
+            .filter(|name| name.starts_with(prefix) && name.ends_with(suffix))
+            .map(|name| format!("{}/{}", dir, name))
+            .collect(),
+        None => Vec::new(),
+    };
+    files.sort();
+    files
+}
+
+/*
+    Find the file loaded for each DT_NEEDED entry of the ELF at fs_path, in
+    the order of the loader: DT_RPATH (ignored if there is a DT_RUNPATH),
+    DT_RUNPATH then lib_dirs (see library_dirs). $ORIGIN is the directory
+    of the ELF.
+*/
+pub fn resolve_libraries(fstree: &FsTree, fs_path: &str, elf_data: &ElfData, lib_dirs: &[String]) -> HashMap<String, Node> {
+    let origin = match fs_path.rsplit_once('/') {
+        Some((origin, _)) if !origin.is_empty() => origin,
+        _ => "/",
+    };
+    let expand = |dir: &String| dir.replace("${ORIGIN}", origin).replace("$ORIGIN", origin);
+
+    let mut search_dirs: Vec<String> = Vec::new();
+    if elf_data.runpath.is_empty() {
+        search_dirs.extend(elf_data.rpath.iter().map(expand));
+    }
+    search_dirs.extend(elf_data.runpath.iter().map(expand));
+    search_dirs.extend(lib_dirs.iter().cloned());
+
+    let mut dyn_libs = HashMap::new();
+    for needed in &elf_data.needed {
+        // A name with a slash is a path and is not searched
+        let found = if needed.contains('/') {
+            fstree.get_follow(needed)
+        } else {
+            search_dirs
+                .iter()
+                .filter_map(|dir| fstree.get_follow(&format!("{}/{}", dir, needed)))
+                .find(|node| node.is_file())
+        };
+
+        match found.filter(|node| node.is_file()) {
+            Some(node) => {
+                dyn_libs.insert(needed.clone(), node);
+            }
+            None => warn!("Dynamic library {} of {} not found", needed, fs_path),
+        }
+    }
+    dyn_libs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::{image, remove};
+
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn library_search_order() {
+        let root = image(
+            "elf_libs",
+            &[
+                ("/opt/app/bin/tool", b""),
+                ("/opt/app/lib/libfoo.so", b""),
+                ("/usr/lib/libfoo.so", b""),
+                ("/lib/libbar.so.1.2", b""),
+                ("/opt/extra/libbaz.so", b""),
+                ("/etc/ld.so.conf", b"# local libraries\ninclude ld.so.conf.d/*.conf\n"),
+                ("/etc/ld.so.conf.d/extra.conf", b"/opt/extra\n"),
+            ],
+        );
+        symlink("libbar.so.1.2", root.join("lib/libbar.so.1")).unwrap();
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+
+        let lib_dirs = library_dirs(&fstree);
+        assert_eq!(lib_dirs[..3], ["/opt/extra", "/lib", "/usr/lib"]);
+
+        let mut elf_data = ElfData::new();
+        elf_data.needed = ["libfoo.so", "libbar.so.1", "libbaz.so", "libmissing.so"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        elf_data.rpath = vec!["$ORIGIN/../lib".to_string()];
+
+        let path = |libs: &HashMap<String, Node>, name: &str| libs.get(name).map(|node| node.fs_path());
+        let libs = resolve_libraries(&fstree, "/opt/app/bin/tool", &elf_data, &lib_dirs);
+        assert_eq!(path(&libs, "libfoo.so").as_deref(), Some("/opt/app/lib/libfoo.so"));
+        assert_eq!(path(&libs, "libbar.so.1").as_deref(), Some("/lib/libbar.so.1.2"));
+        assert_eq!(path(&libs, "libbaz.so").as_deref(), Some("/opt/extra/libbaz.so"));
+        assert_eq!(libs.len(), 3);
+
+        // DT_RPATH is ignored when there is a DT_RUNPATH
+        elf_data.runpath = vec!["/nowhere".to_string()];
+        let libs = resolve_libraries(&fstree, "/opt/app/bin/tool", &elf_data, &lib_dirs);
+        assert_eq!(path(&libs, "libfoo.so").as_deref(), Some("/usr/lib/libfoo.so"));
+
+        remove(&root);
+    }
 }
@@ -1,7 +1,7 @@
 pub mod elf;
 pub mod extension;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum FileType {
     Data,
     Text,
@@ -23,15 +23,63 @@ pub enum FileType {
 
     Php,
     Cgi,
+    Lua,
 
     Asp,
     Aspx,
 }
 
+impl FileType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileType::Data => "data",
+            FileType::Text => "text",
+            FileType::SymLink => "symlink",
+            FileType::Sh => "sh",
+            FileType::Elf(_) => "elf",
+            FileType::Driver => "driver",
+            FileType::Pe => "pe",
+            FileType::Header => "header",
+            FileType::Source => "source",
+            FileType::Markdown => "markdown",
+            FileType::Html => "html",
+            FileType::XHtml => "xhtml",
+            FileType::Js => "js",
+            FileType::Php => "php",
+            FileType::Cgi => "cgi",
+            FileType::Lua => "lua",
+            FileType::Asp => "asp",
+            FileType::Aspx => "aspx",
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(
+            self,
+            FileType::Text
+                | FileType::Sh
+                | FileType::Header
+                | FileType::Source
+                | FileType::Markdown
+                | FileType::Html
+                | FileType::XHtml
+                | FileType::Js
+                | FileType::Php
+                | FileType::Cgi
+                | FileType::Lua
+                | FileType::Asp
+                | FileType::Aspx
+        )
+    }
+}
+
 pub fn check_type(file_name: &str, ptr: &[u8]) -> FileType {
     let mut file_type = FileType::Data;
-    elf::check_elf(ptr as *const _ as *const u8, &mut file_type);
-    extension::check_extension(file_name, ptr as *const _ as *const u8, &mut file_type);
+    // Too short for a magic number, only the extension is used
+    if ptr.len() >= 4 {
+        elf::check_elf(ptr, &mut file_type);
+    }
+    extension::check_extension(file_name, &mut file_type);
 
     match file_type {
         FileType::Php => {
@@ -0,0 +1,31 @@
+use crate::core::fstree::node::Node;
+
+/*
+    Lazy pre-order iterator over the nodes of a tree, childrens are only
+    read when their parent is reached
+*/
+pub struct NodeIter {
+    stack: Vec<Node>,
+}
+
+impl NodeIter {
+    pub fn new(start: Node) -> Self {
+        Self { stack: vec![start] }
+    }
+}
+
+impl Iterator for NodeIter {
+    type Item = Node;
+
+    fn next(&mut self) -> Option<Node> {
+        let node = self.stack.pop()?;
+
+        if node.is_dir() {
+            // Reversed so the first child is visited first
+            let mut childrens = node.childrens();
+            childrens.reverse();
+            self.stack.append(&mut childrens);
+        }
+        Some(node)
+    }
+}
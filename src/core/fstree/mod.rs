@@ -1,13 +1,38 @@
 
 
+pub mod iter;
 pub mod node;
+pub mod pass;
+pub mod pipeline;
+pub mod visit;
 
-use node::Node;
+use iter::NodeIter;
+use pass::{BinaryPass, FileTypePass, HashPass, Pass, PassRegistry};
+use pipeline::{FileConsumer, FilePipeline, DEFAULT_MAX_MAP_SIZE};
+use visit::Visitor;
+use node::{name, InodeKey, Node};
+use node::symlink::{self, LinkStatus};
 
-use std::sync::{Arc, Mutex};
+use crate::core::accounts::AccountAudit;
+use crate::core::diff::{self, DiffOptions, TreeCmpResult};
+use crate::core::error::{Error, ErrorList, NodeError, Result};
+use crate::core::hash::DigestKind;
+use crate::core::permissions::PermissionReport;
+use crate::core::query::Query;
+use crate::core::services::{self, BootService};
+use crate::core::surface::AttackSurface;
+use crate::core::web::WebAnalysis;
+use crate::core::secrets::{SecretFinding, SecretRules, SecretScan};
 
+use std::sync::Arc;
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use std::collections::HashMap;
+
+use std::fs;
 use std::fs::metadata;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 
 pub struct FsTree {
     // Path of the FS on the local system
@@ -15,55 +40,490 @@ pub struct FsTree {
     
     // Root directory node
     pub head_node: Node,
+    
+    // Nodes indexed by their raw path in the fs (ex: /usr/lib/libc.so)
+    pub index: HashMap<PathBuf, Node>,
+    
+    // Threads used to build and analyse the tree
+    pool: Arc<ThreadPool>,
+    
+    // Nodes which could not be built or analysed
+    errors: ErrorList,
 }
 
 impl FsTree {
-    pub fn build_from_path(path: &str) -> Self {
-        let path_metadata = metadata(path).unwrap();
+    /*
+        Build the tree with one thread per CPU
+    */
+    pub fn build_from_path(path: &str) -> Result<Self> {
+        Self::build_with_threads(path, 0)
+    }
+    
+    /*
+        Build the tree with the given number of threads (0 for one per CPU),
+        the same threads are used by the analysis passes. Entries which can't
+        be read are skipped and recorded in the errors of the tree.
+    */
+    pub fn build_with_threads(path: &str, threads: usize) -> Result<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|err| Error::ThreadPool(err.to_string()))?;
+        let path_metadata = metadata(path).map_err(|err| Error::io(path, err))?;
         
         if !path_metadata.is_dir() {
-            panic!("The root path is not a directory");
-        } else {
-            // Get the directory name
-            let dir_name = Path::new(path).file_name().unwrap().to_str().unwrap();
-            
-            // Build the tree
-            let head_node = Node::new_dir(path, dir_name, path, path, None);
-            
-            let fstree = Self {
-                path: path.to_string(),
-                head_node,
-            };
-            fstree
+            return Err(Error::NotADirectory(path.to_string()));
+        }
+        
+        // Get the directory name
+        let root_path = Path::new(path);
+        let dir_name = match root_path.file_name() {
+            Some(name) => name,
+            None => root_path.as_os_str(),
+        };
+        
+        // Build the tree
+        let errors = ErrorList::new();
+        let head_node = pool.install(|| Node::new_dir(root_path, dir_name, root_path, Path::new("/"), None, &errors))?;
+        
+        let index = NodeIter::new(head_node.clone()).map(|node| (node.fs_path_os(), node)).collect();
+        
+        let fstree = Self {
+            path: path.to_string(),
+            head_node,
+            index,
+            pool: Arc::new(pool),
+            errors,
+        };
+        Ok(fstree)
+    }
+    
+    /*
+        Record a failure on a node, the analysis goes on without it
+    */
+    pub fn record_error(&self, node: &Node, stage: &str, error: Error) {
+        self.errors.push(&node.fs_path(), stage, error);
+    }
+    
+    /*
+        What could not be built or analysed, sorted by path
+    */
+    pub fn errors(&self) -> Vec<NodeError> {
+        self.errors.to_vec()
+    }
+    
+    pub fn display_errors(&self) {
+        let errors = self.errors();
+        println!("{} nodes could not be analysed", errors.len());
+        for error in errors {
+            println!("    {}", error);
         }
     }
     
+    /*
+        Run op in the thread pool of the tree
+    */
+    pub fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        self.pool.install(op)
+    }
+    
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+    
+    /*
+        Depth-first traversal of the tree with pre and post hooks
+    */
+    pub fn walk<V: Visitor + ?Sized>(&self, visitor: &mut V) {
+        visit::walk(&self.head_node, visitor);
+    }
+    
+    /*
+        Run the passes of a registry (and their dependencies first)
+    */
+    pub fn run_passes(&self, registry: &mut PassRegistry) -> Result<()> {
+        registry.run(self)
+    }
+    
+    /*
+        Number of directories, the root directory is not counted
+    */
     pub fn count_dirs(&self) -> u64 {
-        self.head_node.count_dirs_rec()
+        self.iter().filter(|node| node.is_dir()).count() as u64 - 1
     }
     
     pub fn count_files(&self) -> u64 {
-        self.head_node.count_files_rec()
+        self.files().len() as u64
+    }
+    
+    pub fn count_special_files(&self) -> u64 {
+        self.iter().filter(|node| node.is_special()).count() as u64
     }
     
-    pub fn analyse_files_type(&self) {
-        self.head_node.analyse_files_type_rec();
+    pub fn files(&self) -> Vec<Node> {
+        self.iter().filter(|node| node.is_file()).collect()
     }
     
-    pub fn calc_files_hash(&self) {
-        self.head_node.calc_files_hash_rec();
+    pub fn elf_files(&self) -> Vec<Node> {
+        self.iter().filter(|node| node.is_elf()).collect()
+    }
+    
+    /*
+        Number of distinct files, hard links to the same file are counted once
+    */
+    pub fn count_unique_files(&self) -> u64 {
+        let mut keys: Vec<InodeKey> = self.files().iter().map(|node| node.inode_key()).collect();
+        keys.sort();
+        keys.dedup();
+        keys.len() as u64
+    }
+    
+    /*
+        Groups of paths sharing the same (device, inode)
+    */
+    pub fn hard_link_groups(&self) -> Vec<Vec<Node>> {
+        let mut groups: HashMap<InodeKey, Vec<Node>> = HashMap::new();
+        for node in self.files() {
+            if node.nlink() > 1 {
+                groups.entry(node.inode_key()).or_default().push(node);
+            }
+        }
+        
+        let mut groups: Vec<Vec<Node>> = groups.into_values().filter(|group| group.len() > 1).collect();
+        groups.sort_by_key(|group| group[0].fs_path_os());
+        groups
+    }
+    
+    pub fn count_symlinks(&self) -> u64 {
+        self.symlinks().len() as u64
+    }
+    
+    pub fn symlinks(&self) -> Vec<Node> {
+        self.iter().filter(|node| node.is_symlink()).collect()
+    }
+    
+    /*
+        Dangling links, links escaping the root and link loops
+    */
+    pub fn broken_symlinks(&self) -> Vec<Node> {
+        self.symlinks()
+            .into_iter()
+            .filter(|node| match node.symlink_data() {
+                Some(symlink_data) => symlink_data.status != LinkStatus::Resolved,
+                None => false,
+            })
+            .collect()
+    }
+    
+    /*
+        SUID/SGID, world-writable and ownership report. Files are expected to
+        be owned by root or by an account of /etc/passwd.
+    */
+    pub fn permission_report(&self) -> PermissionReport {
+        let mut expected_uids: Vec<u32> = self.audit_accounts().passwd.iter().map(|user| user.uid).collect();
+        expected_uids.push(0);
+        
+        self.permission_report_with_uids(expected_uids)
+    }
+    
+    pub fn permission_report_with_uids(&self, expected_uids: Vec<u32>) -> PermissionReport {
+        let mut report = PermissionReport::new(expected_uids);
+        self.walk(&mut report);
+        report
+    }
+    
+    /*
+        Type, digests and ELF analysis with a single read of each file. Files
+        bigger than max_map_size are streamed instead of mapped.
+    */
+    pub fn analyse_files(&self, max_map_size: u64, digests: &[DigestKind]) -> Result<()> {
+        let mut pipeline = FilePipeline::with_digests(digests);
+        pipeline.set_max_map_size(max_map_size);
+        pipeline.run(self)
+    }
+    
+    pub fn analyse_files_type(&self) -> Result<()> {
+        FileTypePass::new().run(self)
+    }
+    
+    pub fn calc_files_hash(&self) -> Result<()> {
+        HashPass::new().run(self)
     }
     
     pub fn list_files(&self) {
-        self.head_node.list_files_rec();
+        for node in self.files() {
+            println!("{}", node);
+        }
+    }
+    
+    /*
+        Get a node from its escaped path in the fs (ex: /usr/lib/libc.so,
+        /tmp/caf\xe9), symlinks are not followed
+    */
+    pub fn get(&self, path: &str) -> Option<Node> {
+        self.get_os(&name::unescape_path(path))
+    }
+    
+    /*
+        Get a node from its raw path in the fs
+    */
+    pub fn get_os(&self, path: &Path) -> Option<Node> {
+        self.index.get(&normalize_path_os(path)).cloned()
+    }
+    
+    /*
+        Get a node from its path, following the symlinks inside the image
+    */
+    pub fn get_follow(&self, path: &str) -> Option<Node> {
+        let (resolved, status) = symlink::resolve(Path::new(&self.path), "/", path);
+        match status {
+            LinkStatus::Resolved | LinkStatus::EscapesRoot => self.get(&resolved?),
+            _ => None,
+        }
+    }
+    
+    /*
+        Childrens of a directory
+    */
+    pub fn list_dir(&self, path: &str) -> Option<Vec<Node>> {
+        let node = self.get(path)?;
+        if node.is_dir() {
+            Some(node.childrens())
+        } else {
+            None
+        }
+    }
+    
+    /*
+        Read the content of a file of the fs. Symlinks are followed inside
+        the image, the local system is never read through a link.
+    */
+    pub fn read_file(&self, path: &str) -> Option<Vec<u8>> {
+        let node = self.get_follow(path)?;
+        if !node.is_file() {
+            return None;
+        }
+        fs::read(node.local_path()).ok()
     }
     
     pub fn list_path(&self, path: &str) {
-            
+        match self.list_dir(path) {
+            Some(mut childrens) => {
+                childrens.sort_by_key(|child| child.name_os());
+                for child in childrens {
+                    println!("{}", child);
+                }
+            }
+            None => {
+                if let Some(node) = self.get(path) {
+                    println!("{}", node);
+                }
+            }
+        }
+    }
+    
+    /*
+        Lazy pre-order iterator over all the nodes of the fs
+    */
+    pub fn iter(&self) -> NodeIter {
+        NodeIter::new(self.head_node.clone())
+    }
+    
+    /*
+        Nodes matching a query (ex: "machine:arm pie:false path~^/usr/sbin/ imports:system")
+    */
+    pub fn query(&self, query: &str) -> Result<impl Iterator<Item = Node>> {
+        Ok(Query::parse(query)?.filter(self.iter()))
+    }
+    
+    pub fn analyse_binaries(&self) -> Result<()> {
+        BinaryPass::new().run(self)
     }
     
-    pub fn analyse_binaries(&self) {
-        self.head_node.analyse_binaries_rec(self.head_node.clone());
+    /*
+        Secrets of the files, read through the file pipeline (see
+        analyse_files to scan them with the other analyses)
+    */
+    pub fn scan_secrets(&self, rules: &SecretRules) -> Vec<SecretFinding> {
+        let scan = SecretScan::new(rules.clone());
+        let consumers: [&dyn FileConsumer; 1] = [&scan];
+        pipeline::run_consumers(self, &consumers, DEFAULT_MAX_MAP_SIZE);
+        scan.findings()
+    }
+    
+    pub fn audit_accounts(&self) -> AccountAudit {
+        AccountAudit::new(self)
+    }
+    
+    pub fn boot_services(&self) -> Vec<BootService> {
+        services::discover(self)
+    }
+    
+    pub fn attack_surface(&self) -> AttackSurface {
+        AttackSurface::new(self)
+    }
+    
+    pub fn analyse_web(&self) -> WebAnalysis {
+        WebAnalysis::new(self)
+    }
+    
+    /*
+        Differences from this tree to other (new, removed, moved and modified
+        nodes). Both trees should have run the same passes to compare the digests.
+    */
+    pub fn compare(&self, other: &FsTree) -> TreeCmpResult {
+        diff::compare(self, other)
+    }
+    
+    pub fn compare_with(&self, other: &FsTree, options: &DiffOptions) -> TreeCmpResult {
+        diff::compare_with(self, other, options)
+    }
+    
+}
+
+/*
+    Normalize a path of the fs: "//", "." and trailing "/" are removed,
+    ".." is applied lexically and never goes above the root
+*/
+pub fn normalize_path(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            _ => components.push(component),
+        }
     }
+    format!("/{}", components.join("/"))
+}
+
+/*
+    Same as normalize_path on a raw path
+*/
+pub fn normalize_path_os(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
     
+    for component in path.components() {
+        match component {
+            Component::Normal(component) => normalized.push(component),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => {}
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::{image, remove, tree};
+    use node::NodeType;
+
+    use std::os::unix::fs::symlink;
+    use std::os::unix::net::UnixListener;
+    use std::process::Command;
+
+    #[test]
+    fn special_nodes() {
+        let root = image("special_nodes", &[("etc/hostname", b"device\n")]);
+        fs::create_dir_all(root.join("dev")).unwrap();
+        assert!(Command::new("mkfifo").arg(root.join("dev/initctl")).status().unwrap().success());
+        let _listener = UnixListener::bind(root.join("dev/log")).unwrap();
+        // Creating a device needs CAP_MKNOD
+        let has_device = Command::new("mknod")
+            .arg(root.join("dev/null"))
+            .args(["c", "1", "3"])
+            .status()
+            .is_ok_and(|status| status.success());
+
+        let fstree = tree(&root);
+        assert_eq!(fstree.count_files(), 1);
+        assert_eq!(fstree.count_special_files(), if has_device { 3 } else { 2 });
+
+        let fifo = fstree.get("/dev/initctl").unwrap();
+        assert!(fifo.is_special() && !fifo.is_file());
+        assert!(matches!(fifo.inner().node_type, NodeType::Fifo));
+        assert!(matches!(fstree.get("/dev/log").unwrap().inner().node_type, NodeType::Socket));
+        if has_device {
+            let null = fstree.get("/dev/null").unwrap();
+            assert!(matches!(null.inner().node_type, NodeType::CharDevice { major: 1, minor: 3 }));
+            assert_eq!(null.metadata().mode_string(), "crw-r--r--");
+        }
+
+        remove(&root);
+    }
+
+    #[test]
+    fn hard_links_share_the_analysis() {
+        let root = image(
+            "hard_links",
+            &[("bin/busybox.sh", b"#!/bin/sh\necho busybox\n"), ("etc/motd", b"hello\n")],
+        );
+        fs::create_dir_all(root.join("sbin")).unwrap();
+        fs::hard_link(root.join("bin/busybox.sh"), root.join("bin/sh")).unwrap();
+        fs::hard_link(root.join("bin/busybox.sh"), root.join("sbin/init")).unwrap();
+
+        let fstree = tree(&root);
+        fstree.calc_files_hash().unwrap();
+        assert_eq!(fstree.count_files(), 4);
+        assert_eq!(fstree.count_unique_files(), 2);
+
+        let groups: Vec<Vec<String>> = fstree
+            .hard_link_groups()
+            .iter()
+            .map(|group| group.iter().map(|node| node.fs_path()).collect())
+            .collect();
+        assert_eq!(groups.len(), 1);
+        let mut group = groups[0].clone();
+        group.sort();
+        assert_eq!(group, vec!["/bin/busybox.sh", "/bin/sh", "/sbin/init"]);
+
+        // The type is detected on the first path seen and copied to the other links,
+        // even if it comes from the extension of one name
+        let busybox = fstree.get("/bin/busybox.sh").unwrap();
+        for path in ["/bin/sh", "/sbin/init"] {
+            let link = fstree.get(path).unwrap();
+            assert_eq!(link.inode_key(), busybox.inode_key());
+            assert_eq!(link.nlink(), 3);
+            assert_eq!(format!("{:?}", link.file_type()), format!("{:?}", busybox.file_type()));
+            assert_eq!(link.hash(), busybox.hash());
+        }
+        assert!(busybox.hash().is_some());
+
+        remove(&root);
+    }
+
+    #[test]
+    fn read_file_follows_links_inside_the_image() {
+        let root = image("read_file", &[("/usr/share/defaults/passwd", b"image:x:0:0::/:/bin/sh\n")]);
+        fs::create_dir_all(root.join("etc")).unwrap();
+        symlink("/usr/share/defaults/passwd", root.join("etc/passwd")).unwrap();
+        symlink("../../../../usr/share/defaults/passwd", root.join("etc/up")).unwrap();
+        symlink("/etc/hostname", root.join("etc/host")).unwrap();
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+
+        assert_eq!(fstree.read_file("/etc/passwd").unwrap(), b"image:x:0:0::/:/bin/sh\n");
+        // ".." stops at the image root
+        assert_eq!(fstree.read_file("/etc/up").unwrap(), b"image:x:0:0::/:/bin/sh\n");
+        // Dangling in the image, even if the host has the target
+        assert!(fstree.read_file("/etc/host").is_none());
+        assert!(fstree.read_file("/usr/share").is_none());
+
+        assert!(fstree.get("/etc/passwd").unwrap().is_symlink());
+        assert_eq!(fstree.get_follow("/etc/passwd").unwrap().fs_path(), "/usr/share/defaults/passwd");
+
+        remove(&root);
+    }
+
+    #[test]
+    fn normalized_lookups() {
+        assert_eq!(normalize_path("//usr/./lib/../bin/"), "/usr/bin");
+        assert_eq!(normalize_path("/../.."), "/");
+        assert_eq!(normalize_path_os(Path::new("/a/../../b")), PathBuf::from("/b"));
+    }
 }
@@ -0,0 +1,278 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+
+const S_ISUID: u32 = 0o4000;
+const S_ISGID: u32 = 0o2000;
+const S_ISVTX: u32 = 0o1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PosixFileType {
+    Regular,
+    Dir,
+    SymLink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+    Unknown,
+}
+
+impl PosixFileType {
+    fn from_metadata(metadata: &fs::Metadata) -> Self {
+        let file_type = metadata.file_type();
+
+        if file_type.is_file() {
+            PosixFileType::Regular
+        } else if file_type.is_dir() {
+            PosixFileType::Dir
+        } else if file_type.is_symlink() {
+            PosixFileType::SymLink
+        } else if file_type.is_char_device() {
+            PosixFileType::CharDevice
+        } else if file_type.is_block_device() {
+            PosixFileType::BlockDevice
+        } else if file_type.is_fifo() {
+            PosixFileType::Fifo
+        } else if file_type.is_socket() {
+            PosixFileType::Socket
+        } else {
+            PosixFileType::Unknown
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PosixFileType::Regular => "file",
+            PosixFileType::Dir => "dir",
+            PosixFileType::SymLink => "symlink",
+            PosixFileType::CharDevice => "chardevice",
+            PosixFileType::BlockDevice => "blockdevice",
+            PosixFileType::Fifo => "fifo",
+            PosixFileType::Socket => "socket",
+            PosixFileType::Unknown => "unknown",
+        }
+    }
+
+    // Character used by ls -l
+    fn symbol(&self) -> char {
+        match self {
+            PosixFileType::Regular => '-',
+            PosixFileType::Dir => 'd',
+            PosixFileType::SymLink => 'l',
+            PosixFileType::CharDevice => 'c',
+            PosixFileType::BlockDevice => 'b',
+            PosixFileType::Fifo => 'p',
+            PosixFileType::Socket => 's',
+            PosixFileType::Unknown => '?',
+        }
+    }
+}
+
+/*
+    lstat data of a node, symlinks are not followed
+*/
+#[derive(Debug, Clone)]
+pub struct NodeMetadata {
+    pub file_type: PosixFileType,
+    // Full st_mode (file type and permission bits)
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    // Seconds and nanoseconds since the epoch
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub ctime: i64,
+    pub ctime_nsec: i64,
+    pub inode: u64,
+    pub dev: u64,
+    // Device number of a character or block device
+    pub rdev: u64,
+    pub nlink: u64,
+}
+
+impl NodeMetadata {
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let metadata = fs::symlink_metadata(path)?;
+
+        Ok(Self {
+            file_type: PosixFileType::from_metadata(&metadata),
+            mode: metadata.mode(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.len(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            ctime: metadata.ctime(),
+            ctime_nsec: metadata.ctime_nsec(),
+            inode: metadata.ino(),
+            dev: metadata.dev(),
+            rdev: metadata.rdev(),
+            nlink: metadata.nlink(),
+        })
+    }
+
+    // Permission bits, including setuid, setgid and sticky
+    pub fn permissions(&self) -> u32 {
+        self.mode & 0o7777
+    }
+
+    pub fn is_suid(&self) -> bool {
+        self.mode & S_ISUID != 0
+    }
+
+    pub fn is_sgid(&self) -> bool {
+        self.mode & S_ISGID != 0
+    }
+
+    pub fn is_sticky(&self) -> bool {
+        self.mode & S_ISVTX != 0
+    }
+
+    pub fn is_world_writable(&self) -> bool {
+        self.mode & 0o002 != 0
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.mode & 0o111 != 0
+    }
+
+    // Major number of a device (Linux encoding of dev_t)
+    pub fn major(&self) -> u32 {
+        (((self.rdev >> 32) & 0xfffff000) | ((self.rdev >> 8) & 0xfff)) as u32
+    }
+
+    // Minor number of a device (Linux encoding of dev_t)
+    pub fn minor(&self) -> u32 {
+        (((self.rdev >> 12) & 0xffffff00) | (self.rdev & 0xff)) as u32
+    }
+
+    /*
+        Mode as displayed by ls -l (ex: -rwsr-xr-x)
+    */
+    pub fn mode_string(&self) -> String {
+        let mut s = String::with_capacity(10);
+        s.push(self.file_type.symbol());
+
+        let special = [(S_ISUID, 's'), (S_ISGID, 's'), (S_ISVTX, 't')];
+
+        for (i, (special_bit, special_char)) in special.iter().enumerate() {
+            let shift = 6 - 3 * i;
+            let bits = (self.mode >> shift) & 0o7;
+
+            s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+            s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+            s.push(match (bits & 0o1 != 0, self.mode & special_bit != 0) {
+                (true, true) => *special_char,
+                (false, true) => special_char.to_ascii_uppercase(),
+                (true, false) => 'x',
+                (false, false) => '-',
+            });
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::{image, remove};
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    fn with_mode(file_type: PosixFileType, mode: u32) -> NodeMetadata {
+        NodeMetadata {
+            file_type,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            ctime: 0,
+            ctime_nsec: 0,
+            inode: 0,
+            dev: 0,
+            rdev: 0,
+            nlink: 1,
+        }
+    }
+
+    #[test]
+    fn mode_strings() {
+        let cases = [
+            (PosixFileType::Regular, 0o100644, "-rw-r--r--"),
+            (PosixFileType::Regular, 0o104755, "-rwsr-xr-x"),
+            (PosixFileType::Regular, 0o104644, "-rwSr--r--"),
+            (PosixFileType::Regular, 0o102755, "-rwxr-sr-x"),
+            (PosixFileType::Dir, 0o041777, "drwxrwxrwt"),
+            (PosixFileType::Dir, 0o041776, "drwxrwxrwT"),
+            (PosixFileType::SymLink, 0o120777, "lrwxrwxrwx"),
+            (PosixFileType::CharDevice, 0o020600, "crw-------"),
+            (PosixFileType::BlockDevice, 0o060660, "brw-rw----"),
+            (PosixFileType::Fifo, 0o010000, "p---------"),
+            (PosixFileType::Socket, 0o140755, "srwxr-xr-x"),
+        ];
+        for (file_type, mode, expected) in cases {
+            assert_eq!(with_mode(file_type, mode).mode_string(), expected);
+        }
+    }
+
+    #[test]
+    fn permission_bits() {
+        let metadata = with_mode(PosixFileType::Regular, 0o106757);
+        assert_eq!(metadata.permissions(), 0o6757);
+        assert!(metadata.is_suid() && metadata.is_sgid());
+        assert!(!metadata.is_sticky());
+        assert!(metadata.is_world_writable() && metadata.is_executable());
+
+        let metadata = with_mode(PosixFileType::Dir, 0o041750);
+        assert!(metadata.is_sticky());
+        assert!(!metadata.is_suid() && !metadata.is_world_writable());
+        assert!(!with_mode(PosixFileType::Regular, 0o100644).is_executable());
+    }
+
+    #[test]
+    fn device_numbers() {
+        let mut metadata = with_mode(PosixFileType::BlockDevice, 0o060660);
+        // makedev(8, 1), /dev/sda1
+        metadata.rdev = 0x801;
+        assert_eq!((metadata.major(), metadata.minor()), (8, 1));
+        // makedev(259, 300000), both numbers overflow the old 8 bit encoding
+        metadata.rdev = (259 << 8) | (0x493 << 20) | 0xe0;
+        assert_eq!((metadata.major(), metadata.minor()), (259, 300000));
+        metadata.rdev = (0x12345 << 44) | (0x678 << 8);
+        assert_eq!(metadata.major(), 0x12345678);
+    }
+
+    #[test]
+    fn lstat_capture() {
+        let root = image("metadata_lstat", &[("bin/tool", b"#!/bin/sh\n")]);
+        let tool = root.join("bin/tool");
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o4755)).unwrap();
+        symlink("tool", root.join("bin/link")).unwrap();
+
+        let metadata = NodeMetadata::from_path(&tool).unwrap();
+        assert_eq!(metadata.file_type, PosixFileType::Regular);
+        assert_eq!(metadata.permissions(), 0o4755);
+        assert_eq!(metadata.mode_string(), "-rwsr-xr-x");
+        assert_eq!(metadata.size, 10);
+        assert_eq!(metadata.nlink, 1);
+        let stat = fs::metadata(&tool).unwrap();
+        assert_eq!((metadata.uid, metadata.gid), (stat.uid(), stat.gid()));
+        assert_eq!((metadata.inode, metadata.mtime), (stat.ino(), stat.mtime()));
+
+        // The link itself is described, not its target
+        let link = NodeMetadata::from_path(&root.join("bin/link")).unwrap();
+        assert_eq!(link.file_type, PosixFileType::SymLink);
+        assert_eq!(link.size, 4);
+        assert_ne!(link.inode, metadata.inode);
+
+        let dir = NodeMetadata::from_path(&root.join("bin")).unwrap();
+        assert_eq!(dir.file_type, PosixFileType::Dir);
+        assert!(NodeMetadata::from_path(&root.join("missing")).is_err());
+
+        remove(&root);
+    }
+}
@@ -1,22 +1,40 @@
 
-use crate::core::file;
+pub mod metadata;
+pub mod name;
+pub mod symlink;
+pub mod xattrs;
+
+use crate::core::error::{Error, ErrorList, Result};
 use crate::core::file::FileType;
+use crate::core::hash::Digests;
+
+use metadata::{NodeMetadata, PosixFileType};
+use symlink::SymLinkData;
+use xattrs::{FileCapabilities, NodeXattrs};
 
-use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::fmt;
+use std::path::{Path, PathBuf};
+use std::os::unix::fs::FileTypeExt;
+
+use rayon::prelude::*;
 
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
 
-type NbDir = u64;
-type NbFile = u64;
+// (device, inode) of a node, shared by all the hard links of a file
+pub type InodeKey = (u64, u64);
 
 #[derive(Debug)]
 pub enum NodeType {
     File(Option<FileType>),
     Dir,
+    SymLink(SymLinkData),
+    CharDevice { major: u32, minor: u32 },
+    BlockDevice { major: u32, minor: u32 },
+    Fifo,
+    Socket,
 }
 
 //pub type Node = Arc<Mutex<FsTreeNode>>;
@@ -25,16 +43,22 @@ pub enum NodeType {
 pub struct NodeInner {
     // Type of node
     pub node_type: NodeType,
-    // Name of the file or directory
-    pub name: String,
+    // Name of the file or directory (raw bytes, see name::escape to display it)
+    pub name: OsString,
+    // Path of the file/dir on the local system (root fs + fs_path)
+    pub local_path: PathBuf,
     // Path of the file/dir in the root fs
-    pub local_path: String,
-    // Path of the file/dir on the system (root fs + local_path)
-    pub fs_path: String,
-    // Hash of the node
-    pub hash: Option<u64>,
+    pub fs_path: PathBuf,
+    // Digests of the content of the file
+    pub digests: Digests,
     // Length of the node
     pub len: u64,
+    // POSIX metadata (lstat) of the node
+    pub metadata: NodeMetadata,
+    // Extended attributes (capabilities, SELinux and IMA labels)
+    pub xattrs: NodeXattrs,
+    // Tags set by the analyses or by the user
+    pub tags: Vec<String>,
     // Childrens of the node
     pub childrens: Arc<RwLock<Vec<Node>>>,
     // Parent of the node
@@ -42,19 +66,22 @@ pub struct NodeInner {
 }
 
 impl NodeInner {
-    pub fn new(name: &str, node_type: NodeType, local_path: &str, fs_path: &str, parent: Option<Node>) -> Self {
-        let metadata = fs::metadata(local_path).unwrap();
+    pub fn new(name: &OsStr, node_type: NodeType, local_path: &Path, fs_path: &Path, parent: Option<Node>) -> Result<Self> {
+        let metadata = NodeMetadata::from_path(local_path).map_err(|err| Error::io(local_path, err))?;
         
-        Self {
+        Ok(Self {
             node_type,
-            name: name.to_string(),
-            local_path: local_path.to_string(),
-            fs_path: fs_path.to_string(),
-            hash: None,
-            len: metadata.len(),
+            name: name.to_os_string(),
+            local_path: local_path.to_path_buf(),
+            fs_path: fs_path.to_path_buf(),
+            digests: Digests::default(),
+            len: metadata.size,
+            metadata,
+            xattrs: NodeXattrs::from_path(local_path),
+            tags: Vec::new(),
             childrens: Arc::new(RwLock::new(Vec::new())),
             parent,
-        }
+        })
     }
 }
 
@@ -64,231 +91,431 @@ pub struct Node {
 }
 
 impl Node {
-    pub fn new_file(root_path: &str, name: &str, local_path: &str, fs_path: &str, parent: Option<Node>) -> Self {
-        let node_inner = NodeInner::new(name, NodeType::File(None), local_path, fs_path, parent);
+    fn from_inner(node_inner: NodeInner) -> Self {
         Self {
             inner: Arc::new(RwLock::new(node_inner)),
         }
     }
     
-    pub fn new_dir(root_path: &str, name: &str, local_path: &str, fs_path: &str, parent: Option<Node>) -> Self {
-        let node_inner = NodeInner::new(name, NodeType::Dir, local_path, fs_path, parent);
+    pub fn new_file(name: &OsStr, local_path: &Path, fs_path: &Path, parent: Option<Node>) -> Result<Self> {
+        let node_inner = NodeInner::new(name, NodeType::File(None), local_path, fs_path, parent)?;
+        Ok(Self::from_inner(node_inner))
+    }
+    
+    pub fn new_symlink(root_path: &Path, name: &OsStr, local_path: &Path, fs_path: &Path, parent: Option<Node>) -> Result<Self> {
+        let symlink_data = SymLinkData::new(root_path, local_path, fs_path)?;
+        let node_inner = NodeInner::new(name, NodeType::SymLink(symlink_data), local_path, fs_path, parent)?;
+        Ok(Self::from_inner(node_inner))
+    }
+    
+    /*
+        Character and block devices, FIFOs and sockets
+    */
+    pub fn new_special(name: &OsStr, local_path: &Path, fs_path: &Path, parent: Option<Node>) -> Result<Self> {
+        let mut node_inner = NodeInner::new(name, NodeType::Fifo, local_path, fs_path, parent)?;
         
-        let node = Self {
-            inner: Arc::new(RwLock::new(node_inner)),
+        let metadata = &node_inner.metadata;
+        node_inner.node_type = match metadata.file_type {
+            PosixFileType::CharDevice => NodeType::CharDevice {
+                major: metadata.major(),
+                minor: metadata.minor(),
+            },
+            PosixFileType::BlockDevice => NodeType::BlockDevice {
+                major: metadata.major(),
+                minor: metadata.minor(),
+            },
+            PosixFileType::Socket => NodeType::Socket,
+            _ => NodeType::Fifo,
         };
         
-        let mut childrens: Vec<Node> = Vec::new();
+        Ok(Self::from_inner(node_inner))
+    }
+    
+    /*
+        Build a directory and its subtree. Only an error on the directory
+        itself is returned, the entries which can't be read are recorded in
+        errors and skipped.
+    */
+    pub fn new_dir(root_path: &Path, name: &OsStr, local_path: &Path, fs_path: &Path, parent: Option<Node>, errors: &ErrorList) -> Result<Self> {
+        let node_inner = NodeInner::new(name, NodeType::Dir, local_path, fs_path, parent)?;
+        let node = Self::from_inner(node_inner);
         
-        for entry in fs::read_dir(local_path).unwrap() {
-            let entry = entry.unwrap();
-            let entry_path = entry.path();
-            let entry_type = entry.file_type().unwrap();
-            
-            let file_name = entry_path.file_name().unwrap().to_str().unwrap();
-            let local_path = entry_path.as_path().to_str().unwrap().to_string();
-            let fs_path = local_path.replace(root_path, "/");
-            
-            if entry_type.is_dir() {
-                let dir_node = Self::new_dir(root_path, file_name, &local_path, &fs_path, Some(node.clone()));
-                childrens.push(dir_node);
+        let read_dir = match fs::read_dir(local_path) {
+            Ok(read_dir) => read_dir,
+            Err(err) => {
+                errors.push(&name::escape_path(fs_path), "build", Error::io(local_path, err));
+                return Ok(node);
             }
-            else if entry_type.is_file() {
-                let file_node = Self::new_file(root_path, file_name, &local_path, &fs_path, Some(node.clone()));
-                childrens.push(file_node);
+        };
+        
+        // Sorted by name so the tree does not depend on the readdir order
+        let mut entries: Vec<fs::DirEntry> = Vec::new();
+        for entry in read_dir {
+            match entry {
+                Ok(entry) => entries.push(entry),
+                Err(err) => errors.push(&name::escape_path(fs_path), "build", Error::io(local_path, err)),
             }
         }
+        entries.sort_by_key(|entry| entry.file_name());
+        
+        // Sub-directories are built in parallel, collect keeps the order of the entries
+        let childrens: Vec<Node> = entries
+            .into_par_iter()
+            .filter_map(|entry| {
+                // Names are kept as raw bytes, they don't have to be valid UTF-8
+                let file_name = entry.file_name();
+                let local_path = entry.path();
+                let fs_path = fs_path.join(&file_name);
+                let escaped_path = name::escape_path(&fs_path);
+                
+                let entry_type = match entry.file_type() {
+                    Ok(entry_type) => entry_type,
+                    Err(err) => {
+                        errors.push(&escaped_path, "build", Error::io(&local_path, err));
+                        return None;
+                    }
+                };
+                
+                let parent = Some(node.clone());
+                let res = if entry_type.is_dir() {
+                    Self::new_dir(root_path, &file_name, &local_path, &fs_path, parent, errors)
+                }
+                else if entry_type.is_file() {
+                    Self::new_file(&file_name, &local_path, &fs_path, parent)
+                }
+                else if entry_type.is_symlink() {
+                    Self::new_symlink(root_path, &file_name, &local_path, &fs_path, parent)
+                }
+                else if entry_type.is_char_device() || entry_type.is_block_device()
+                    || entry_type.is_fifo() || entry_type.is_socket() {
+                    Self::new_special(&file_name, &local_path, &fs_path, parent)
+                }
+                else {
+                    return None;
+                };
+                
+                match res {
+                    Ok(child) => Some(child),
+                    Err(err) => {
+                        // The entry may have been removed or changed while the tree is built
+                        errors.push(&escaped_path, "build", err);
+                        None
+                    }
+                }
+            })
+            .collect();
         
         node.set_childrens(childrens);
-        node
+        Ok(node)
     }
     
-    pub fn inner(&self) -> RwLockReadGuard<NodeInner> {
+    pub fn inner(&self) -> RwLockReadGuard<'_, NodeInner> {
         self.inner.read().unwrap()
     }
     
     pub fn is_dir(&self) -> bool {
         let inner = self.inner.read().unwrap();
-        match inner.node_type {
-            NodeType::Dir => true,
-            _ => false,
-        }
+        matches!(inner.node_type, NodeType::Dir)
     }
     
     pub fn is_file(&self) -> bool {
         let inner = self.inner.read().unwrap();
-        match inner.node_type {
-            NodeType::File(_) => true,
-            _ => false,
+        matches!(inner.node_type, NodeType::File(_))
+    }
+    
+    pub fn is_symlink(&self) -> bool {
+        let inner = self.inner.read().unwrap();
+        matches!(inner.node_type, NodeType::SymLink(_))
+    }
+    
+    pub fn is_special(&self) -> bool {
+        let inner = self.inner.read().unwrap();
+        matches!(
+            inner.node_type,
+            NodeType::CharDevice { .. } | NodeType::BlockDevice { .. } | NodeType::Fifo | NodeType::Socket
+        )
+    }
+    
+    pub fn symlink_data(&self) -> Option<SymLinkData> {
+        let inner = self.inner.read().unwrap();
+        match &inner.node_type {
+            NodeType::SymLink(symlink_data) => Some(symlink_data.clone()),
+            _ => None,
         }
     }
     
     pub fn is_elf(&self) -> bool {
         let inner = self.inner.read().unwrap();
-        match inner.node_type {
-            NodeType::File(Some(FileType::Elf(_))) => true,
-            _ => false,
+        matches!(inner.node_type, NodeType::File(Some(FileType::Elf(_))))
+    }
+    
+    pub fn file_type(&self) -> Option<FileType> {
+        let inner = self.inner.read().unwrap();
+        match &inner.node_type {
+            NodeType::File(file_type) => file_type.clone(),
+            _ => None,
         }
     }
     
-    pub fn len(&self) -> u64 {
+    /*
+        Symbols imported by an analysed ELF
+    */
+    pub fn elf_imports(&self) -> Vec<String> {
         let inner = self.inner.read().unwrap();
-        inner.len
+        match &inner.node_type {
+            NodeType::File(Some(FileType::Elf(Some(elf_data)))) => elf_data.dyn_funcs.clone(),
+            _ => Vec::new(),
+        }
     }
     
-    pub fn local_path(&self) -> String {
+    pub fn elf_machine(&self) -> Option<String> {
         let inner = self.inner.read().unwrap();
-        inner.local_path.clone()
+        match &inner.node_type {
+            NodeType::File(Some(FileType::Elf(Some(elf_data)))) => Some(elf_data.machine.clone()),
+            _ => None,
+        }
     }
     
-    pub fn name(&self) -> String {
+    pub fn elf_is_pie(&self) -> Option<bool> {
         let inner = self.inner.read().unwrap();
-        inner.name.clone()
+        match &inner.node_type {
+            NodeType::File(Some(FileType::Elf(Some(elf_data)))) => Some(elf_data.pie),
+            _ => None,
+        }
     }
     
-    fn set_type(&self, node_type: NodeType) {
-        let mut inner = self.inner.write().unwrap();
-        inner.node_type = node_type;
+    pub fn tags(&self) -> Vec<String> {
+        let inner = self.inner.read().unwrap();
+        inner.tags.clone()
     }
     
-    fn set_hash(&self, hash: u64) {
-        let mut inner = self.inner.write().unwrap();
-        inner.hash = Some(hash);
+    pub fn has_tag(&self, tag: &str) -> bool {
+        let inner = self.inner.read().unwrap();
+        inner.tags.iter().any(|t| t == tag)
     }
     
-    fn set_childrens(&self, childrens: Vec<Node>) -> bool {
+    pub fn add_tag(&self, tag: &str) {
         let mut inner = self.inner.write().unwrap();
-        inner.childrens = Arc::new(RwLock::new(childrens));
-        true
+        if !inner.tags.iter().any(|t| t == tag) {
+            inner.tags.push(tag.to_string());
+        }
     }
     
-    pub fn find_node_by_name_rec(&self, name: &str) -> Vec<Node> {
-        
-        let mut node_list: Vec<Node> = Vec::new();
-        
+    /*
+        Names of the node type used by the queries (ex: ["file", "elf"], ["dir"])
+    */
+    pub fn type_names(&self) -> Vec<String> {
         let inner = self.inner.read().unwrap();
-        let childrens = inner.childrens.read().unwrap();
-        
-        for child in &(*childrens) {
-            if child.is_dir() {
-                let mut nodes = child.find_node_by_name_rec(name);
-                node_list.append(&mut nodes);
-            }
-            else if child.is_file() {
-                if name == child.name() {
-                    node_list.push(child.clone());
+        match &inner.node_type {
+            NodeType::File(file_type) => {
+                let mut names = vec!["file".to_string()];
+                if let Some(file_type) = file_type {
+                    names.push(file_type.name().to_string());
                 }
+                names
             }
+            NodeType::Dir => vec!["dir".to_string()],
+            NodeType::SymLink(_) => vec!["symlink".to_string()],
+            NodeType::CharDevice { .. } => vec!["device".to_string(), "chardevice".to_string()],
+            NodeType::BlockDevice { .. } => vec!["device".to_string(), "blockdevice".to_string()],
+            NodeType::Fifo => vec!["fifo".to_string()],
+            NodeType::Socket => vec!["socket".to_string()],
         }
-        
-        node_list
     }
     
-    pub fn count_dirs_rec(&self) -> u64 {
-        let mut count = 0;
+    pub fn len(&self) -> u64 {
         let inner = self.inner.read().unwrap();
-        
-        let childrens = inner.childrens.read().unwrap();
-        
-        for child in &(*childrens) {
-            if child.is_dir() {
-                count += child.count_dirs_rec();
-                count += 1;
-            }
-        }
-        
-        count
+        inner.len
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
     
-    pub fn count_files_rec(&self) -> u64 {
-        let mut count = 0;
+    pub fn metadata(&self) -> NodeMetadata {
         let inner = self.inner.read().unwrap();
-        
-        let childrens = inner.childrens.read().unwrap();
-        
-        for child in &(*childrens) {
-            if child.is_dir() {
-                count += child.count_files_rec();
-            }
-            else if child.is_file() {
-                count += 1;
-            }
-        }
-        
-        count
+        inner.metadata.clone()
     }
     
-    pub fn analyse_files_type_rec(&self) {
+    pub fn posix_file_type(&self) -> PosixFileType {
         let inner = self.inner.read().unwrap();
-        let childrens = inner.childrens.read().unwrap();
-        
-        for child in &(*childrens) {
-            if child.is_dir() {
-                child.analyse_files_type_rec();
-            }
-            else if child.is_file() {
-                if child.len() <= 50000000 {
-                    let bytes = fs::read(child.local_path()).unwrap();
-                    let file_type = file::check_type(&child.name(), bytes.as_slice());
-                    child.set_type(NodeType::File(Some(file_type)));
-                }
-            }
+        inner.metadata.file_type
+    }
+    
+    pub fn mode(&self) -> u32 {
+        let inner = self.inner.read().unwrap();
+        inner.metadata.mode
+    }
+    
+    pub fn permissions(&self) -> u32 {
+        let inner = self.inner.read().unwrap();
+        inner.metadata.permissions()
+    }
+    
+    pub fn uid(&self) -> u32 {
+        let inner = self.inner.read().unwrap();
+        inner.metadata.uid
+    }
+    
+    pub fn gid(&self) -> u32 {
+        let inner = self.inner.read().unwrap();
+        inner.metadata.gid
+    }
+    
+    pub fn mtime(&self) -> i64 {
+        let inner = self.inner.read().unwrap();
+        inner.metadata.mtime
+    }
+    
+    pub fn ctime(&self) -> i64 {
+        let inner = self.inner.read().unwrap();
+        inner.metadata.ctime
+    }
+    
+    pub fn inode(&self) -> u64 {
+        let inner = self.inner.read().unwrap();
+        inner.metadata.inode
+    }
+    
+    pub fn dev(&self) -> u64 {
+        let inner = self.inner.read().unwrap();
+        inner.metadata.dev
+    }
+    
+    pub fn nlink(&self) -> u64 {
+        let inner = self.inner.read().unwrap();
+        inner.metadata.nlink
+    }
+    
+    pub fn xattrs(&self) -> NodeXattrs {
+        let inner = self.inner.read().unwrap();
+        inner.xattrs.clone()
+    }
+    
+    pub fn capabilities(&self) -> Option<FileCapabilities> {
+        let inner = self.inner.read().unwrap();
+        inner.xattrs.capabilities.clone()
+    }
+    
+    pub fn selinux_label(&self) -> Option<String> {
+        let inner = self.inner.read().unwrap();
+        inner.xattrs.selinux.clone()
+    }
+    
+    pub fn inode_key(&self) -> InodeKey {
+        let inner = self.inner.read().unwrap();
+        (inner.metadata.dev, inner.metadata.inode)
+    }
+    
+    /*
+        Path of the node on the local system
+    */
+    pub fn local_path(&self) -> PathBuf {
+        let inner = self.inner.read().unwrap();
+        inner.local_path.clone()
+    }
+    
+    /*
+        Escaped name, lossless even if the name is not valid UTF-8 (see name::escape)
+    */
+    pub fn name(&self) -> String {
+        let inner = self.inner.read().unwrap();
+        name::escape(&inner.name)
+    }
+    
+    pub fn name_os(&self) -> OsString {
+        let inner = self.inner.read().unwrap();
+        inner.name.clone()
+    }
+    
+    /*
+        Escaped path in the fs (ex: /usr/lib/libc.so)
+    */
+    pub fn fs_path(&self) -> String {
+        let inner = self.inner.read().unwrap();
+        name::escape_path(&inner.fs_path)
+    }
+    
+    pub fn fs_path_os(&self) -> PathBuf {
+        let inner = self.inner.read().unwrap();
+        inner.fs_path.clone()
+    }
+    
+    /*
+        Main cryptographic digest of the file (SHA-256 by default)
+    */
+    pub fn hash(&self) -> Option<String> {
+        let inner = self.inner.read().unwrap();
+        inner.digests.primary().map(|digest| digest.to_string())
+    }
+    
+    pub fn digests(&self) -> Digests {
+        let inner = self.inner.read().unwrap();
+        inner.digests.clone()
+    }
+    
+    pub fn is_text(&self) -> bool {
+        let inner = self.inner.read().unwrap();
+        match &inner.node_type {
+            NodeType::File(Some(file_type)) => file_type.is_text(),
+            _ => false,
         }
     }
     
-    pub fn analyse_binaries_rec(&self, head_node: Node) {
+    pub(crate) fn set_type(&self, node_type: NodeType) {
+        let mut inner = self.inner.write().unwrap();
+        inner.node_type = node_type;
+    }
+    
+    pub(crate) fn set_digests(&self, digests: Digests) {
+        let mut inner = self.inner.write().unwrap();
+        inner.digests = digests;
+    }
+    
+    fn set_childrens(&self, childrens: Vec<Node>) -> bool {
+        let mut inner = self.inner.write().unwrap();
+        inner.childrens = Arc::new(RwLock::new(childrens));
+        true
+    }
+    
+    pub fn parent(&self) -> Option<Node> {
         let inner = self.inner.read().unwrap();
-        let childrens = inner.childrens.read().unwrap();
+        inner.parent.clone()
+    }
+    
+    /*
+        Parent, grand-parent... up to the root node
+    */
+    pub fn ancestors(&self) -> Vec<Node> {
+        let mut ancestors = Vec::new();
+        let mut node = self.parent();
         
-        for child in &(*childrens) {
-            
-            //let node_type = &child.inner().node_type;
-            
-            if child.is_dir() {
-                child.analyse_binaries_rec(head_node.clone());
-            }
-            else if child.is_elf() {
-            //else if let NodeType::File(Some(FileType::Elf(None))) = child.inner().node_type {
-                let elf_data = file::elf::analyse_elf2(head_node.clone(), &child.local_path());
-                child.set_type(NodeType::File(Some(FileType::Elf(Some(elf_data)))));
-            }
+        while let Some(parent) = node {
+            node = parent.parent();
+            ancestors.push(parent);
         }
+        ancestors
     }
     
-    pub fn calc_files_hash_rec(&self) {
+    pub fn childrens(&self) -> Vec<Node> {
         let inner = self.inner.read().unwrap();
         let childrens = inner.childrens.read().unwrap();
-        
-        for child in &(*childrens) {
-            if child.is_dir() {
-                child.calc_files_hash_rec();
-            }
-            else if child.is_file() {
-                if child.len() <= 50000000 {
-                    let bytes = fs::read(child.local_path()).unwrap();
-                    let mut s = DefaultHasher::new();
-                    s.write(&bytes);
-                    let hash = s.finish();
-                    child.set_hash(hash);
-                }
-            }
-        }
+        childrens.clone()
     }
     
-    pub fn list_files_rec(&self) {
+    /*
+        Child with an escaped name
+    */
+    pub fn child_by_name(&self, name: &str) -> Option<Node> {
+        self.child_by_name_os(&name::unescape(name))
+    }
+    
+    pub fn child_by_name_os(&self, name: &OsStr) -> Option<Node> {
         let inner = self.inner.read().unwrap();
         let childrens = inner.childrens.read().unwrap();
         
-        for child in &(*childrens) {
-            if child.is_dir() {
-                child.list_files_rec();
-            }
-            else if child.is_file() {
-                println!("{}", child);
-            }
-        }
+        childrens.iter().find(|child| child.inner().name == name).cloned()
     }
-    
 }
 
 impl fmt::Display for Node {
@@ -297,8 +524,15 @@ impl fmt::Display for Node {
         
         write!(
             f,
-            "{:?} {} - {} {} bytes ({})",
-            inner.node_type, inner.fs_path, inner.name, inner.len, inner.local_path,
+            "{:?} {} {}:{} {} - {} {} bytes ({})",
+            inner.node_type,
+            inner.metadata.mode_string(),
+            inner.metadata.uid,
+            inner.metadata.gid,
+            name::escape_path(&inner.fs_path),
+            name::escape(&inner.name),
+            inner.len,
+            name::escape_path(&inner.local_path),
         )
     }
 }
\ No newline at end of file
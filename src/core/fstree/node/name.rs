@@ -0,0 +1,145 @@
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+/*
+    Lossless text form of the names and paths of a fs. Names are raw bytes
+    on Linux: valid UTF-8 is kept as is, the bytes which are not valid UTF-8
+    and the control characters are written \xNN and "\" is written "\\".
+    Two different names never have the same escaped form, so the escaped
+    strings can be compared, used as keys and converted back with unescape.
+*/
+pub fn escape_bytes(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    let mut bytes = bytes;
+
+    while !bytes.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(bytes) {
+            Ok(valid) => (valid, 0),
+            Err(err) => {
+                let valid = std::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap();
+                (valid, err.error_len().unwrap_or(bytes.len() - err.valid_up_to()))
+            }
+        };
+
+        for c in valid.chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                c if c.is_ascii_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+                c => escaped.push(c),
+            }
+        }
+        for b in &bytes[valid.len()..valid.len() + invalid] {
+            escaped.push_str(&format!("\\x{:02x}", b));
+        }
+        bytes = &bytes[valid.len() + invalid..];
+    }
+    escaped
+}
+
+pub fn escape(name: &OsStr) -> String {
+    escape_bytes(name.as_bytes())
+}
+
+pub fn escape_path(path: &Path) -> String {
+    escape(path.as_os_str())
+}
+
+/*
+    Raw bytes of an escaped name. A "\" which does not start a valid escape
+    sequence is kept, so a path typed by the user is unchanged.
+*/
+pub fn unescape_bytes(escaped: &str) -> Vec<u8> {
+    let bytes = escaped.as_bytes();
+    let mut raw = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\' {
+            if bytes.get(i + 1) == Some(&b'\\') {
+                raw.push(b'\\');
+                i += 2;
+                continue;
+            }
+            if bytes.get(i + 1) == Some(&b'x') {
+                let byte = escaped.get(i + 2..i + 4).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                if let Some(byte) = byte {
+                    raw.push(byte);
+                    i += 4;
+                    continue;
+                }
+            }
+        }
+        raw.push(bytes[i]);
+        i += 1;
+    }
+    raw
+}
+
+pub fn unescape(escaped: &str) -> OsString {
+    OsString::from_vec(unescape_bytes(escaped))
+}
+
+pub fn unescape_path(escaped: &str) -> PathBuf {
+    PathBuf::from(unescape(escaped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::fstree::FsTree;
+    use crate::core::testutil::{image, remove};
+
+    #[test]
+    fn escaped_forms() {
+        assert_eq!(escape_bytes(b"busybox"), "busybox");
+        assert_eq!(escape_bytes("caf\u{e9}".as_bytes()), "caf\u{e9}");
+        assert_eq!(escape_bytes(b"a\\b"), "a\\\\b");
+        assert_eq!(escape_bytes(b"a\nb\x7f"), "a\\x0ab\\x7f");
+        assert_eq!(escape_bytes(b"bad\xff\xfe"), "bad\\xff\\xfe");
+        // Truncated UTF-8 sequence at the end
+        assert_eq!(escape_bytes(b"x\xc3"), "x\\xc3");
+        // A name which looks like an escape sequence
+        assert_eq!(escape_bytes(b"\\x41"), "\\\\x41");
+    }
+
+    #[test]
+    fn round_trip() {
+        let names: [&[u8]; 7] = [b"", b"plain", b"\\x41", b"\\\\", b"\xc3\xa9\xc3", b"a\x00b\x1b[0m", "\u{1f600}\\".as_bytes()];
+        for name in names {
+            assert_eq!(unescape_bytes(&escape_bytes(name)), name);
+        }
+
+        // Every pair of bytes, to cover the invalid and partial UTF-8 sequences
+        for a in 0..=255u8 {
+            for b in 0..=255u8 {
+                let name = [a, b'-', a, b];
+                assert_eq!(unescape_bytes(&escape_bytes(&name)), name);
+            }
+        }
+    }
+
+    #[test]
+    fn typed_paths() {
+        // A "\" not starting an escape sequence is kept
+        assert_eq!(unescape_bytes("a\\b"), b"a\\b");
+        assert_eq!(unescape_bytes("a\\x4"), b"a\\x4");
+        assert_eq!(unescape_bytes("a\\xzz"), b"a\\xzz");
+        assert_eq!(unescape_bytes("\\x41\\x42"), b"AB");
+        assert_eq!(unescape_path("/etc/caf\\xe9"), PathBuf::from(OsStr::from_bytes(b"/etc/caf\xe9")));
+    }
+
+    #[test]
+    fn raw_names_in_a_tree() {
+        let root = image("name_raw", &[]);
+        let raw = OsStr::from_bytes(b"bad\xffname");
+        std::fs::write(root.join(raw), b"").unwrap();
+
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+        let node = fstree.get("/bad\\xffname").unwrap();
+        assert_eq!(node.fs_path(), "/bad\\xffname");
+        assert_eq!(node.name_os(), raw);
+
+        remove(&root);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::core::error::{Error, Result};
+use crate::core::fstree::node::name;
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+
+// Same limit as the Linux kernel (MAXSYMLINKS)
+const MAX_SYMLINKS: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkStatus {
+    // The target exists in the image
+    Resolved,
+    // The target does not exist in the image
+    Dangling,
+    // A relative target climbs above the image root (resolved as a chroot would)
+    EscapesRoot,
+    // Too many levels of symbolic links
+    Loop,
+}
+
+#[derive(Debug, Clone)]
+pub struct SymLinkData {
+    // Content of the link (escaped, see name::escape)
+    pub target: String,
+    // In-image path of the final target (escaped)
+    pub resolved: Option<String>,
+    pub status: LinkStatus,
+}
+
+impl SymLinkData {
+    /*
+        Read and resolve the link at fs_path, the image root is root_path on the local system
+    */
+    pub fn new(root_path: &Path, local_path: &Path, fs_path: &Path) -> Result<Self> {
+        let target = fs::read_link(local_path).map_err(|err| Error::io(local_path, err))?;
+        let target = name::escape_path(&target);
+
+        let parent = name::escape_path(fs_path.parent().unwrap_or(Path::new("/")));
+        let (resolved, status) = resolve(root_path, &parent, &target);
+
+        Ok(Self {
+            target,
+            resolved,
+            status,
+        })
+    }
+}
+
+/*
+    Resolve a path in the image with chroot semantics: absolute links start
+    from the image root and ".." never goes above it. Relative paths start
+    from start_dir (an in-image directory). Paths are escaped, they are
+    unescaped only to look at the local system.
+*/
+pub fn resolve(root: &Path, start_dir: &str, path: &str) -> (Option<String>, LinkStatus) {
+    let mut resolved: Vec<String> = Vec::new();
+    if !path.starts_with('/') {
+        resolved = components(start_dir).into_iter().collect();
+    }
+    let mut remaining: VecDeque<String> = components(path);
+
+    let mut escaped = false;
+    let mut nb_links = 0;
+
+    while let Some(component) = remaining.pop_front() {
+        if component == ".." {
+            if resolved.pop().is_none() {
+                escaped = true;
+            }
+            continue;
+        }
+
+        let mut candidate = resolved.clone();
+        candidate.push(component.clone());
+        let local_path = root.join(name::unescape_path(&candidate.join("/")));
+
+        let metadata = match fs::symlink_metadata(&local_path) {
+            Ok(metadata) => metadata,
+            Err(_) => return (None, LinkStatus::Dangling),
+        };
+
+        if metadata.file_type().is_symlink() {
+            nb_links += 1;
+            if nb_links > MAX_SYMLINKS {
+                return (None, LinkStatus::Loop);
+            }
+
+            let target = match fs::read_link(&local_path) {
+                Ok(target) => name::escape_path(&target),
+                Err(_) => return (None, LinkStatus::Dangling),
+            };
+            if target.starts_with('/') {
+                resolved.clear();
+            }
+            for c in components(&target).into_iter().rev() {
+                remaining.push_front(c);
+            }
+        } else {
+            // Only the last component can be something else than a directory
+            if !remaining.is_empty() && !metadata.is_dir() {
+                return (None, LinkStatus::Dangling);
+            }
+            resolved.push(component);
+        }
+    }
+
+    let status = if escaped { LinkStatus::EscapesRoot } else { LinkStatus::Resolved };
+    (Some(format!("/{}", resolved.join("/"))), status)
+}
+
+fn components(path: &str) -> VecDeque<String> {
+    path.split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .map(|c| c.to_string())
+        .collect()
+}
@@ -0,0 +1,302 @@
+use std::path::Path;
+
+const VFS_CAP_REVISION_MASK: u32 = 0xff000000;
+const VFS_CAP_FLAGS_EFFECTIVE: u32 = 0x000001;
+const VFS_CAP_REVISION_1: u32 = 0x01000000;
+const VFS_CAP_REVISION_2: u32 = 0x02000000;
+const VFS_CAP_REVISION_3: u32 = 0x03000000;
+
+// Capability names, indexed by capability number (linux/capability.h)
+const CAPABILITY_NAMES: [&str; 41] = [
+    "cap_chown",
+    "cap_dac_override",
+    "cap_dac_read_search",
+    "cap_fowner",
+    "cap_fsetid",
+    "cap_kill",
+    "cap_setgid",
+    "cap_setuid",
+    "cap_setpcap",
+    "cap_linux_immutable",
+    "cap_net_bind_service",
+    "cap_net_broadcast",
+    "cap_net_admin",
+    "cap_net_raw",
+    "cap_ipc_lock",
+    "cap_ipc_owner",
+    "cap_sys_module",
+    "cap_sys_rawio",
+    "cap_sys_chroot",
+    "cap_sys_ptrace",
+    "cap_sys_pacct",
+    "cap_sys_admin",
+    "cap_sys_boot",
+    "cap_sys_nice",
+    "cap_sys_resource",
+    "cap_sys_time",
+    "cap_sys_tty_config",
+    "cap_mknod",
+    "cap_lease",
+    "cap_audit_write",
+    "cap_audit_control",
+    "cap_setfcap",
+    "cap_mac_override",
+    "cap_mac_admin",
+    "cap_syslog",
+    "cap_wake_alarm",
+    "cap_block_suspend",
+    "cap_audit_read",
+    "cap_perfmon",
+    "cap_bpf",
+    "cap_checkpoint_restore",
+];
+
+// Hash algorithms used by IMA (linux/hash_info.h)
+const IMA_HASH_ALGOS: [&str; 20] = [
+    "md4", "md5", "sha1", "rmd160", "sha256", "sha384", "sha512", "sha224", "rmd128", "rmd256", "rmd320", "wp256",
+    "wp384", "wp512", "tgr128", "tgr160", "tgr192", "sm3-256", "streebog256", "streebog512",
+];
+
+/*
+    File capabilities decoded from security.capability (struct vfs_cap_data)
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileCapabilities {
+    // Revision of vfs_cap_data (1, 2 or 3)
+    pub version: u8,
+    pub effective: bool,
+    pub permitted: u64,
+    pub inheritable: u64,
+    // Root UID of the user namespace (version 3 only)
+    pub rootid: Option<u32>,
+}
+
+impl FileCapabilities {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let word = |i: usize| -> Option<u32> {
+            let b = bytes.get(i * 4..i * 4 + 4)?;
+            Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+
+        let magic_etc = word(0)?;
+        let effective = magic_etc & VFS_CAP_FLAGS_EFFECTIVE != 0;
+
+        match magic_etc & VFS_CAP_REVISION_MASK {
+            VFS_CAP_REVISION_1 => Some(Self {
+                version: 1,
+                effective,
+                permitted: word(1)? as u64,
+                inheritable: word(2)? as u64,
+                rootid: None,
+            }),
+            revision @ (VFS_CAP_REVISION_2 | VFS_CAP_REVISION_3) => Some(Self {
+                version: if revision == VFS_CAP_REVISION_2 { 2 } else { 3 },
+                effective,
+                permitted: word(1)? as u64 | (word(3)? as u64) << 32,
+                inheritable: word(2)? as u64 | (word(4)? as u64) << 32,
+                rootid: if revision == VFS_CAP_REVISION_3 { Some(word(5)?) } else { None },
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn permitted_names(&self) -> Vec<&'static str> {
+        names(self.permitted)
+    }
+
+    pub fn inheritable_names(&self) -> Vec<&'static str> {
+        names(self.inheritable)
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.permitted_names().contains(&name)
+    }
+}
+
+fn names(set: u64) -> Vec<&'static str> {
+    CAPABILITY_NAMES
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| set & (1 << i) != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImaKind {
+    // IMA_XATTR_DIGEST (SHA-1)
+    Digest,
+    // EVM_XATTR_HMAC
+    Hmac,
+    // EVM_IMA_XATTR_DIGSIG
+    Signature,
+    // IMA_XATTR_DIGEST_NG (digest with its algorithm)
+    DigestNg,
+    // EVM_XATTR_PORTABLE_DIGSIG
+    PortableSignature,
+    // IMA_VERITY_DIGSIG
+    VeritySignature,
+    Unknown(u8),
+}
+
+/*
+    Content of security.ima
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImaLabel {
+    pub kind: ImaKind,
+    pub algo: Option<&'static str>,
+    // Digest or signature
+    pub data: Vec<u8>,
+}
+
+impl ImaLabel {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (kind_byte, rest) = bytes.split_first()?;
+        let algo_name = |id: &u8| IMA_HASH_ALGOS.get(*id as usize).copied();
+
+        let label = match kind_byte {
+            1 => Self {
+                kind: ImaKind::Digest,
+                algo: Some("sha1"),
+                data: rest.to_vec(),
+            },
+            2 => Self {
+                kind: ImaKind::Hmac,
+                algo: Some("sha1"),
+                data: rest.to_vec(),
+            },
+            4 => Self {
+                kind: ImaKind::DigestNg,
+                algo: rest.first().and_then(algo_name),
+                data: rest.get(1..).unwrap_or(&[]).to_vec(),
+            },
+            // Signature v2 header: version, hash algo, key id (4), signature size (2)
+            3 | 5 | 6 => Self {
+                kind: match kind_byte {
+                    3 => ImaKind::Signature,
+                    5 => ImaKind::PortableSignature,
+                    _ => ImaKind::VeritySignature,
+                },
+                algo: rest.get(1).and_then(algo_name),
+                data: rest.get(8..).unwrap_or(&[]).to_vec(),
+            },
+            other => Self {
+                kind: ImaKind::Unknown(*other),
+                algo: None,
+                data: rest.to_vec(),
+            },
+        };
+        Some(label)
+    }
+}
+
+/*
+    Extended attributes of a node, read without following symlinks
+*/
+#[derive(Debug, Clone, Default)]
+pub struct NodeXattrs {
+    // All the attributes (name, value)
+    pub raw: Vec<(String, Vec<u8>)>,
+    pub capabilities: Option<FileCapabilities>,
+    pub selinux: Option<String>,
+    pub ima: Option<ImaLabel>,
+}
+
+impl NodeXattrs {
+    /*
+        Filesystems without xattr support give an empty set
+    */
+    pub fn from_path(path: &Path) -> Self {
+        let mut xattrs = Self::default();
+
+        let names = match ::xattr::list(path) {
+            Ok(names) => names,
+            Err(_) => return xattrs,
+        };
+
+        for name in names {
+            let value = match ::xattr::get(path, &name) {
+                Ok(Some(value)) => value,
+                _ => continue,
+            };
+            let name = name.to_string_lossy().to_string();
+
+            match name.as_str() {
+                "security.capability" => xattrs.capabilities = FileCapabilities::parse(&value),
+                "security.selinux" => {
+                    let label = String::from_utf8_lossy(&value);
+                    xattrs.selinux = Some(label.trim_end_matches('\0').to_string());
+                }
+                "security.ima" => xattrs.ima = ImaLabel::parse(&value),
+                _ => {}
+            }
+            xattrs.raw.push((name, value));
+        }
+        xattrs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn capabilities() {
+        // Values written by setcap
+        let caps = FileCapabilities::parse(&hex("0100000200300000000000000000000000000000")).unwrap();
+        assert_eq!((caps.version, caps.effective, caps.rootid), (2, true, None));
+        assert_eq!(caps.permitted_names(), vec!["cap_net_admin", "cap_net_raw"]);
+        assert!(caps.inheritable_names().is_empty());
+        assert!(caps.has("cap_net_raw") && !caps.has("cap_sys_admin"));
+
+        // cap_sys_admin,cap_bpf+ip, cap_bpf (39) is in the second word
+        let caps = FileCapabilities::parse(&hex("0000000200002000000020008000000080000000")).unwrap();
+        assert!(!caps.effective);
+        assert_eq!(caps.permitted, 1 << 21 | 1 << 39);
+        assert_eq!(caps.inheritable_names(), vec!["cap_sys_admin", "cap_bpf"]);
+
+        // setcap -n 1000 cap_net_bind_service+ep
+        let caps = FileCapabilities::parse(&hex("0100000300040000000000000000000000000000e8030000")).unwrap();
+        assert_eq!((caps.version, caps.rootid), (3, Some(1000)));
+        assert_eq!(caps.permitted_names(), vec!["cap_net_bind_service"]);
+
+        // The 32 bit revision 1 has no upper words
+        let caps = FileCapabilities::parse(&hex("010000010010000000000000")).unwrap();
+        assert_eq!((caps.version, caps.effective), (1, true));
+        assert_eq!(caps.permitted_names(), vec!["cap_net_admin"]);
+
+        assert!(FileCapabilities::parse(&hex("0100000200300000")).is_none());
+        assert!(FileCapabilities::parse(&hex("0100000400300000000000000000000000000000")).is_none());
+        assert!(FileCapabilities::parse(&[]).is_none());
+    }
+
+    #[test]
+    fn ima_labels() {
+        let label = ImaLabel::parse(&hex("01da39a3ee5e6b4b0d3255bfef95601890afd80709")).unwrap();
+        assert_eq!((label.kind, label.algo), (ImaKind::Digest, Some("sha1")));
+        assert_eq!(label.data, hex("da39a3ee5e6b4b0d3255bfef95601890afd80709"));
+
+        let sha256 = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let label = ImaLabel::parse(&hex(&format!("0404{}", sha256))).unwrap();
+        assert_eq!((label.kind, label.algo), (ImaKind::DigestNg, Some("sha256")));
+        assert_eq!(label.data, hex(sha256));
+
+        // evmctl ima_sign: signature v2, sha256, key id, big endian size and signature
+        let label = ImaLabel::parse(&hex("030204a1b2c3d40004deadbeef")).unwrap();
+        assert_eq!((label.kind, label.algo), (ImaKind::Signature, Some("sha256")));
+        assert_eq!(label.data, hex("deadbeef"));
+        assert_eq!(ImaLabel::parse(&hex("0502")).unwrap().kind, ImaKind::PortableSignature);
+        assert_eq!(ImaLabel::parse(&hex("0602")).unwrap().kind, ImaKind::VeritySignature);
+
+        let label = ImaLabel::parse(&hex("0240")).unwrap();
+        assert_eq!(label.kind, ImaKind::Hmac);
+        assert_eq!(ImaLabel::parse(&hex("09")).unwrap().kind, ImaKind::Unknown(9));
+        assert_eq!(ImaLabel::parse(&hex("04ff")).unwrap().algo, None);
+        assert!(ImaLabel::parse(&[]).is_none());
+    }
+}
@@ -0,0 +1,483 @@
+use crate::core::error::{Error, Result};
+use crate::core::file;
+use crate::core::file::FileType;
+use crate::core::fstree::node::{Node, NodeType};
+use crate::core::fstree::pipeline::{run_consumers, Content, FileConsumer, FilePipeline, FileSink, DEFAULT_MAX_MAP_SIZE};
+use crate::core::fstree::FsTree;
+
+use crate::core::hash::{self, DigestKind, MultiHasher, DEFAULT_DIGESTS};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+
+use log::debug;
+
+use memmap2::Mmap;
+use rayon::prelude::*;
+
+#[derive(Debug)]
+pub enum PassError {
+    // Two passes registered with the same name
+    DuplicateName(String),
+    UnknownDependency { pass: String, dependency: String },
+    // Passes depending on each other
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for PassError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PassError::DuplicateName(name) => write!(f, "pass {} registered twice", name),
+            PassError::UnknownDependency { pass, dependency } => {
+                write!(f, "pass {} depends on unknown pass {}", pass, dependency)
+            }
+            PassError::Cycle(names) => write!(f, "dependency cycle between passes {}", names.join(", ")),
+        }
+    }
+}
+
+/*
+    An analysis run over a FsTree. Passes declare the passes they need by
+    name, the registry runs them first. Most passes are visitors and only
+    have to call fstree.walk(self) in run.
+*/
+pub trait Pass {
+    fn name(&self) -> &'static str;
+
+    // Names satisfying the dependencies of other passes
+    fn provides(&self) -> Vec<&'static str> {
+        vec![self.name()]
+    }
+
+    fn dependencies(&self) -> &[&'static str] {
+        &[]
+    }
+
+    /*
+        Failures on single nodes are recorded on the tree, an error stops the
+        passes depending on this one
+    */
+    fn run(&mut self, fstree: &FsTree) -> Result<()>;
+}
+
+pub struct PassRegistry {
+    passes: Vec<Box<dyn Pass>>,
+    // Names of the passes already run
+    done: Vec<&'static str>,
+}
+
+impl PassRegistry {
+    pub fn new() -> Self {
+        Self {
+            passes: Vec::new(),
+            done: Vec::new(),
+        }
+    }
+
+    /*
+        Registry with the passes of the crate: file types, hashes and ELF
+        analysis in a single pipeline
+    */
+    pub fn with_default_passes() -> Self {
+        Self::with_options(DEFAULT_MAX_MAP_SIZE, &DEFAULT_DIGESTS)
+    }
+
+    pub fn with_options(max_map_size: u64, digests: &[DigestKind]) -> Self {
+        let mut pipeline = FilePipeline::with_digests(digests);
+        pipeline.set_max_map_size(max_map_size);
+
+        let mut registry = Self::new();
+        registry.register(pipeline);
+        registry
+    }
+
+    pub fn register<P: Pass + 'static>(&mut self, pass: P) -> &mut Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    pub fn is_done(&self, name: &str) -> bool {
+        self.done.contains(&name)
+    }
+
+    /*
+        Indexes of the passes, each pass after its dependencies. Registration
+        order is kept between independent passes.
+    */
+    pub fn order(&self) -> std::result::Result<Vec<usize>, PassError> {
+        let mut by_name: HashMap<&str, usize> = HashMap::new();
+        for (i, pass) in self.passes.iter().enumerate() {
+            for name in pass.provides() {
+                if by_name.insert(name, i).is_some() {
+                    return Err(PassError::DuplicateName(name.to_string()));
+                }
+            }
+        }
+
+        for pass in &self.passes {
+            for dependency in pass.dependencies() {
+                if !by_name.contains_key(dependency) {
+                    return Err(PassError::UnknownDependency {
+                        pass: pass.name().to_string(),
+                        dependency: dependency.to_string(),
+                    });
+                }
+            }
+        }
+
+        let mut order = Vec::new();
+        let mut placed = vec![false; self.passes.len()];
+        while order.len() < self.passes.len() {
+            let ready = (0..self.passes.len()).find(|&i| {
+                !placed[i] && self.passes[i].dependencies().iter().all(|dependency| placed[by_name[dependency]])
+            });
+
+            match ready {
+                Some(i) => {
+                    placed[i] = true;
+                    order.push(i);
+                }
+                None => {
+                    let names = (0..self.passes.len())
+                        .filter(|&i| !placed[i])
+                        .map(|i| self.passes[i].name().to_string())
+                        .collect();
+                    return Err(PassError::Cycle(names));
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    /*
+        Run all the passes not run yet, in dependency order
+    */
+    pub fn run(&mut self, fstree: &FsTree) -> Result<()> {
+        for i in self.order()? {
+            let name = self.passes[i].name();
+            if self.done.contains(&name) {
+                continue;
+            }
+            debug!("Running pass {}", name);
+            self.passes[i].run(fstree)?;
+            self.done.push(name);
+        }
+        Ok(())
+    }
+}
+
+impl Default for PassRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/*
+    Detect the type of the files
+*/
+pub struct FileTypePass {
+    // Files up to this size are mapped when the pass is run alone
+    max_map_size: u64,
+}
+
+impl FileTypePass {
+    pub fn new() -> Self {
+        Self {
+            max_map_size: DEFAULT_MAX_MAP_SIZE,
+        }
+    }
+}
+
+impl Default for FileTypePass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileConsumer for FileTypePass {
+    fn name(&self) -> &'static str {
+        "file-type"
+    }
+
+    /*
+        The type of big files is detected from their first chunk
+    */
+    fn consume(&self, node: &Node, content: Content) -> Result<Option<Box<dyn FileSink>>> {
+        let file_type = file::check_type(&node.name(), content.bytes());
+        node.set_type(NodeType::File(Some(file_type)));
+        Ok(None)
+    }
+
+    fn copy(&self, source: &Node, node: &Node) {
+        node.set_type(NodeType::File(source.file_type()));
+    }
+}
+
+/*
+    Digests of the content of the files, big files are hashed while they are streamed
+*/
+pub struct HashPass {
+    max_map_size: u64,
+    digests: Vec<DigestKind>,
+}
+
+impl HashPass {
+    pub fn new() -> Self {
+        Self::with_digests(&DEFAULT_DIGESTS)
+    }
+
+    pub fn with_digests(digests: &[DigestKind]) -> Self {
+        Self {
+            max_map_size: DEFAULT_MAX_MAP_SIZE,
+            digests: digests.to_vec(),
+        }
+    }
+}
+
+impl Default for HashPass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct HashSink {
+    hasher: MultiHasher,
+}
+
+impl FileSink for HashSink {
+    fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+    }
+
+    fn finish(self: Box<Self>, node: &Node) {
+        node.set_digests(self.hasher.finish());
+    }
+}
+
+impl FileConsumer for HashPass {
+    fn name(&self) -> &'static str {
+        "hash"
+    }
+
+    fn consume(&self, node: &Node, content: Content) -> Result<Option<Box<dyn FileSink>>> {
+        match content {
+            Content::Whole(bytes) => {
+                node.set_digests(hash::digest_bytes(&self.digests, bytes));
+                Ok(None)
+            }
+            Content::Head(bytes) => {
+                let mut hasher = MultiHasher::new(&self.digests, node.len());
+                hasher.update(bytes);
+                Ok(Some(Box::new(HashSink { hasher })))
+            }
+        }
+    }
+
+    fn copy(&self, source: &Node, node: &Node) {
+        node.set_digests(source.digests());
+    }
+}
+
+/*
+    Parse the ELF files (imports, dynamic libraries, machine...)
+*/
+pub struct BinaryPass {
+    max_map_size: u64,
+}
+
+impl BinaryPass {
+    pub fn new() -> Self {
+        Self {
+            max_map_size: DEFAULT_MAX_MAP_SIZE,
+        }
+    }
+}
+
+impl Default for BinaryPass {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileConsumer for BinaryPass {
+    fn name(&self) -> &'static str {
+        "binaries"
+    }
+
+    fn dependencies(&self) -> &[&'static str] {
+        &["file-type"]
+    }
+
+    fn consume(&self, node: &Node, content: Content) -> Result<Option<Box<dyn FileSink>>> {
+        if !node.is_elf() {
+            return Ok(None);
+        }
+        // A big ELF is mapped anyway, the mapping does not copy the file
+        let mmap;
+        let bytes = match content {
+            Content::Whole(bytes) => bytes,
+            Content::Head(_) => {
+                let local_path = node.local_path();
+                let file = File::open(&local_path).map_err(|err| Error::io(&local_path, err))?;
+                mmap = unsafe { Mmap::map(&file) }.map_err(|err| Error::io(&local_path, err))?;
+                &mmap[..]
+            }
+        };
+
+        let elf_data = file::elf::analyse_elf2(bytes).map_err(|msg| Error::elf(&node.fs_path(), msg))?;
+        node.set_type(NodeType::File(Some(FileType::Elf(Some(elf_data)))));
+        Ok(None)
+    }
+
+    fn copy(&self, source: &Node, node: &Node) {
+        node.set_type(NodeType::File(source.file_type()));
+    }
+
+    /*
+        Libraries are looked up by path in the search directories of each
+        ELF, hard links are resolved from their own directory ($ORIGIN)
+    */
+    fn after_files(&self, fstree: &FsTree) {
+        let lib_dirs = file::elf::library_dirs(fstree);
+        let elf_files = fstree.elf_files();
+
+        fstree.install(|| {
+            elf_files.par_iter().for_each(|node| {
+                if let Some(FileType::Elf(Some(mut elf_data))) = node.file_type() {
+                    elf_data.dyn_libs = file::elf::resolve_libraries(fstree, &node.fs_path(), &elf_data, &lib_dirs);
+                    node.set_type(NodeType::File(Some(FileType::Elf(Some(elf_data)))));
+                }
+            })
+        });
+    }
+}
+
+/*
+    A file consumer run alone is a pass reading the files for itself, the
+    files up to its max_map_size are mapped
+*/
+macro_rules! consumer_pass {
+    ($consumer:ty) => {
+        impl $consumer {
+            pub fn set_max_map_size(&mut self, max_map_size: u64) -> &mut Self {
+                self.max_map_size = max_map_size;
+                self
+            }
+        }
+
+        impl Pass for $consumer {
+            fn name(&self) -> &'static str {
+                FileConsumer::name(self)
+            }
+
+            fn dependencies(&self) -> &[&'static str] {
+                FileConsumer::dependencies(self)
+            }
+
+            fn run(&mut self, fstree: &FsTree) -> Result<()> {
+                let max_map_size = self.max_map_size;
+                let consumers: [&dyn FileConsumer; 1] = [&*self];
+                run_consumers(fstree, &consumers, max_map_size);
+                Ok(())
+            }
+        }
+    };
+}
+
+consumer_pass!(FileTypePass);
+consumer_pass!(HashPass);
+consumer_pass!(BinaryPass);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::{image, remove};
+
+    use std::sync::{Arc, Mutex};
+
+    struct TestPass {
+        name: &'static str,
+        dependencies: Vec<&'static str>,
+        log: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Pass for TestPass {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn dependencies(&self) -> &[&'static str] {
+            &self.dependencies
+        }
+
+        fn run(&mut self, _fstree: &FsTree) -> Result<()> {
+            self.log.lock().unwrap().push(self.name);
+            Ok(())
+        }
+    }
+
+    // Passes given as (name, space separated dependencies)
+    fn test_registry(passes: &[(&'static str, &'static str)]) -> (PassRegistry, Arc<Mutex<Vec<&'static str>>>) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut registry = PassRegistry::new();
+        for (name, dependencies) in passes {
+            registry.register(TestPass {
+                name,
+                dependencies: dependencies.split_whitespace().collect(),
+                log: log.clone(),
+            });
+        }
+        (registry, log)
+    }
+
+    #[test]
+    fn dependency_order() {
+        let (mut registry, log) =
+            test_registry(&[("report", "hash types"), ("hash", "types"), ("types", ""), ("other", "")]);
+        assert_eq!(registry.order().unwrap(), vec![2, 1, 0, 3]);
+
+        let root = image("pass_order", &[("file", b"x\n")]);
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+        registry.run(&fstree).unwrap();
+        assert_eq!(*log.lock().unwrap(), vec!["types", "hash", "report", "other"]);
+        assert!(registry.is_done("report"));
+
+        // The passes already run are skipped
+        registry.register(TestPass {
+            name: "late",
+            dependencies: vec!["report"],
+            log: log.clone(),
+        });
+        registry.run(&fstree).unwrap();
+        assert_eq!(*log.lock().unwrap(), vec!["types", "hash", "report", "other", "late"]);
+
+        let default = PassRegistry::with_default_passes();
+        assert_eq!(default.names(), vec!["files"]);
+        assert_eq!(default.order().unwrap(), vec![0]);
+
+        remove(&root);
+    }
+
+    #[test]
+    fn order_errors() {
+        let (registry, _) = test_registry(&[("types", ""), ("types", "")]);
+        assert!(matches!(registry.order(), Err(PassError::DuplicateName(name)) if name == "types"));
+
+        let (registry, _) = test_registry(&[("hash", "types")]);
+        assert!(matches!(
+            registry.order(),
+            Err(PassError::UnknownDependency { pass, dependency }) if pass == "hash" && dependency == "types"
+        ));
+
+        let (registry, _) = test_registry(&[("a", "c"), ("free", ""), ("b", "a"), ("c", "b")]);
+        match registry.order() {
+            Err(error @ PassError::Cycle(_)) => assert_eq!(error.to_string(), "dependency cycle between passes a, b, c"),
+            other => panic!("unexpected order {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,462 @@
+use crate::core::error::{Error, Result};
+use crate::core::fstree::node::{InodeKey, Node};
+use crate::core::fstree::pass::{BinaryPass, FileTypePass, HashPass, Pass};
+use crate::core::fstree::FsTree;
+use crate::core::hash::{DigestKind, DEFAULT_DIGESTS};
+
+use memmap2::Mmap;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+
+use rayon::prelude::*;
+
+// Files up to this size are mapped, bigger ones are streamed
+pub const DEFAULT_MAX_MAP_SIZE: u64 = 50000000;
+
+// Size of the chunks read from streamed files
+pub const CHUNK_SIZE: usize = 1 << 20;
+
+pub enum Content<'a> {
+    // Whole content of the file
+    Whole(&'a [u8]),
+    // First chunk of a file bigger than the limit, the rest is streamed to the sinks
+    Head(&'a [u8]),
+}
+
+impl<'a> Content<'a> {
+    pub fn bytes(&self) -> &'a [u8] {
+        match self {
+            Content::Whole(bytes) | Content::Head(bytes) => bytes,
+        }
+    }
+
+    pub fn is_whole(&self) -> bool {
+        matches!(self, Content::Whole(_))
+    }
+}
+
+/*
+    Streaming state of a consumer for one file
+*/
+pub trait FileSink {
+    fn update(&mut self, chunk: &[u8]);
+
+    fn finish(self: Box<Self>, node: &Node);
+}
+
+/*
+    Analysis fed with the content of each regular file. For a file, the
+    consumers of a pipeline are called after the consumers they depend on,
+    so a consumer can use their results (ex: the file type).
+*/
+pub trait FileConsumer: Sync {
+    fn name(&self) -> &'static str;
+
+    fn dependencies(&self) -> &[&'static str] {
+        &[]
+    }
+
+    /*
+        Called once per file. For a file bigger than the limit, the consumer
+        returns a sink to get the rest of the file. An error is recorded on
+        the tree and the other consumers still get the file.
+    */
+    fn consume(&self, node: &Node, content: Content) -> Result<Option<Box<dyn FileSink>>>;
+
+    // Copy the result of the first hard link to the other ones
+    fn copy(&self, source: &Node, node: &Node);
+
+    /*
+        Called once all the files were consumed, to link the results of
+        several files (ex: an ELF and its libraries)
+    */
+    fn after_files(&self, _fstree: &FsTree) {}
+}
+
+/*
+    Runs the consumers over the files of a tree, each file is opened once and
+    memory-mapped (or streamed if bigger than max_map_size)
+*/
+pub struct FilePipeline {
+    consumers: Vec<Box<dyn FileConsumer>>,
+    max_map_size: u64,
+    // Dependencies of the consumers not provided by the pipeline
+    dependencies: Vec<&'static str>,
+}
+
+impl FilePipeline {
+    pub fn new() -> Self {
+        Self {
+            consumers: Vec::new(),
+            max_map_size: DEFAULT_MAX_MAP_SIZE,
+            dependencies: Vec::new(),
+        }
+    }
+
+    /*
+        File types, hashes and ELF analysis
+    */
+    pub fn with_default_consumers() -> Self {
+        Self::with_digests(&DEFAULT_DIGESTS)
+    }
+
+    pub fn with_digests(digests: &[DigestKind]) -> Self {
+        let mut pipeline = Self::new();
+        pipeline.add(FileTypePass::new());
+        pipeline.add(HashPass::with_digests(digests));
+        pipeline.add(BinaryPass::new());
+        pipeline
+    }
+
+    pub fn add<C: FileConsumer + 'static>(&mut self, consumer: C) -> &mut Self {
+        self.consumers.push(Box::new(consumer));
+        self.sort_consumers();
+        self
+    }
+
+    /*
+        Put each consumer after the consumers it depends on, the order of
+        addition is kept otherwise. Consumers depending on each other are
+        left in that order.
+    */
+    fn sort_consumers(&mut self) {
+        let names: Vec<&'static str> = self.consumers.iter().map(|consumer| consumer.name()).collect();
+        let mut remaining = std::mem::take(&mut self.consumers);
+        let mut placed: Vec<&'static str> = Vec::new();
+
+        while !remaining.is_empty() {
+            let ready = remaining.iter().position(|consumer| {
+                consumer
+                    .dependencies()
+                    .iter()
+                    .all(|dependency| placed.contains(dependency) || !names.contains(dependency))
+            });
+            let consumer = remaining.remove(ready.unwrap_or(0));
+            placed.push(consumer.name());
+            self.consumers.push(consumer);
+        }
+
+        self.dependencies.clear();
+        for consumer in &self.consumers {
+            for dependency in consumer.dependencies() {
+                if !names.contains(dependency) && !self.dependencies.contains(dependency) {
+                    self.dependencies.push(dependency);
+                }
+            }
+        }
+    }
+
+    pub fn set_max_map_size(&mut self, max_map_size: u64) -> &mut Self {
+        self.max_map_size = max_map_size;
+        self
+    }
+
+    pub fn max_map_size(&self) -> u64 {
+        self.max_map_size
+    }
+}
+
+impl Default for FilePipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pass for FilePipeline {
+    fn name(&self) -> &'static str {
+        "files"
+    }
+
+    fn provides(&self) -> Vec<&'static str> {
+        let mut names = vec![self.name()];
+        names.extend(self.consumers.iter().map(|consumer| consumer.name()));
+        names
+    }
+
+    fn dependencies(&self) -> &[&'static str] {
+        &self.dependencies
+    }
+
+    fn run(&mut self, fstree: &FsTree) -> Result<()> {
+        let consumers: Vec<&dyn FileConsumer> = self.consumers.iter().map(|consumer| consumer.as_ref()).collect();
+        run_consumers(fstree, &consumers, self.max_map_size);
+        Ok(())
+    }
+}
+
+/*
+    Feed the files of the tree to the consumers. Files are processed in
+    parallel, hard links are read once and the results of the first link (in
+    tree order) are copied to the other ones.
+*/
+pub fn run_consumers(fstree: &FsTree, consumers: &[&dyn FileConsumer], max_map_size: u64) {
+    let mut sources: HashMap<InodeKey, Node> = HashMap::new();
+    let mut unique = Vec::new();
+    let mut links = Vec::new();
+
+    for node in fstree.iter().filter(|node| node.is_file()) {
+        if node.nlink() > 1 {
+            if let Some(source) = sources.get(&node.inode_key()) {
+                links.push((source.clone(), node));
+                continue;
+            }
+            sources.insert(node.inode_key(), node.clone());
+        }
+        unique.push(node);
+    }
+
+    fstree.install(|| {
+        unique.par_iter().for_each(|node| {
+            if let Err(err) = process_file(fstree, node, consumers, max_map_size) {
+                fstree.record_error(node, "read", err);
+            }
+        })
+    });
+
+    for (source, node) in links {
+        for consumer in consumers {
+            consumer.copy(&source, &node);
+        }
+    }
+
+    for consumer in consumers {
+        consumer.after_files(fstree);
+    }
+}
+
+/*
+    Read errors are returned, the errors of the consumers are recorded on the tree
+*/
+fn process_file(fstree: &FsTree, node: &Node, consumers: &[&dyn FileConsumer], max_map_size: u64) -> Result<()> {
+    let local_path = node.local_path();
+    let mut file = File::open(&local_path).map_err(|err| Error::io(&local_path, err))?;
+
+    let len = node.len();
+    if len == 0 {
+        for consumer in consumers {
+            consume(fstree, node, *consumer, Content::Whole(&[]));
+        }
+        return Ok(());
+    }
+
+    if len <= max_map_size {
+        // The image is not modified while it is analysed
+        if let Ok(mmap) = unsafe { Mmap::map(&file) } {
+            for consumer in consumers {
+                consume(fstree, node, *consumer, Content::Whole(&mmap));
+            }
+            return Ok(());
+        }
+    }
+
+    // Streaming: the consumers get the first chunk, then their sinks get the rest
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let head_len = read_chunk(&mut file, &mut chunk).map_err(|err| Error::io(&local_path, err))?;
+
+    let mut sinks: Vec<Box<dyn FileSink>> = consumers
+        .iter()
+        .filter_map(|consumer| consume(fstree, node, *consumer, Content::Head(&chunk[..head_len])))
+        .collect();
+
+    if !sinks.is_empty() {
+        loop {
+            let read = read_chunk(&mut file, &mut chunk).map_err(|err| Error::io(&local_path, err))?;
+            if read == 0 {
+                break;
+            }
+            for sink in sinks.iter_mut() {
+                sink.update(&chunk[..read]);
+            }
+        }
+    }
+
+    for sink in sinks {
+        sink.finish(node);
+    }
+    Ok(())
+}
+
+fn consume(fstree: &FsTree, node: &Node, consumer: &dyn FileConsumer, content: Content) -> Option<Box<dyn FileSink>> {
+    match consumer.consume(node, content) {
+        Ok(sink) => sink,
+        Err(err) => {
+            fstree.record_error(node, consumer.name(), err);
+            None
+        }
+    }
+}
+
+/*
+    Fill the buffer as much as possible, returns the number of bytes read
+*/
+fn read_chunk(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::file::FileType;
+    use crate::core::hash::sha256_hex;
+    use crate::core::testutil::{image, remove};
+
+    use std::fs;
+    use std::sync::Mutex;
+
+    // Records the calls made by the pipeline
+    #[derive(Default)]
+    struct CountConsumer {
+        consumed: Mutex<Vec<String>>,
+        copied: Mutex<Vec<(String, String)>>,
+    }
+
+    impl FileConsumer for CountConsumer {
+        fn name(&self) -> &'static str {
+            "count"
+        }
+
+        fn consume(&self, node: &Node, _content: Content) -> Result<Option<Box<dyn FileSink>>> {
+            self.consumed.lock().unwrap().push(node.fs_path());
+            Ok(None)
+        }
+
+        fn copy(&self, source: &Node, node: &Node) {
+            self.copied.lock().unwrap().push((source.fs_path(), node.fs_path()));
+        }
+    }
+
+    #[test]
+    fn hard_links_are_read_once() {
+        let root = image("pipeline_links", &[("bin/busybox", b"busybox\n"), ("etc/motd", b"hello\n")]);
+        fs::hard_link(root.join("bin/busybox"), root.join("bin/sh")).unwrap();
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+
+        let count = CountConsumer::default();
+        let hash = HashPass::new();
+        run_consumers(&fstree, &[&count, &hash], DEFAULT_MAX_MAP_SIZE);
+
+        let mut consumed = count.consumed.lock().unwrap().clone();
+        consumed.sort();
+        assert_eq!(consumed.len(), 2);
+        assert_eq!(consumed[1], "/etc/motd");
+
+        // The link read is the source of the copy
+        let copied = count.copied.lock().unwrap().clone();
+        assert_eq!(copied.len(), 1);
+        let (source, link) = &copied[0];
+        assert_eq!(source, &consumed[0]);
+        assert_ne!(source, link);
+        assert!(["/bin/busybox", "/bin/sh"].contains(&link.as_str()));
+
+        let busybox = fstree.get("/bin/busybox").unwrap();
+        assert_eq!(busybox.hash(), Some(sha256_hex(b"busybox\n")));
+        assert_eq!(fstree.get("/bin/sh").unwrap().hash(), busybox.hash());
+
+        remove(&root);
+    }
+
+    #[test]
+    fn mapped_and_streamed_files() {
+        let big: Vec<u8> = (0..3 * CHUNK_SIZE + 123).map(|i| b'a' + (i % 26) as u8).collect();
+        let root = image("pipeline_stream", &[("big.txt", &big), ("small.sh", b"#!/bin/sh\nexit 0\n")]);
+
+        let results = |max_map_size| {
+            let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+            let mut pipeline = FilePipeline::with_default_consumers();
+            pipeline.set_max_map_size(max_map_size);
+            pipeline.run(&fstree).unwrap();
+            ["/big.txt", "/small.sh"].map(|path| {
+                let node = fstree.get(path).unwrap();
+                (format!("{:?}", node.file_type()), node.hash())
+            })
+        };
+
+        let mapped = results(DEFAULT_MAX_MAP_SIZE);
+        let streamed = results(16);
+        assert_eq!(mapped, streamed);
+        assert_eq!(mapped[0].1, Some(sha256_hex(&big)));
+        assert_eq!(mapped[1].1, Some(sha256_hex(b"#!/bin/sh\nexit 0\n")));
+
+        remove(&root);
+    }
+
+    #[test]
+    fn consumers_follow_their_dependencies() {
+        let mut pipeline = FilePipeline::new();
+        pipeline.add(BinaryPass::new());
+        assert_eq!(pipeline.provides(), vec!["files", "binaries"]);
+        assert_eq!(pipeline.dependencies(), &["file-type"]);
+
+        pipeline.add(HashPass::new());
+        pipeline.add(FileTypePass::new());
+        assert_eq!(pipeline.provides(), vec!["files", "hash", "file-type", "binaries"]);
+        assert!(pipeline.dependencies().is_empty());
+    }
+
+    #[test]
+    fn big_elf_is_analysed() {
+        let exe = fs::read(std::env::current_exe().unwrap()).unwrap();
+        let root = image("pipeline_elf", &[("bin/test", &exe)]);
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+
+        let mut pipeline = FilePipeline::with_default_consumers();
+        pipeline.set_max_map_size(16);
+        pipeline.run(&fstree).unwrap();
+
+        let node = fstree.get("/bin/test").unwrap();
+        match node.file_type() {
+            Some(FileType::Elf(Some(elf_data))) => assert!(!elf_data.dyn_funcs.is_empty()),
+            other => panic!("unexpected type {:?}", other),
+        }
+        assert_eq!(node.hash(), Some(sha256_hex(&exe)));
+
+        // A consumer run alone takes its own limit
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+        FileTypePass::new().set_max_map_size(16).run(&fstree).unwrap();
+        BinaryPass::new().set_max_map_size(16).run(&fstree).unwrap();
+        assert!(matches!(fstree.get("/bin/test").unwrap().file_type(), Some(FileType::Elf(Some(_)))));
+
+        remove(&root);
+    }
+
+    #[test]
+    fn empty_and_short_files() {
+        let root = image("pipeline_empty", &[("empty", b""), ("magic", b"\x7fEL"), ("notes.txt", b"")]);
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+        FilePipeline::with_default_consumers().run(&fstree).unwrap();
+
+        let empty = fstree.get("/empty").unwrap();
+        assert!(matches!(empty.file_type(), Some(FileType::Data)));
+        assert!(empty.hash().is_some());
+        assert!(matches!(fstree.get("/magic").unwrap().file_type(), Some(FileType::Data)));
+        assert!(matches!(fstree.get("/notes.txt").unwrap().file_type(), Some(FileType::Text)));
+        assert!(fstree.errors().is_empty());
+
+        remove(&root);
+    }
+
+    #[test]
+    fn truncated_elf_is_recorded() {
+        let root = image("pipeline_truncated", &[("bin", b"\x7fELF\x01\x01\x01\x00")]);
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+        FilePipeline::with_default_consumers().run(&fstree).unwrap();
+
+        let errors = fstree.errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].fs_path, "/bin");
+        assert_eq!(errors[0].stage, "binaries");
+
+        remove(&root);
+    }
+}
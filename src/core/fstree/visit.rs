@@ -0,0 +1,55 @@
+use crate::core::fstree::node::Node;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    Continue,
+    // Do not visit the childrens of this node (post is still called)
+    SkipChildrens,
+    // Stop the whole traversal
+    Stop,
+}
+
+/*
+    Hooks called during a depth-first traversal of the tree. pre is called
+    before the childrens of a node, post after all of them.
+*/
+pub trait Visitor {
+    fn pre(&mut self, _node: &Node) -> Visit {
+        Visit::Continue
+    }
+
+    fn post(&mut self, _node: &Node) {}
+}
+
+/*
+    Walk the subtree of node, returns false if the visitor stopped the traversal
+*/
+pub fn walk<V: Visitor + ?Sized>(node: &Node, visitor: &mut V) -> bool {
+    match visitor.pre(node) {
+        Visit::Stop => return false,
+        Visit::SkipChildrens => {}
+        Visit::Continue => {
+            if node.is_dir() {
+                for child in node.childrens() {
+                    if !walk(&child, visitor) {
+                        return false;
+                    }
+                }
+            }
+        }
+    }
+    visitor.post(node);
+    true
+}
+
+/*
+    Visitor calling a closure on each node (pre-order)
+*/
+pub struct FnVisitor<F: FnMut(&Node)>(pub F);
+
+impl<F: FnMut(&Node)> Visitor for FnVisitor<F> {
+    fn pre(&mut self, node: &Node) -> Visit {
+        (self.0)(node);
+        Visit::Continue
+    }
+}
@@ -0,0 +1,384 @@
+/*
+    Context triggered piecewise hashing, compatible with ssdeep digests
+    ("<block size>:<digest>:<digest of the double block size>")
+*/
+
+const ROLLING_WINDOW: usize = 7;
+const MIN_BLOCKSIZE: u32 = 3;
+const NUM_BLOCKHASHES: usize = 31;
+const SPAMSUM_LENGTH: usize = 64;
+const HASH_PRIME: u32 = 0x01000193;
+const HASH_INIT: u32 = 0x28021967;
+
+const B64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn block_size(i: usize) -> u64 {
+    (MIN_BLOCKSIZE as u64) << i
+}
+
+fn sum_hash(c: u8, h: u32) -> u32 {
+    h.wrapping_mul(HASH_PRIME) ^ c as u32
+}
+
+#[derive(Default)]
+struct RollState {
+    window: [u8; ROLLING_WINDOW],
+    h1: u32,
+    h2: u32,
+    h3: u32,
+    n: usize,
+}
+
+impl RollState {
+    fn update(&mut self, c: u8) {
+        self.h2 = self.h2.wrapping_sub(self.h1);
+        self.h2 = self.h2.wrapping_add((ROLLING_WINDOW as u32).wrapping_mul(c as u32));
+
+        self.h1 = self.h1.wrapping_add(c as u32);
+        self.h1 = self.h1.wrapping_sub(self.window[self.n % ROLLING_WINDOW] as u32);
+
+        self.window[self.n % ROLLING_WINDOW] = c;
+        self.n += 1;
+
+        self.h3 = (self.h3 << 5) ^ c as u32;
+    }
+
+    fn sum(&self) -> u32 {
+        self.h1.wrapping_add(self.h2).wrapping_add(self.h3)
+    }
+}
+
+#[derive(Clone)]
+struct BlockHash {
+    h: u32,
+    halfh: u32,
+    digest: Vec<u8>,
+    // Pending character of the digest (written on each trigger)
+    last: Option<u8>,
+    halfdigest: Option<u8>,
+}
+
+impl BlockHash {
+    fn new(h: u32, halfh: u32) -> Self {
+        Self {
+            h,
+            halfh,
+            digest: Vec::with_capacity(SPAMSUM_LENGTH),
+            last: None,
+            halfdigest: None,
+        }
+    }
+}
+
+/*
+    Streaming CTPH state. All the block sizes which can be selected are
+    computed at the same time, so the input is read only once.
+*/
+pub struct Ctph {
+    roll: RollState,
+    blocks: Vec<BlockHash>,
+    // First block size still candidate
+    start: usize,
+    // Total length of the input, used to select the block size
+    total_len: u64,
+}
+
+impl Ctph {
+    pub fn new(total_len: u64) -> Self {
+        Self {
+            roll: RollState::default(),
+            blocks: vec![BlockHash::new(HASH_INIT, HASH_INIT)],
+            start: 0,
+            total_len,
+        }
+    }
+
+    fn try_fork(&mut self) {
+        if self.blocks.len() < NUM_BLOCKHASHES {
+            let last = self.blocks.last().unwrap();
+            let block = BlockHash::new(last.h, last.halfh);
+            self.blocks.push(block);
+        }
+    }
+
+    fn try_reduce(&mut self) {
+        if self.blocks.len() - self.start < 2 {
+            return;
+        }
+        // Still a candidate for the length of the input
+        if block_size(self.start) * SPAMSUM_LENGTH as u64 >= self.total_len {
+            return;
+        }
+        // The next block size does not have enough characters yet
+        if self.blocks[self.start + 1].digest.len() < SPAMSUM_LENGTH / 2 {
+            return;
+        }
+        self.start += 1;
+    }
+
+    fn step(&mut self, c: u8) {
+        self.roll.update(c);
+        let h = self.roll.sum() as u64;
+
+        for block in &mut self.blocks[self.start..] {
+            block.h = sum_hash(c, block.h);
+            block.halfh = sum_hash(c, block.halfh);
+        }
+
+        let mut i = self.start;
+        while i < self.blocks.len() {
+            let size = block_size(i);
+            if h % size != size - 1 {
+                break;
+            }
+            if self.blocks[i].digest.is_empty() {
+                self.try_fork();
+            }
+
+            let block = &mut self.blocks[i];
+            block.last = Some(B64[(block.h % 64) as usize]);
+            block.halfdigest = Some(B64[(block.halfh % 64) as usize]);
+
+            if block.digest.len() < SPAMSUM_LENGTH - 1 {
+                block.digest.push(block.last.take().unwrap());
+                block.h = HASH_INIT;
+                if block.digest.len() < SPAMSUM_LENGTH / 2 {
+                    block.halfh = HASH_INIT;
+                    block.halfdigest = None;
+                }
+            } else {
+                self.try_reduce();
+            }
+            i += 1;
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &c in bytes {
+            self.step(c);
+        }
+    }
+
+    pub fn finish(self) -> String {
+        let h = self.roll.sum();
+
+        // Initial guess from the length, then use a smaller block size if the digest is too short
+        let mut i = self.start;
+        while block_size(i) * (SPAMSUM_LENGTH as u64) < self.total_len && i < NUM_BLOCKHASHES - 1 {
+            i += 1;
+        }
+        if i >= self.blocks.len() {
+            i = self.blocks.len() - 1;
+        }
+        while i > self.start && self.blocks[i].digest.len() < SPAMSUM_LENGTH / 2 {
+            i -= 1;
+        }
+
+        let block = &self.blocks[i];
+        let mut digest = block.digest.clone();
+        if h != 0 {
+            digest.push(B64[(block.h % 64) as usize]);
+        } else if let Some(last) = block.last {
+            digest.push(last);
+        }
+
+        let mut double_digest = Vec::new();
+        if i < self.blocks.len() - 1 {
+            let block = &self.blocks[i + 1];
+            let len = block.digest.len().min(SPAMSUM_LENGTH / 2 - 1);
+            double_digest.extend_from_slice(&block.digest[..len]);
+            if h != 0 {
+                double_digest.push(B64[(block.halfh % 64) as usize]);
+            } else if let Some(halfdigest) = block.halfdigest {
+                double_digest.push(halfdigest);
+            }
+        } else if h != 0 {
+            double_digest.push(B64[(block.h % 64) as usize]);
+        }
+
+        format!(
+            "{}:{}:{}",
+            block_size(i),
+            String::from_utf8_lossy(&digest),
+            String::from_utf8_lossy(&double_digest)
+        )
+    }
+}
+
+pub fn hash(bytes: &[u8]) -> String {
+    let mut ctph = Ctph::new(bytes.len() as u64);
+    ctph.update(bytes);
+    ctph.finish()
+}
+
+/*
+    Similarity score (0 to 100) of two CTPH digests, 0 if the block sizes
+    can't be compared
+*/
+pub fn compare(digest1: &str, digest2: &str) -> u32 {
+    let (size1, a1, b1) = match parse(digest1) {
+        Some(parsed) => parsed,
+        None => return 0,
+    };
+    let (size2, a2, b2) = match parse(digest2) {
+        Some(parsed) => parsed,
+        None => return 0,
+    };
+
+    if size1 != size2 && size1 != size2 * 2 && size2 != size1 * 2 {
+        return 0;
+    }
+    if size1 == size2 && a1 == a2 {
+        return 100;
+    }
+
+    if size1 == size2 {
+        score_strings(&a1, &a2, size1).max(score_strings(&b1, &b2, size1 * 2))
+    } else if size1 == size2 * 2 {
+        score_strings(&a1, &b2, size1)
+    } else {
+        score_strings(&b1, &a2, size2)
+    }
+}
+
+fn parse(digest: &str) -> Option<(u64, Vec<u8>, Vec<u8>)> {
+    let mut parts = digest.splitn(3, ':');
+    let size = parts.next()?.parse().ok()?;
+    let a = eliminate_sequences(parts.next()?.as_bytes());
+    let b = eliminate_sequences(parts.next()?.trim_end_matches([',', '"']).as_bytes());
+    Some((size, a, b))
+}
+
+/*
+    Runs of more than 3 identical characters are reduced to 3
+*/
+fn eliminate_sequences(s: &[u8]) -> Vec<u8> {
+    let mut res: Vec<u8> = Vec::with_capacity(s.len());
+    for (i, &c) in s.iter().enumerate() {
+        if i >= 3 && c == s[i - 1] && c == s[i - 2] && c == s[i - 3] {
+            continue;
+        }
+        res.push(c);
+    }
+    res
+}
+
+fn has_common_substring(s1: &[u8], s2: &[u8]) -> bool {
+    if s1.len() < ROLLING_WINDOW || s2.len() < ROLLING_WINDOW {
+        return false;
+    }
+    s1.windows(ROLLING_WINDOW).any(|w1| s2.windows(ROLLING_WINDOW).any(|w2| w1 == w2))
+}
+
+/*
+    Edit distance with insertions and deletions costing 1 and substitutions 2
+*/
+fn edit_distance(s1: &[u8], s2: &[u8]) -> u32 {
+    let mut prev: Vec<u32> = (0..=s2.len() as u32).collect();
+    let mut cur = vec![0; s2.len() + 1];
+
+    for (i, &c1) in s1.iter().enumerate() {
+        cur[0] = i as u32 + 1;
+        for (j, &c2) in s2.iter().enumerate() {
+            let substitution = prev[j] + if c1 == c2 { 0 } else { 2 };
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[s2.len()]
+}
+
+fn score_strings(s1: &[u8], s2: &[u8], block_size: u64) -> u32 {
+    if s1.len() > SPAMSUM_LENGTH || s2.len() > SPAMSUM_LENGTH {
+        return 0;
+    }
+    if !has_common_substring(s1, s2) {
+        return 0;
+    }
+
+    let mut score = edit_distance(s1, s2) as u64;
+    score = score * SPAMSUM_LENGTH as u64 / (s1.len() + s2.len()) as u64;
+    score = 100 * score / SPAMSUM_LENGTH as u64;
+    if score >= 100 {
+        return 0;
+    }
+    score = 100 - score;
+
+    // Small block sizes can't give a high score for short digests
+    let limit_size = (99 + ROLLING_WINDOW as u64) / ROLLING_WINDOW as u64 * MIN_BLOCKSIZE as u64;
+    if block_size < limit_size {
+        let cap = block_size / MIN_BLOCKSIZE as u64 * s1.len().min(s2.len()) as u64;
+        score = score.min(cap);
+    }
+    score as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic pseudo-random content
+    fn lcg(len: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345) & 0x7fffffff;
+                (x >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn known_digests() {
+        assert_eq!(hash(b""), "3::");
+        assert_eq!(hash(b"Hello, World!\n"), "3:aaX8v:aV");
+        assert_eq!(
+            hash(&b"The quick brown fox jumps over the lazy dog. ".repeat(40)),
+            "12:Fg66666666666666666666666666666666666666G:F1"
+        );
+        assert_eq!(
+            hash(&lcg(4096, 1)),
+            "96:60D/ucey7/cIHEAe/gmb4TZuCeXaXQ7diFzFvG6pcEob:xD/uceMkIkJ/jb4ACeXCQ7diBlG6apb"
+        );
+        assert_eq!(
+            hash(&lcg(65536, 7)),
+            "1536:FlB1iF6tlrom/ZZ7NNGMFMgy2AUNoPrY6fWNeQ44oA5P+c:XB1U6tlroq7NNhmgyaKThfWNN8Wx"
+        );
+    }
+
+    #[test]
+    fn streamed_digest() {
+        let bytes = lcg(65536, 7);
+        let mut ctph = Ctph::new(bytes.len() as u64);
+        for chunk in bytes.chunks(1000) {
+            ctph.update(chunk);
+        }
+        assert_eq!(ctph.finish(), hash(&bytes));
+    }
+
+    #[test]
+    fn compare_scores() {
+        let bytes = lcg(65536, 7);
+        let digest = hash(&bytes);
+        assert_eq!(compare(&digest, &digest), 100);
+        assert_eq!(compare(&digest, &hash(&lcg(65536, 8))), 0);
+
+        // A small change keeps most of the digest
+        let mut changed = bytes.clone();
+        changed[30000..30100].fill(0);
+        let score = compare(&digest, &hash(&changed));
+        assert!(score > 50 && score < 100, "score {}", score);
+
+        // Block sizes too far apart, or malformed digests
+        assert_eq!(compare("3:abcdefgh:abcd", "12:abcdefgh:abcd"), 0);
+        assert_eq!(compare(&digest, "not a digest"), 0);
+    }
+
+    #[test]
+    fn sequences_and_distance() {
+        assert_eq!(eliminate_sequences(b"aaaaabbbc"), b"aaabbbc");
+        assert_eq!(edit_distance(b"abc", b"abc"), 0);
+        assert_eq!(edit_distance(b"abc", b"abd"), 2);
+        assert_eq!(edit_distance(b"abc", b"ab"), 1);
+    }
+}
@@ -0,0 +1,266 @@
+pub mod ctph;
+
+use ctph::Ctph;
+
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use tlsh2::{TlshDefault, TlshDefaultBuilder};
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DigestKind {
+    Sha256,
+    Sha1,
+    Md5,
+    Blake3,
+    // ssdeep-compatible context triggered piecewise hash
+    Ctph,
+    Tlsh,
+}
+
+impl DigestKind {
+    pub const ALL: [DigestKind; 6] = [
+        DigestKind::Sha256,
+        DigestKind::Sha1,
+        DigestKind::Md5,
+        DigestKind::Blake3,
+        DigestKind::Ctph,
+        DigestKind::Tlsh,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DigestKind::Sha256 => "sha256",
+            DigestKind::Sha1 => "sha1",
+            DigestKind::Md5 => "md5",
+            DigestKind::Blake3 => "blake3",
+            DigestKind::Ctph => "ctph",
+            DigestKind::Tlsh => "tlsh",
+        }
+    }
+
+    // Similarity digests, compared with a score instead of equality
+    pub fn is_fuzzy(&self) -> bool {
+        matches!(self, DigestKind::Ctph | DigestKind::Tlsh)
+    }
+}
+
+impl fmt::Display for DigestKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for DigestKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sha256" | "sha-256" => Ok(DigestKind::Sha256),
+            "sha1" | "sha-1" => Ok(DigestKind::Sha1),
+            "md5" => Ok(DigestKind::Md5),
+            "blake3" => Ok(DigestKind::Blake3),
+            "ctph" | "ssdeep" => Ok(DigestKind::Ctph),
+            "tlsh" => Ok(DigestKind::Tlsh),
+            _ => Err(format!("unknown digest '{}'", s)),
+        }
+    }
+}
+
+// Digests computed when none are configured
+pub const DEFAULT_DIGESTS: [DigestKind; 3] = [DigestKind::Sha256, DigestKind::Ctph, DigestKind::Tlsh];
+
+/*
+    Digests of a file, as lowercase hex strings (CTPH and TLSH use their
+    usual text form)
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Digests {
+    pub sha256: Option<String>,
+    pub sha1: Option<String>,
+    pub md5: Option<String>,
+    pub blake3: Option<String>,
+    pub ctph: Option<String>,
+    pub tlsh: Option<String>,
+}
+
+impl Digests {
+    pub fn get(&self, kind: DigestKind) -> Option<&str> {
+        let digest = match kind {
+            DigestKind::Sha256 => &self.sha256,
+            DigestKind::Sha1 => &self.sha1,
+            DigestKind::Md5 => &self.md5,
+            DigestKind::Blake3 => &self.blake3,
+            DigestKind::Ctph => &self.ctph,
+            DigestKind::Tlsh => &self.tlsh,
+        };
+        digest.as_deref()
+    }
+
+    pub fn set(&mut self, kind: DigestKind, digest: String) {
+        let field = match kind {
+            DigestKind::Sha256 => &mut self.sha256,
+            DigestKind::Sha1 => &mut self.sha1,
+            DigestKind::Md5 => &mut self.md5,
+            DigestKind::Blake3 => &mut self.blake3,
+            DigestKind::Ctph => &mut self.ctph,
+            DigestKind::Tlsh => &mut self.tlsh,
+        };
+        *field = Some(digest);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().is_empty()
+    }
+
+    /*
+        All the digests computed, (kind, digest)
+    */
+    pub fn iter(&self) -> Vec<(DigestKind, &str)> {
+        DigestKind::ALL
+            .iter()
+            .filter_map(|kind| self.get(*kind).map(|digest| (*kind, digest)))
+            .collect()
+    }
+
+    /*
+        Main cryptographic digest, used to compare contents (SHA-256 if computed)
+    */
+    pub fn primary(&self) -> Option<&str> {
+        self.iter()
+            .into_iter()
+            .find(|(kind, _)| !kind.is_fuzzy())
+            .map(|(_, digest)| digest)
+    }
+
+    /*
+        True if a cryptographic digest is known for both and they are equal
+    */
+    pub fn same_content(&self, other: &Digests) -> Option<bool> {
+        DigestKind::ALL
+            .iter()
+            .filter(|kind| !kind.is_fuzzy())
+            .find_map(|kind| match (self.get(*kind), other.get(*kind)) {
+                (Some(a), Some(b)) => Some(a == b),
+                _ => None,
+            })
+    }
+
+    /*
+        Similarity (0 to 100) from the fuzzy digests, CTPH first then TLSH
+    */
+    pub fn similarity(&self, other: &Digests) -> Option<u32> {
+        if let (Some(a), Some(b)) = (&self.ctph, &other.ctph) {
+            let score = ctph::compare(a, b);
+            if score > 0 {
+                return Some(score);
+            }
+        }
+        if let (Some(a), Some(b)) = (&self.tlsh, &other.tlsh) {
+            return tlsh_distance(a, b).map(tlsh_similarity);
+        }
+        if let (Some(a), Some(b)) = (&self.ctph, &other.ctph) {
+            return Some(ctph::compare(a, b));
+        }
+        None
+    }
+}
+
+/*
+    Computes a set of digests over a stream of bytes
+*/
+pub struct MultiHasher {
+    sha256: Option<Sha256>,
+    sha1: Option<Sha1>,
+    md5: Option<Md5>,
+    blake3: Option<Box<blake3::Hasher>>,
+    ctph: Option<Ctph>,
+    tlsh: Option<TlshDefaultBuilder>,
+}
+
+impl MultiHasher {
+    /*
+        total_len is the length of the whole input (needed by CTPH to select its block size)
+    */
+    pub fn new(kinds: &[DigestKind], total_len: u64) -> Self {
+        let has = |kind| kinds.contains(&kind);
+
+        Self {
+            sha256: has(DigestKind::Sha256).then(Sha256::new),
+            sha1: has(DigestKind::Sha1).then(Sha1::new),
+            md5: has(DigestKind::Md5).then(Md5::new),
+            blake3: has(DigestKind::Blake3).then(|| Box::new(blake3::Hasher::new())),
+            ctph: has(DigestKind::Ctph).then(|| Ctph::new(total_len)),
+            tlsh: has(DigestKind::Tlsh).then(TlshDefaultBuilder::new),
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        if let Some(hasher) = &mut self.sha256 {
+            hasher.update(bytes);
+        }
+        if let Some(hasher) = &mut self.sha1 {
+            hasher.update(bytes);
+        }
+        if let Some(hasher) = &mut self.md5 {
+            hasher.update(bytes);
+        }
+        if let Some(hasher) = &mut self.blake3 {
+            hasher.update(bytes);
+        }
+        if let Some(hasher) = &mut self.ctph {
+            hasher.update(bytes);
+        }
+        if let Some(hasher) = &mut self.tlsh {
+            hasher.update(bytes);
+        }
+    }
+
+    pub fn finish(self) -> Digests {
+        Digests {
+            sha256: self.sha256.map(|hasher| hex(&hasher.finalize())),
+            sha1: self.sha1.map(|hasher| hex(&hasher.finalize())),
+            md5: self.md5.map(|hasher| hex(&hasher.finalize())),
+            blake3: self.blake3.map(|hasher| hasher.finalize().to_hex().to_string()),
+            ctph: self.ctph.map(|hasher| hasher.finish()),
+            // TLSH needs at least 50 bytes with enough variety
+            tlsh: self
+                .tlsh
+                .and_then(|builder| builder.build())
+                .map(|tlsh| String::from_utf8_lossy(&tlsh.hash()).to_lowercase()),
+        }
+    }
+}
+
+pub fn digest_bytes(kinds: &[DigestKind], bytes: &[u8]) -> Digests {
+    let mut hasher = MultiHasher::new(kinds, bytes.len() as u64);
+    hasher.update(bytes);
+    hasher.finish()
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/*
+    TLSH distance (0 for identical files, more than 300 for unrelated ones)
+*/
+pub fn tlsh_distance(digest1: &str, digest2: &str) -> Option<i32> {
+    let tlsh1 = TlshDefault::from_str(&digest1.to_uppercase()).ok()?;
+    let tlsh2 = TlshDefault::from_str(&digest2.to_uppercase()).ok()?;
+    Some(tlsh1.diff(&tlsh2, true))
+}
+
+/*
+    Map a TLSH distance on a 0 to 100 similarity, 100 meaning identical
+*/
+pub fn tlsh_similarity(distance: i32) -> u32 {
+    (100 - distance.clamp(0, 100)) as u32
+}
@@ -1,9 +1,30 @@
-pub mod analyzer;
 pub mod args;
-pub mod tree;
 
 pub mod fstree;
 
-pub mod node;
-
 pub mod file;
+
+pub mod secrets;
+
+pub mod config;
+
+pub mod accounts;
+
+pub mod services;
+
+pub mod surface;
+
+pub mod web;
+
+pub mod permissions;
+
+pub mod query;
+
+pub mod hash;
+
+pub mod error;
+
+pub mod diff;
+
+#[cfg(test)]
+pub mod testutil;
@@ -0,0 +1,257 @@
+use crate::core::fstree::node::metadata::NodeMetadata;
+use crate::core::fstree::node::Node;
+use crate::core::fstree::visit::{Visit, Visitor};
+
+// Capabilities giving (almost) full control of the system
+const DANGEROUS_CAPABILITIES: [&str; 9] = [
+    "cap_sys_admin",
+    "cap_net_admin",
+    "cap_net_raw",
+    "cap_sys_module",
+    "cap_sys_ptrace",
+    "cap_sys_rawio",
+    "cap_dac_override",
+    "cap_setuid",
+    "cap_setgid",
+];
+
+// Imports making a SUID binary worth a closer look
+const DANGEROUS_IMPORTS: [&str; 12] = [
+    "system", "popen", "execl", "execle", "execlp", "execv", "execve", "execvp", "execvpe", "gets", "strcpy", "sprintf",
+];
+
+pub struct PermissionReport {
+    // UIDs allowed to own files (root and the accounts of /etc/passwd)
+    pub expected_uids: Vec<u32>,
+
+    pub suid: Vec<Node>,
+    pub sgid: Vec<Node>,
+    pub world_writable_files: Vec<Node>,
+    // World-writable directories without the sticky bit
+    pub world_writable_dirs: Vec<Node>,
+    // World-writable directories with the sticky bit (like /tmp)
+    pub world_writable_sticky_dirs: Vec<Node>,
+    pub unexpected_owner: Vec<Node>,
+    // Executables in a directory writable by a non-root user
+    pub exec_in_writable_dir: Vec<Node>,
+    // SUID/SGID ELFs with their dangerous imports
+    pub dangerous_suid: Vec<(Node, Vec<String>)>,
+    // Files with file capabilities (security.capability)
+    pub capabilities: Vec<(Node, Vec<&'static str>)>,
+}
+
+impl PermissionReport {
+    pub fn new(expected_uids: Vec<u32>) -> Self {
+        Self {
+            expected_uids,
+            suid: Vec::new(),
+            sgid: Vec::new(),
+            world_writable_files: Vec::new(),
+            world_writable_dirs: Vec::new(),
+            world_writable_sticky_dirs: Vec::new(),
+            unexpected_owner: Vec::new(),
+            exec_in_writable_dir: Vec::new(),
+            dangerous_suid: Vec::new(),
+            capabilities: Vec::new(),
+        }
+    }
+
+    /*
+        Check a node, parent_writable tells if its directory can be written by a non-root user
+    */
+    pub fn add_node(&mut self, node: &Node, parent_writable: bool) {
+        let metadata = node.metadata();
+
+        if !self.expected_uids.contains(&metadata.uid) {
+            self.unexpected_owner.push(node.clone());
+        }
+
+        // The mode of a symlink is meaningless
+        if node.is_symlink() {
+            return;
+        }
+
+        if node.is_dir() {
+            if metadata.is_world_writable() {
+                if metadata.is_sticky() {
+                    self.world_writable_sticky_dirs.push(node.clone());
+                } else {
+                    self.world_writable_dirs.push(node.clone());
+                }
+            }
+            return;
+        }
+
+        if !node.is_file() {
+            return;
+        }
+
+        if metadata.is_world_writable() {
+            self.world_writable_files.push(node.clone());
+        }
+
+        if let Some(capabilities) = node.capabilities() {
+            let names = capabilities.permitted_names();
+            if !names.is_empty() {
+                self.capabilities.push((node.clone(), names));
+            }
+        }
+
+        if !metadata.is_executable() {
+            return;
+        }
+
+        if metadata.is_suid() {
+            self.suid.push(node.clone());
+        }
+        if metadata.is_sgid() {
+            self.sgid.push(node.clone());
+        }
+
+        if (metadata.is_suid() || metadata.is_sgid()) && node.is_elf() {
+            let imports: Vec<String> = node
+                .elf_imports()
+                .into_iter()
+                .filter(|i| DANGEROUS_IMPORTS.contains(&i.as_str()))
+                .collect();
+            if !imports.is_empty() {
+                self.dangerous_suid.push((node.clone(), imports));
+            }
+        }
+
+        if parent_writable {
+            self.exec_in_writable_dir.push(node.clone());
+        }
+    }
+
+    pub fn display(&self) {
+        let sections = [
+            ("SUID executables", &self.suid),
+            ("SGID executables", &self.sgid),
+            ("World-writable files", &self.world_writable_files),
+            ("World-writable directories", &self.world_writable_dirs),
+            ("World-writable sticky directories", &self.world_writable_sticky_dirs),
+            ("Unexpected owners", &self.unexpected_owner),
+            ("Executables in writable directories", &self.exec_in_writable_dir),
+        ];
+
+        for (title, nodes) in sections {
+            println!("{} ({})", title, nodes.len());
+            for node in nodes {
+                let metadata = node.metadata();
+                println!("    {} {}:{} {}", metadata.mode_string(), metadata.uid, metadata.gid, node.fs_path());
+            }
+        }
+
+        println!("SUID/SGID binaries with dangerous imports ({})", self.dangerous_suid.len());
+        for (node, imports) in &self.dangerous_suid {
+            println!("    {} [{}]", node.fs_path(), imports.join(", "));
+        }
+
+        println!("Files with capabilities ({})", self.capabilities.len());
+        for (node, names) in &self.capabilities {
+            let dangerous = names.iter().any(|name| DANGEROUS_CAPABILITIES.contains(name));
+            let marker = if dangerous { "!" } else { " " };
+            println!("  {} {} [{}]", marker, node.fs_path(), names.join(", "));
+        }
+    }
+}
+
+impl Visitor for PermissionReport {
+    fn pre(&mut self, node: &Node) -> Visit {
+        let parent_writable = match node.parent() {
+            Some(parent) => is_writable_by_non_root(&parent.metadata()),
+            None => false,
+        };
+        self.add_node(node, parent_writable);
+        Visit::Continue
+    }
+}
+
+/*
+    A directory is writable by a non-root user if it is world-writable or if
+    a non-root owner or group has the write bit
+*/
+pub fn is_writable_by_non_root(metadata: &NodeMetadata) -> bool {
+    metadata.is_world_writable()
+        || (metadata.uid != 0 && metadata.mode & 0o200 != 0)
+        || (metadata.gid != 0 && metadata.mode & 0o020 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::{image, remove, tree};
+
+    use std::fs;
+    use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+
+    fn paths(nodes: &[Node]) -> Vec<String> {
+        let mut paths: Vec<String> = nodes.iter().map(|node| node.fs_path()).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn findings() {
+        let modes = [
+            ("bin/su", 0o4755),
+            ("bin/wall", 0o2755),
+            ("bin/both", 0o6755),
+            // The SUID bit without an execute bit does nothing
+            ("bin/inert", 0o4644),
+            ("etc/shared.conf", 0o666),
+            ("etc/hosts", 0o644),
+            ("srv/upload/run.sh", 0o755),
+            ("srv/upload/notes.txt", 0o644),
+        ];
+        let files: Vec<(&str, &[u8])> = modes.iter().map(|(path, _)| (*path, b"#!/bin/sh\n" as &[u8])).collect();
+        let root = image("permissions_findings", &files);
+        for (path, mode) in modes {
+            fs::set_permissions(root.join(path), fs::Permissions::from_mode(mode)).unwrap();
+        }
+        fs::create_dir_all(root.join("tmp")).unwrap();
+        fs::set_permissions(root.join("tmp"), fs::Permissions::from_mode(0o1777)).unwrap();
+        fs::set_permissions(root.join("srv/upload"), fs::Permissions::from_mode(0o777)).unwrap();
+        symlink("/etc/hosts", root.join("etc/link")).unwrap();
+        let owner = fs::metadata(&root).unwrap().uid();
+
+        let fstree = tree(&root);
+        let report = fstree.permission_report_with_uids(vec![owner]);
+        assert_eq!(paths(&report.suid), vec!["/bin/both", "/bin/su"]);
+        assert_eq!(paths(&report.sgid), vec!["/bin/both", "/bin/wall"]);
+        // The symlink mode (0777) is ignored
+        assert_eq!(paths(&report.world_writable_files), vec!["/etc/shared.conf"]);
+        assert_eq!(paths(&report.world_writable_dirs), vec!["/srv/upload"]);
+        assert_eq!(paths(&report.world_writable_sticky_dirs), vec!["/tmp"]);
+        assert_eq!(paths(&report.exec_in_writable_dir), vec!["/srv/upload/run.sh"]);
+        assert!(report.unexpected_owner.is_empty());
+        assert!(report.dangerous_suid.is_empty());
+
+        // Every node, the root and the symlink included, has an unexpected owner
+        let report = fstree.permission_report_with_uids(vec![owner + 1]);
+        assert_eq!(report.unexpected_owner.len(), 15);
+        assert!(paths(&report.unexpected_owner).contains(&"/etc/link".to_string()));
+
+        remove(&root);
+    }
+
+    #[test]
+    fn writable_by_non_root() {
+        let root = image("permissions_writable", &[("file", b"x\n")]);
+        let file = root.join("file");
+        let metadata = |mode| {
+            fs::set_permissions(&file, fs::Permissions::from_mode(mode)).unwrap();
+            NodeMetadata::from_path(&file).unwrap()
+        };
+
+        assert!(is_writable_by_non_root(&metadata(0o666)));
+        let owner_writable = metadata(0o644);
+        assert_eq!(is_writable_by_non_root(&owner_writable), owner_writable.uid != 0);
+        let group_writable = metadata(0o464);
+        assert_eq!(is_writable_by_non_root(&group_writable), group_writable.gid != 0);
+        assert!(!is_writable_by_non_root(&metadata(0o444)));
+
+        remove(&root);
+    }
+}
@@ -0,0 +1,230 @@
+pub mod parser;
+
+use crate::core::fstree::node::Node;
+
+use regex::Regex;
+
+use std::fmt;
+
+#[derive(Debug)]
+pub struct QueryError {
+    // Position (in tokens) of the error in the query
+    pub pos: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "query error at token {}: {}", self.pos, self.msg)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Perm {
+    Suid,
+    Sgid,
+    Sticky,
+    WorldWritable,
+    Exec,
+    // Exact permission bits (ex: 4755)
+    Exact(u32),
+    // At least one of the bits is set (ex: +0002)
+    Any(u32),
+}
+
+#[derive(Debug, Clone)]
+pub enum Predicate {
+    // Glob on the name or on the path in the fs
+    NameGlob(Regex),
+    PathGlob(Regex),
+    NameRegex(Regex),
+    PathRegex(Regex),
+    // Node type (file, dir, symlink, device...) or file type (elf, php, text...)
+    Type(String),
+    // Size range, bounds included
+    Size { min: u64, max: u64 },
+    Perm(Perm),
+    Uid(u32),
+    Gid(u32),
+    // Prefix of one of the digests (hex)
+    Hash(String),
+    ElfMachine(String),
+    ElfPie(bool),
+    // The ELF imports a symbol matching the glob
+    Imports(Regex),
+    // The file has the capability in its permitted set
+    Capability(String),
+    Tag(String),
+}
+
+impl Predicate {
+    pub fn matches(&self, node: &Node) -> bool {
+        match self {
+            Predicate::NameGlob(re) | Predicate::NameRegex(re) => re.is_match(&node.name()),
+            Predicate::PathGlob(re) | Predicate::PathRegex(re) => re.is_match(&node.fs_path()),
+            Predicate::Type(name) => node.type_names().iter().any(|t| t == name),
+            Predicate::Size { min, max } => {
+                let len = node.len();
+                node.is_file() && *min <= len && len <= *max
+            }
+            Predicate::Perm(perm) => {
+                let metadata = node.metadata();
+                match perm {
+                    Perm::Suid => metadata.is_suid(),
+                    Perm::Sgid => metadata.is_sgid(),
+                    Perm::Sticky => metadata.is_sticky(),
+                    Perm::WorldWritable => !node.is_symlink() && metadata.is_world_writable(),
+                    Perm::Exec => metadata.is_executable(),
+                    Perm::Exact(bits) => metadata.permissions() == *bits,
+                    Perm::Any(bits) => metadata.permissions() & bits != 0,
+                }
+            }
+            Predicate::Uid(uid) => node.uid() == *uid,
+            Predicate::Gid(gid) => node.gid() == *gid,
+            Predicate::Hash(prefix) => node
+                .digests()
+                .iter()
+                .into_iter()
+                .any(|(_, digest)| digest.starts_with(prefix.as_str())),
+            Predicate::ElfMachine(machine) => match node.elf_machine() {
+                Some(m) => m.to_lowercase() == *machine,
+                None => false,
+            },
+            Predicate::ElfPie(pie) => node.elf_is_pie() == Some(*pie),
+            Predicate::Imports(re) => node.elf_imports().iter().any(|i| re.is_match(i)),
+            Predicate::Capability(name) => match node.capabilities() {
+                Some(capabilities) => capabilities.has(name),
+                None => false,
+            },
+            Predicate::Tag(tag) => node.has_tag(tag),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Pred(Predicate),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    // Matches every node (empty query)
+    All,
+}
+
+impl Expr {
+    pub fn matches(&self, node: &Node) -> bool {
+        match self {
+            Expr::Pred(predicate) => predicate.matches(node),
+            Expr::Not(expr) => !expr.matches(node),
+            Expr::And(left, right) => left.matches(node) && right.matches(node),
+            Expr::Or(left, right) => left.matches(node) || right.matches(node),
+            Expr::All => true,
+        }
+    }
+
+    pub fn and(self, other: Expr) -> Expr {
+        Expr::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Expr) -> Expr {
+        Expr::Or(Box::new(self), Box::new(other))
+    }
+}
+
+impl std::ops::Not for Expr {
+    type Output = Expr;
+
+    fn not(self) -> Expr {
+        Expr::Not(Box::new(self))
+    }
+}
+
+/*
+    A query over the nodes of a FsTree. Queries are written as predicates
+    <key>:<value> combined with and, or, not and parentheses. Adjacent
+    predicates are joined with "and".
+
+        machine:arm and pie:false and path~:^/usr/sbin/ and imports:system
+        (name:*.php or type:lua) and not tag:reviewed
+
+    Keys: name, path (globs), name~, path~ (regexes), type, size (>1M,
+    <=4096, 10K..2M), perm (suid, sgid, sticky, world-writable, exec, 4755,
+    +0002), uid, gid, hash, machine, pie, imports, cap, tag
+*/
+#[derive(Debug, Clone)]
+pub struct Query {
+    pub expr: Expr,
+}
+
+impl Query {
+    pub fn new(expr: Expr) -> Self {
+        Self { expr }
+    }
+
+    pub fn parse(query: &str) -> Result<Self, QueryError> {
+        Ok(Self {
+            expr: parser::parse(query)?,
+        })
+    }
+
+    pub fn matches(&self, node: &Node) -> bool {
+        self.expr.matches(node)
+    }
+
+    /*
+        Lazily filter the nodes of an iterator (ex: FsTree::iter)
+    */
+    pub fn filter<I>(self, nodes: I) -> impl Iterator<Item = Node>
+    where
+        I: Iterator<Item = Node>,
+    {
+        nodes.filter(move |node| self.matches(node))
+    }
+}
+
+/*
+    Convert a glob to a regex: "**" matches anything, "*" and "?" do not
+    match "/", [...] classes are kept and [!...] is a negated class
+*/
+pub fn glob_to_regex(glob: &str) -> Result<Regex, regex::Error> {
+    let mut pattern = String::from("^");
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => {
+                if chars.peek() == Some(&'*') {
+                    chars.next();
+                    pattern.push_str(".*");
+                } else {
+                    pattern.push_str("[^/]*");
+                }
+            }
+            '?' => pattern.push_str("[^/]"),
+            '[' => {
+                pattern.push('[');
+                // [!...] is the negation of a glob class
+                if chars.next_if(|c| *c == '!' || *c == '^').is_some() {
+                    pattern.push('^');
+                }
+                // A ']' first in the class is a literal
+                if chars.next_if_eq(&']').is_some() {
+                    pattern.push_str("\\]");
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' || c == '&' || c == '~' {
+                        pattern.push('\\');
+                    }
+                    pattern.push(c);
+                }
+                pattern.push(']');
+            }
+            _ => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    Regex::new(&pattern)
+}
@@ -0,0 +1,392 @@
+use crate::core::query::{glob_to_regex, Expr, Perm, Predicate, QueryError};
+
+use regex::Regex;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    // key:value predicate
+    Term(String),
+}
+
+/*
+    Split a query in tokens, values can be quoted to keep spaces or
+    parentheses (ex: name:"my file")
+*/
+fn tokenize(query: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == '(' || c == ')' {
+            chars.next();
+            tokens.push(if c == '(' { Token::LParen } else { Token::RParen });
+            continue;
+        }
+
+        let mut word = String::new();
+        let mut quoted = false;
+        while let Some(&c) = chars.peek() {
+            if c == '"' {
+                chars.next();
+                quoted = !quoted;
+                continue;
+            }
+            if !quoted && (c.is_whitespace() || c == '(' || c == ')') {
+                break;
+            }
+            if quoted && c == '\\' {
+                chars.next();
+                if let Some(escaped) = chars.next() {
+                    word.push(escaped);
+                }
+                continue;
+            }
+            word.push(c);
+            chars.next();
+        }
+        if quoted {
+            return Err(QueryError {
+                pos: tokens.len(),
+                msg: "unterminated quote".to_string(),
+            });
+        }
+
+        let token = match word.to_lowercase().as_str() {
+            "and" | "&&" => Token::And,
+            "or" | "||" => Token::Or,
+            "not" | "!" => Token::Not,
+            _ => Token::Term(word),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/*
+    Recursive descent parser:
+        or   := and ("or" and)*
+        and  := not ("and"? not)*
+        not  := "not" not | atom
+        atom := "(" or ")" | term
+*/
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, msg: &str) -> QueryError {
+        QueryError {
+            pos: self.pos,
+            msg: msg.to_string(),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = expr.or(self.parse_and()?);
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_not()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.pos += 1,
+                // Implicit "and" between adjacent predicates
+                Some(Token::Not) | Some(Token::LParen) | Some(Token::Term(_)) => {}
+                _ => break,
+            }
+            expr = expr.and(self.parse_not()?);
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, QueryError> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(!self.parse_not()?);
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr, QueryError> {
+        match self.peek().cloned() {
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let expr = self.parse_or()?;
+                if self.peek() != Some(&Token::RParen) {
+                    return Err(self.error("expected ')'"));
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            Some(Token::Term(term)) => {
+                let predicate = parse_predicate(&term).map_err(|msg| self.error(&msg))?;
+                self.pos += 1;
+                Ok(Expr::Pred(predicate))
+            }
+            Some(_) => Err(self.error("unexpected operator")),
+            None => Err(self.error("unexpected end of query")),
+        }
+    }
+}
+
+pub fn parse(query: &str) -> Result<Expr, QueryError> {
+    let tokens = tokenize(query)?;
+    if tokens.is_empty() {
+        return Ok(Expr::All);
+    }
+
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.parse_or()?;
+    if parser.pos != parser.tokens.len() {
+        return Err(parser.error("unexpected token"));
+    }
+    Ok(expr)
+}
+
+/*
+    Parse a key:value term in a predicate
+*/
+pub fn parse_predicate(term: &str) -> Result<Predicate, String> {
+    let (key, value) = match term.split_once(':') {
+        Some((key, value)) => (key.to_lowercase(), value),
+        None => return Err(format!("expected <key>:<value>, got \"{}\"", term)),
+    };
+    if value.is_empty() {
+        return Err(format!("empty value for \"{}\"", key));
+    }
+
+    let glob = |value: &str| glob_to_regex(value).map_err(|e| e.to_string());
+    let regex = |value: &str| Regex::new(value).map_err(|e| e.to_string());
+    let number = |value: &str| value.parse::<u32>().map_err(|_| format!("invalid number \"{}\"", value));
+
+    let predicate = match key.as_str() {
+        "name" => Predicate::NameGlob(glob(value)?),
+        "path" => Predicate::PathGlob(glob(value)?),
+        "name~" => Predicate::NameRegex(regex(value)?),
+        "path~" => Predicate::PathRegex(regex(value)?),
+        "type" => Predicate::Type(value.to_lowercase()),
+        "size" => {
+            let (min, max) = parse_size_range(value)?;
+            Predicate::Size { min, max }
+        }
+        "perm" => Predicate::Perm(parse_perm(value)?),
+        "uid" | "owner" => Predicate::Uid(number(value)?),
+        "gid" | "group" => Predicate::Gid(number(value)?),
+        "hash" => Predicate::Hash(value.to_lowercase()),
+        "machine" => Predicate::ElfMachine(value.to_lowercase()),
+        "pie" => match value.to_lowercase().as_str() {
+            "true" | "yes" | "1" => Predicate::ElfPie(true),
+            "false" | "no" | "0" => Predicate::ElfPie(false),
+            _ => return Err(format!("invalid boolean \"{}\"", value)),
+        },
+        "imports" => Predicate::Imports(glob(value)?),
+        "cap" => {
+            let name = value.to_lowercase();
+            if name.starts_with("cap_") {
+                Predicate::Capability(name)
+            } else {
+                Predicate::Capability(format!("cap_{}", name))
+            }
+        }
+        "tag" => Predicate::Tag(value.to_string()),
+        _ => return Err(format!("unknown key \"{}\"", key)),
+    };
+    Ok(predicate)
+}
+
+/*
+    Size with an optional K/M/G unit (powers of 1024)
+*/
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (digits, unit) = match value.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => value.split_at(i),
+        None => (value, ""),
+    };
+    let size: u64 = digits.parse().map_err(|_| format!("invalid size \"{}\"", value))?;
+
+    let multiplier = match unit.to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1 << 10,
+        "m" | "mb" => 1 << 20,
+        "g" | "gb" => 1 << 30,
+        _ => return Err(format!("invalid size unit \"{}\"", unit)),
+    };
+    size.checked_mul(multiplier).ok_or_else(|| format!("size \"{}\" is too large", value))
+}
+
+/*
+    >N, >=N, <N, <=N, N..M or N (exact size), bounds returned are inclusive
+*/
+fn parse_size_range(value: &str) -> Result<(u64, u64), String> {
+    if let Some(rest) = value.strip_prefix(">=") {
+        Ok((parse_size(rest)?, u64::MAX))
+    } else if let Some(rest) = value.strip_prefix("<=") {
+        Ok((0, parse_size(rest)?))
+    } else if let Some(rest) = value.strip_prefix('>') {
+        Ok((parse_size(rest)?.saturating_add(1), u64::MAX))
+    } else if let Some(rest) = value.strip_prefix('<') {
+        let max = parse_size(rest)?;
+        if max == 0 {
+            return Err("empty size range".to_string());
+        }
+        Ok((0, max - 1))
+    } else if let Some((min, max)) = value.split_once("..") {
+        Ok((parse_size(min)?, parse_size(max)?))
+    } else {
+        let size = parse_size(value)?;
+        Ok((size, size))
+    }
+}
+
+fn parse_perm(value: &str) -> Result<Perm, String> {
+    let octal = |value: &str| u32::from_str_radix(value, 8).map_err(|_| format!("invalid permission \"{}\"", value));
+
+    let perm = match value.to_lowercase().as_str() {
+        "suid" => Perm::Suid,
+        "sgid" => Perm::Sgid,
+        "sticky" => Perm::Sticky,
+        "world-writable" | "ww" => Perm::WorldWritable,
+        "exec" | "x" => Perm::Exec,
+        v => match v.strip_prefix('+') {
+            Some(mask) => Perm::Any(octal(mask)?),
+            None => Perm::Exact(octal(v)?),
+        },
+    };
+    Ok(perm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(value: &str) -> Result<(u64, u64), String> {
+        match parse_predicate(&format!("size:{}", value))? {
+            Predicate::Size { min, max } => Ok((min, max)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn glob_matches(glob: &str, name: &str) -> bool {
+        match parse_predicate(&format!("name:{}", glob)).unwrap() {
+            Predicate::NameGlob(re) => re.is_match(name),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            tokenize(r#"(name:"my file" or ! tag:x) && path:"a\"b""#).unwrap(),
+            vec![
+                Token::LParen,
+                Token::Term("name:my file".to_string()),
+                Token::Or,
+                Token::Not,
+                Token::Term("tag:x".to_string()),
+                Token::RParen,
+                Token::And,
+                Token::Term("path:a\"b".to_string()),
+            ]
+        );
+        assert!(tokenize("name:\"unterminated").is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        // not > and (implicit or explicit) > or
+        let expr = parse("type:elf not perm:suid or tag:a and tag:b").unwrap();
+        match expr {
+            Expr::Or(left, right) => {
+                assert!(matches!(*left, Expr::And(_, ref not) if matches!(**not, Expr::Not(_))));
+                assert!(matches!(*right, Expr::And(_, _)));
+            }
+            _ => panic!("expected an or at the top"),
+        }
+
+        assert!(matches!(parse("").unwrap(), Expr::All));
+        assert!(matches!(parse("(tag:a or tag:b)").unwrap(), Expr::Or(_, _)));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("tag:a or").unwrap_err().pos, 2);
+        assert_eq!(parse("(tag:a").unwrap_err().msg, "expected ')'");
+        assert_eq!(parse("tag:a )").unwrap_err().msg, "unexpected token");
+        assert!(parse("and tag:a").is_err());
+        assert!(parse("tag:").is_err());
+        assert!(parse("color:red").is_err());
+        assert!(parse("pie:maybe").is_err());
+        assert!(parse("uid:-1").is_err());
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(size("10"), Ok((10, 10)));
+        assert_eq!(size("2k"), Ok((2048, 2048)));
+        assert_eq!(size("1MB..1G"), Ok((1 << 20, 1 << 30)));
+        assert_eq!(size(">=1k"), Ok((1024, u64::MAX)));
+        assert_eq!(size(">1k"), Ok((1025, u64::MAX)));
+        assert_eq!(size("<=1k"), Ok((0, 1024)));
+        assert_eq!(size("<1k"), Ok((0, 1023)));
+        assert_eq!(size(&format!(">{}", u64::MAX)), Ok((u64::MAX, u64::MAX)));
+
+        assert!(size("<0").is_err());
+        assert!(size("1t").is_err());
+        assert!(size("k").is_err());
+        assert!(size("-1").is_err());
+        // Overflows of the unit multiplication
+        assert!(size("17179869184g").is_err());
+        assert!(size("99999999999999999999").is_err());
+        assert_eq!(size("17179869183g"), Ok((17179869183 << 30, 17179869183 << 30)));
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("*.so*", "libc.so.6"));
+        assert!(!glob_matches("*.so", "libc.so.6"));
+        assert!(glob_matches("lib?.a", "libc.a"));
+        assert!(glob_matches("[ab]*", "busybox"));
+        assert!(!glob_matches("[ab]*", "sh"));
+        assert!(glob_matches("[a-c].conf", "b.conf"));
+        assert!(glob_matches("a.[!c]", "a.h"));
+        assert!(!glob_matches("a.[!c]", "a.c"));
+        assert!(glob_matches("a.[^c]", "a.h"));
+        assert!(glob_matches("[]]", "]"));
+        assert!(glob_matches("[![]", "a"));
+        assert!(!glob_matches("[![]", "["));
+        assert!(glob_matches("a+b(1).txt", "a+b(1).txt"));
+
+        match parse_predicate("path:/etc/**/*.conf").unwrap() {
+            Predicate::PathGlob(re) => {
+                assert!(re.is_match("/etc/ssh/sshd.conf"));
+                assert!(re.is_match("/etc/a/b/c.conf"));
+                assert!(!re.is_match("/usr/etc/a.conf"));
+            }
+            _ => unreachable!(),
+        }
+        match parse_predicate("path:/etc/*.conf").unwrap() {
+            Predicate::PathGlob(re) => assert!(!re.is_match("/etc/ssh/sshd.conf")),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,682 @@
+use crate::core::error;
+use crate::core::fstree::node::Node;
+use crate::core::fstree::pipeline::{Content, FileConsumer, FileSink, CHUNK_SIZE};
+use crate::core::hash;
+
+use regex::Regex;
+use sha2::{Digest, Sha256};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/*
+    Default rules, in the same format as a rule file
+*/
+const DEFAULT_RULES: &str = r#"
+rule password-assignment (?i)\b(pass(word|wd)?|pwd|secret)\s*[:=]\s*["']?[^\s"']{4,}
+rule aws-access-key \b(AKIA|ASIA)[0-9A-Z]{16}\b
+rule aws-secret-key (?i)aws.{0,20}(secret|key).{0,5}[:=]\s*["']?[0-9a-zA-Z/+]{40}
+rule private-key -----BEGIN ((RSA|DSA|EC|OPENSSH|ENCRYPTED) )?PRIVATE KEY-----
+rule api-token (?i)\b(api[_-]?key|auth[_-]?token|access[_-]?token|token)\s*[:=]\s*["']?[A-Za-z0-9_\-\.]{16,}
+rule github-token \bgh[pousr]_[A-Za-z0-9]{36}\b
+rule connection-string (?i)\b(mysql|postgres(ql)?|mongodb(\+srv)?|redis|amqp|ftp|smb|ldap)://[^\s:/@]+:[^\s@/]+@\S+
+entropy high-entropy-token 4.5 \b[A-Za-z0-9+/=_\-]{32,}\b
+"#;
+
+// Minimum length of a printable string extracted from a binary
+const MIN_STRING_LEN: usize = 8;
+
+#[derive(Debug)]
+pub enum RuleError {
+    Io(io::Error),
+    Syntax { line: usize, msg: String },
+    Regex { line: usize, err: regex::Error },
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleError::Io(err) => write!(f, "cannot read rule file: {}", err),
+            RuleError::Syntax { line, msg } => write!(f, "line {}: {}", line, msg),
+            RuleError::Regex { line, err } => write!(f, "line {}: invalid regex: {}", line, err),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SecretRule {
+    // Name of the rule, reported in the findings
+    pub name: String,
+    // Pattern matched against lines and strings
+    pub regex: Regex,
+    // Minimum Shannon entropy (bits per byte) of the match
+    pub min_entropy: Option<f64>,
+}
+
+/*
+    Set of rules and allowlists used by the secret scanner
+
+    Rule file format, one directive per line:
+        # comment
+        rule <name> <regex>
+        entropy <name> <min entropy> <regex>
+        allow-path <path>       (a trailing '*' matches a prefix)
+        allow-hash <hex digest>  (SHA-256 or any other digest of the file)
+*/
+#[derive(Debug, Clone)]
+pub struct SecretRules {
+    pub rules: Vec<SecretRule>,
+    pub allowed_paths: Vec<String>,
+    // Lowercase hex digests
+    pub allowed_hashes: Vec<String>,
+}
+
+impl SecretRules {
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            allowed_paths: Vec::new(),
+            allowed_hashes: Vec::new(),
+        }
+    }
+
+    pub fn default_rules() -> Self {
+        let mut rules = Self::new();
+        rules.parse(DEFAULT_RULES).unwrap();
+        rules
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, RuleError> {
+        let mut rules = Self::new();
+        rules.extend_from_file(path)?;
+        Ok(rules)
+    }
+
+    /*
+        Add the rules and allowlists of a rule file to the set
+    */
+    pub fn extend_from_file(&mut self, path: &str) -> Result<(), RuleError> {
+        let content = fs::read_to_string(path).map_err(RuleError::Io)?;
+        self.parse(&content)
+    }
+
+    pub fn parse(&mut self, content: &str) -> Result<(), RuleError> {
+        for (i, line) in content.lines().enumerate() {
+            let line_nb = i + 1;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (directive, rest) = split_word(line);
+
+            match directive {
+                "rule" => {
+                    let (name, pattern) = split_word(rest);
+                    let regex = compile(line_nb, name, pattern)?;
+                    self.rules.push(SecretRule {
+                        name: name.to_string(),
+                        regex,
+                        min_entropy: None,
+                    });
+                }
+                "entropy" => {
+                    let (name, rest) = split_word(rest);
+                    let (min_entropy, pattern) = split_word(rest);
+                    let min_entropy = min_entropy.parse::<f64>().map_err(|_| RuleError::Syntax {
+                        line: line_nb,
+                        msg: format!("invalid entropy value '{}'", min_entropy),
+                    })?;
+                    let regex = compile(line_nb, name, pattern)?;
+                    self.rules.push(SecretRule {
+                        name: name.to_string(),
+                        regex,
+                        min_entropy: Some(min_entropy),
+                    });
+                }
+                "allow-path" => {
+                    self.allowed_paths.push(rest.to_string());
+                }
+                "allow-hash" => {
+                    if rest.is_empty() || !rest.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return Err(RuleError::Syntax {
+                            line: line_nb,
+                            msg: format!("invalid hash '{}'", rest),
+                        });
+                    }
+                    self.allowed_hashes.push(rest.to_lowercase());
+                }
+                _ => {
+                    return Err(RuleError::Syntax {
+                        line: line_nb,
+                        msg: format!("unknown directive '{}'", directive),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_path_allowed(&self, fs_path: &str) -> bool {
+        self.allowed_paths.iter().any(|allowed| match allowed.strip_suffix('*') {
+            Some(prefix) => fs_path.starts_with(prefix),
+            None => fs_path == allowed,
+        })
+    }
+
+    pub fn is_hash_allowed(&self, hash: &str) -> bool {
+        self.allowed_hashes.iter().any(|allowed| allowed.eq_ignore_ascii_case(hash))
+    }
+
+    /*
+        Run every rule on a piece of text, return (rule name, match start, matched text)
+    */
+    fn match_rules<'a>(&'a self, text: &'a str) -> Vec<(&'a str, usize, &'a str)> {
+        let mut matches = Vec::new();
+
+        for rule in &self.rules {
+            for m in rule.regex.find_iter(text) {
+                if let Some(min_entropy) = rule.min_entropy {
+                    if shannon_entropy(m.as_str().as_bytes()) < min_entropy {
+                        continue;
+                    }
+                }
+                matches.push((rule.name.as_str(), m.start(), m.as_str()));
+            }
+        }
+        matches
+    }
+}
+
+impl Default for SecretRules {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretLocation {
+    // Line number (starting at 1) in a text file
+    Line(usize),
+    // Byte offset in a binary file
+    Offset(usize),
+}
+
+#[derive(Debug)]
+pub struct SecretMatch {
+    pub location: SecretLocation,
+    pub rule: String,
+    pub matched: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SecretFinding {
+    pub node: Node,
+    pub location: SecretLocation,
+    pub rule: String,
+    pub matched: String,
+}
+
+impl SecretFinding {
+    pub fn new(node: Node, secret_match: SecretMatch) -> Self {
+        Self {
+            node,
+            location: secret_match.location,
+            rule: secret_match.rule,
+            matched: secret_match.matched,
+        }
+    }
+}
+
+impl fmt::Display for SecretFinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let location = match self.location {
+            SecretLocation::Line(line) => format!("line {}", line),
+            SecretLocation::Offset(offset) => format!("offset 0x{:x}", offset),
+        };
+
+        write!(
+            f,
+            "[{}] {} ({}): {}",
+            self.rule, self.node.fs_path(), location, self.matched,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanMode {
+    // Line by line
+    Text,
+    // Printable strings of a binary
+    Strings,
+}
+
+impl ScanMode {
+    fn scan(&self, bytes: &[u8], rules: &SecretRules) -> Vec<SecretMatch> {
+        match self {
+            ScanMode::Text => scan_text(bytes, rules),
+            ScanMode::Strings => scan_strings(bytes, rules),
+        }
+    }
+}
+
+/*
+    File consumer collecting the secrets of the files. In a pipeline, it
+    uses the types and digests of the consumers added before it, the files
+    are sniffed and hashed otherwise. Big files are scanned while they are
+    streamed.
+*/
+pub struct SecretScan {
+    rules: Arc<SecretRules>,
+    findings: Arc<Mutex<Vec<SecretFinding>>>,
+}
+
+impl SecretScan {
+    pub fn new(rules: SecretRules) -> Self {
+        Self {
+            rules: Arc::new(rules),
+            findings: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /*
+        Findings sorted by path, in file order for a path
+    */
+    pub fn findings(&self) -> Vec<SecretFinding> {
+        let mut findings = self.findings.lock().unwrap().clone();
+        findings.sort_by_key(|finding| finding.node.fs_path());
+        findings
+    }
+}
+
+impl FileConsumer for SecretScan {
+    fn name(&self) -> &'static str {
+        "secrets"
+    }
+
+    /*
+        ELF files are scanned through their strings, other files are
+        scanned line by line if they contain text
+    */
+    fn consume(&self, node: &Node, content: Content) -> error::Result<Option<Box<dyn FileSink>>> {
+        if self.rules.is_path_allowed(&node.fs_path()) {
+            return Ok(None);
+        }
+        let mode = if node.is_elf() {
+            ScanMode::Strings
+        } else if node.is_text() || looks_like_text(content.bytes()) {
+            ScanMode::Text
+        } else {
+            return Ok(None);
+        };
+
+        match content {
+            Content::Whole(bytes) => {
+                let matches = mode.scan(bytes, &self.rules);
+                if !matches.is_empty() && !is_hash_allowed(&self.rules, node, || hash::sha256_hex(bytes)) {
+                    report(&self.findings, node, matches);
+                }
+                Ok(None)
+            }
+            Content::Head(bytes) => {
+                let mut sink = SecretSink {
+                    rules: self.rules.clone(),
+                    findings: self.findings.clone(),
+                    mode,
+                    pending: Vec::new(),
+                    position: 0,
+                    matches: Vec::new(),
+                    hasher: Sha256::new(),
+                };
+                sink.update(bytes);
+                Ok(Some(Box::new(sink)))
+            }
+        }
+    }
+
+    fn copy(&self, source: &Node, node: &Node) {
+        let mut findings = self.findings.lock().unwrap();
+        let copies: Vec<SecretFinding> = findings
+            .iter()
+            .filter(|finding| finding.node.fs_path() == source.fs_path())
+            .map(|finding| SecretFinding {
+                node: node.clone(),
+                ..finding.clone()
+            })
+            .collect();
+        findings.extend(copies);
+    }
+}
+
+// Bytes of a cut line (or string) scanned again with the next piece, longer than any secret
+const OVERLAP: usize = 4096;
+
+/*
+    Streamed scan of a big file. The last line (or string) of a chunk may
+    continue in the next one, it is kept until it is complete. A line longer
+    than a chunk is scanned in pieces.
+*/
+struct SecretSink {
+    rules: Arc<SecretRules>,
+    findings: Arc<Mutex<Vec<SecretFinding>>>,
+    mode: ScanMode,
+    pending: Vec<u8>,
+    // Lines (or bytes) before pending
+    position: usize,
+    matches: Vec<SecretMatch>,
+    // Used for the allowlist if the file has no digests
+    hasher: Sha256,
+}
+
+impl SecretSink {
+    fn scan(&mut self, end: usize) {
+        let matches = self.mode.scan(&self.pending[..end], &self.rules);
+        self.matches.extend(matches.into_iter().map(|mut secret_match| {
+            secret_match.location = match secret_match.location {
+                SecretLocation::Line(line) => SecretLocation::Line(line + self.position),
+                SecretLocation::Offset(offset) => SecretLocation::Offset(offset + self.position),
+            };
+            secret_match
+        }));
+    }
+
+    /*
+        Scan the incomplete line (or string) and keep only its last OVERLAP
+        bytes. The secrets starting in them are left to the next scan.
+    */
+    fn scan_piece(&mut self) {
+        let mut cut = self.pending.len() - OVERLAP;
+        // Cut on a character boundary
+        while cut > 0 && (0x80..0xc0).contains(&self.pending[cut]) {
+            cut -= 1;
+        }
+
+        match self.mode {
+            ScanMode::Text => {
+                let text = String::from_utf8_lossy(&self.pending);
+                let scanned = String::from_utf8_lossy(&self.pending[..cut]).len();
+                for (rule, start, matched) in self.rules.match_rules(&text) {
+                    if start < scanned {
+                        self.matches.push(SecretMatch {
+                            location: SecretLocation::Line(self.position + 1),
+                            rule: rule.to_string(),
+                            matched: matched.to_string(),
+                        });
+                    }
+                }
+            }
+            ScanMode::Strings => {
+                for mut secret_match in scan_strings(&self.pending, &self.rules) {
+                    if let SecretLocation::Offset(offset) = secret_match.location {
+                        if offset < cut {
+                            secret_match.location = SecretLocation::Offset(offset + self.position);
+                            self.matches.push(secret_match);
+                        }
+                    }
+                }
+                self.position += cut;
+            }
+        }
+        self.pending.drain(..cut);
+    }
+}
+
+impl FileSink for SecretSink {
+    fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+        self.pending.extend_from_slice(chunk);
+
+        // End of the last complete line or string
+        let end = match self.mode {
+            ScanMode::Text => self.pending.iter().rposition(|b| *b == b'\n'),
+            ScanMode::Strings => self.pending.iter().rposition(|b| !is_printable(b)),
+        };
+        if let Some(end) = end {
+            self.scan(end);
+            self.position += match self.mode {
+                ScanMode::Text => self.pending[..=end].iter().filter(|b| **b == b'\n').count(),
+                ScanMode::Strings => end + 1,
+            };
+            self.pending.drain(..=end);
+        }
+
+        if self.pending.len() > CHUNK_SIZE {
+            self.scan_piece();
+        }
+    }
+
+    fn finish(mut self: Box<Self>, node: &Node) {
+        let end = self.pending.len();
+        self.scan(end);
+
+        let matches = std::mem::take(&mut self.matches);
+        let hasher = self.hasher.clone();
+        if !matches.is_empty() && !is_hash_allowed(&self.rules, node, || hash::hex(&hasher.finalize())) {
+            report(&self.findings, node, matches);
+        }
+    }
+}
+
+/*
+    Digests of the node (SHA-256 of the content if the file was not hashed)
+    found in the allowlist
+*/
+fn is_hash_allowed(rules: &SecretRules, node: &Node, sha256: impl FnOnce() -> String) -> bool {
+    let digests = node.digests();
+    if digests.is_empty() {
+        rules.is_hash_allowed(&sha256())
+    } else {
+        digests.iter().into_iter().any(|(_, digest)| rules.is_hash_allowed(digest))
+    }
+}
+
+fn report(findings: &Mutex<Vec<SecretFinding>>, node: &Node, matches: Vec<SecretMatch>) {
+    let mut findings = findings.lock().unwrap();
+    for secret_match in matches {
+        findings.push(SecretFinding::new(node.clone(), secret_match));
+    }
+}
+
+/*
+    Scan a text file line by line
+*/
+pub fn scan_text(bytes: &[u8], rules: &SecretRules) -> Vec<SecretMatch> {
+    let mut res = Vec::new();
+
+    for (i, line) in bytes.split(|b| *b == b'\n').enumerate() {
+        let line = String::from_utf8_lossy(line);
+
+        for (rule, _, matched) in rules.match_rules(&line) {
+            res.push(SecretMatch {
+                location: SecretLocation::Line(i + 1),
+                rule: rule.to_string(),
+                matched: matched.to_string(),
+            });
+        }
+    }
+    res
+}
+
+/*
+    Scan the printable strings of a binary (like strings(1))
+*/
+pub fn scan_strings(bytes: &[u8], rules: &SecretRules) -> Vec<SecretMatch> {
+    let mut res = Vec::new();
+
+    for (offset, string) in printable_strings(bytes) {
+        for (rule, start, matched) in rules.match_rules(string) {
+            res.push(SecretMatch {
+                location: SecretLocation::Offset(offset + start),
+                rule: rule.to_string(),
+                matched: matched.to_string(),
+            });
+        }
+    }
+    res
+}
+
+/*
+    Consider the content as text if there is no NUL byte in the first 8KB
+*/
+pub fn looks_like_text(bytes: &[u8]) -> bool {
+    let head = &bytes[..bytes.len().min(8192)];
+    !head.contains(&0)
+}
+
+pub fn shannon_entropy(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 0.0;
+    }
+
+    let mut counts = [0usize; 256];
+    for b in bytes {
+        counts[*b as usize] += 1;
+    }
+
+    let len = bytes.len() as f64;
+    counts
+        .iter()
+        .filter(|c| **c > 0)
+        .map(|c| {
+            let p = *c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn is_printable(b: &u8) -> bool {
+    *b == b'\t' || (0x20..0x7f).contains(b)
+}
+
+fn printable_strings(bytes: &[u8]) -> Vec<(usize, &str)> {
+    let mut strings = Vec::new();
+    let mut start = 0;
+
+    for (i, b) in bytes.iter().enumerate() {
+        if !is_printable(b) {
+            if i - start >= MIN_STRING_LEN {
+                // Printable ASCII is always valid UTF-8
+                strings.push((start, std::str::from_utf8(&bytes[start..i]).unwrap()));
+            }
+            start = i + 1;
+        }
+    }
+
+    if bytes.len() - start >= MIN_STRING_LEN {
+        strings.push((start, std::str::from_utf8(&bytes[start..]).unwrap()));
+    }
+    strings
+}
+
+fn split_word(s: &str) -> (&str, &str) {
+    match s.find(char::is_whitespace) {
+        Some(pos) => (&s[..pos], s[pos..].trim_start()),
+        None => (s, ""),
+    }
+}
+
+fn compile(line: usize, name: &str, pattern: &str) -> Result<Regex, RuleError> {
+    if name.is_empty() || pattern.is_empty() {
+        return Err(RuleError::Syntax {
+            line,
+            msg: "expected a rule name and a pattern".to_string(),
+        });
+    }
+    Regex::new(pattern).map_err(|err| RuleError::Regex { line, err })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::fstree::pipeline::run_consumers;
+    use crate::core::fstree::FsTree;
+    use crate::core::testutil::{image, remove};
+
+    fn scan(fstree: &FsTree, rules: &SecretRules, max_map_size: u64) -> Vec<SecretFinding> {
+        let scan = SecretScan::new(rules.clone());
+        let consumers: [&dyn FileConsumer; 1] = [&scan];
+        run_consumers(fstree, &consumers, max_map_size);
+        scan.findings()
+    }
+
+    fn found(findings: &[SecretFinding]) -> Vec<(String, &str, SecretLocation)> {
+        findings
+            .iter()
+            .map(|finding| (finding.node.fs_path(), finding.rule.as_str(), finding.location))
+            .collect()
+    }
+
+    #[test]
+    fn mapped_and_streamed_files() {
+        // The secret line is cut by the end of the first chunk (1MB)
+        let mut text = b"x\n".repeat(524283);
+        text.extend_from_slice(b"password = hunter22\nnothing\ntoken=aaaaaaaaaaaaaaaaaaaa\n");
+        let root = image(
+            "secrets_streamed",
+            &[("etc/app.conf", &text), ("etc/small.conf", b"# db\npassword: s3cr3t!\n")],
+        );
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+        let rules = SecretRules::default_rules();
+
+        for max_map_size in [u64::MAX, 16] {
+            assert_eq!(
+                found(&scan(&fstree, &rules, max_map_size)),
+                vec![
+                    ("/etc/app.conf".to_string(), "password-assignment", SecretLocation::Line(524284)),
+                    ("/etc/app.conf".to_string(), "api-token", SecretLocation::Line(524286)),
+                    ("/etc/small.conf".to_string(), "password-assignment", SecretLocation::Line(2)),
+                ]
+            );
+        }
+
+        remove(&root);
+    }
+
+    #[test]
+    fn long_line_is_scanned_in_pieces() {
+        // Without a newline, the first piece is cut OVERLAP bytes before the end of the second chunk
+        let cut = 2 * CHUNK_SIZE - OVERLAP;
+        let mut text = vec![b'x'; 3 * CHUNK_SIZE];
+        text[cut - 6..cut + 13].copy_from_slice(b" password=hunter22 ");
+        text[cut + 20..cut + 39].copy_from_slice(b" password=hunter33 ");
+        let root = image("secrets_long_line", &[("data.txt", &text)]);
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+        let rules = SecretRules::default_rules();
+
+        for max_map_size in [u64::MAX, 16] {
+            let findings = scan(&fstree, &rules, max_map_size);
+            let matched: Vec<&str> = findings.iter().map(|finding| finding.matched.as_str()).collect();
+            assert_eq!(matched, vec!["password=hunter22", "password=hunter33"]);
+            assert!(findings.iter().all(|finding| finding.location == SecretLocation::Line(1)));
+        }
+
+        remove(&root);
+    }
+
+    #[test]
+    fn allowlists() {
+        let root = image(
+            "secrets_allowed",
+            &[("a.conf", b"password=hunter22\n"), ("skip/b.conf", b"password=hunter22\n")],
+        );
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+
+        let mut rules = SecretRules::default_rules();
+        rules.parse("allow-path /skip/*").unwrap();
+        let findings = scan(&fstree, &rules, u64::MAX);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].node.fs_path(), "/a.conf");
+
+        // Without digests on the node, the SHA-256 of the content is used
+        rules
+            .parse(&format!("allow-hash {}", hash::sha256_hex(b"password=hunter22\n")))
+            .unwrap();
+        assert!(scan(&fstree, &rules, u64::MAX).is_empty());
+        assert!(scan(&fstree, &rules, 4).is_empty());
+
+        remove(&root);
+    }
+}
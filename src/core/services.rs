@@ -0,0 +1,781 @@
+use crate::core::fstree::node::Node;
+use crate::core::fstree::{normalize_path, FsTree};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+// Directories searched for commands without an absolute path
+const PATH_DIRS: [&str; 6] = ["/bin", "/sbin", "/usr/bin", "/usr/sbin", "/usr/local/bin", "/usr/local/sbin"];
+
+// Directories containing the rc.d start/kill symlinks or the rc<runlevel>.d directories
+const RC_DIRS: [&str; 2] = ["/etc", "/etc/rc.d"];
+
+const SYSTEMD_UNIT_DIRS: [&str; 3] = ["/etc/systemd/system", "/lib/systemd/system", "/usr/lib/systemd/system"];
+
+// inittab actions that do not start a process at boot
+const INITTAB_IGNORED_ACTIONS: [&str; 6] = ["initdefault", "ctrlaltdel", "shutdown", "restart", "powerfail", "powerokwait"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BootSource {
+    // Entry of /etc/inittab with its action (sysinit, respawn...)
+    Inittab { action: String },
+    // Init script started through a S<order><name> rc.d symlink
+    RcLink { runlevel: String, order: u32 },
+    // Init script named S<order><name> in /etc/init.d (BusyBox rcS)
+    InitScript { order: u32 },
+    // Instance declared in a procd init script
+    Procd,
+    // Enabled systemd service unit
+    Systemd { unit: String },
+    // Command of /etc/rc.local
+    RcLocal,
+}
+
+#[derive(Debug)]
+pub struct BootService {
+    pub source: BootSource,
+    // File where the service was found (inittab, init script, unit...)
+    pub origin: String,
+    pub command: String,
+    pub args: Vec<String>,
+    // User running the process
+    pub user: String,
+    // Node of the ELF or script started
+    pub node: Option<Node>,
+}
+
+impl fmt::Display for BootService {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let resolved = match &self.node {
+            Some(node) => node.fs_path(),
+            None => "not found".to_string(),
+        };
+
+        write!(
+            f,
+            "{:?} {} {} (user {}, from {}, {})",
+            self.source, self.command, self.args.join(" "), self.user, self.origin, resolved,
+        )
+    }
+}
+
+/*
+    Find every process started at boot in the tree
+*/
+pub fn discover(fstree: &FsTree) -> Vec<BootService> {
+    let mut services = Vec::new();
+
+    services.append(&mut parse_inittab(fstree));
+    services.append(&mut parse_init_scripts(fstree));
+    services.append(&mut parse_systemd(fstree));
+    services.append(&mut parse_rc_local(fstree));
+
+    services
+}
+
+/*
+    Content of a file of the image, symlinks are resolved inside the image
+*/
+fn read_text(fstree: &FsTree, path: &str) -> Option<String> {
+    let bytes = fstree.read_file(path)?;
+    Some(String::from_utf8_lossy(&bytes).to_string())
+}
+
+fn new_service(fstree: &FsTree, source: BootSource, origin: &str, argv: Vec<String>, user: &str) -> Option<BootService> {
+    let mut argv = argv.into_iter();
+    let command = argv.next()?;
+    let node = resolve_command(fstree, &command);
+
+    Some(BootService {
+        source,
+        origin: origin.to_string(),
+        command,
+        args: argv.collect(),
+        user: user.to_string(),
+        node,
+    })
+}
+
+/*
+    Resolve a command to its node, using PATH_DIRS for relative commands.
+    Symlinks are followed (BusyBox applets).
+*/
+pub fn resolve_command(fstree: &FsTree, command: &str) -> Option<Node> {
+    if command.starts_with('/') {
+        fstree.get_follow(command)
+    } else {
+        PATH_DIRS
+            .iter()
+            .find_map(|dir| fstree.get_follow(&format!("{}/{}", dir, command)))
+    }
+}
+
+/*
+    /etc/inittab: <id>:<runlevels>:<action>:<process>
+*/
+fn parse_inittab(fstree: &FsTree) -> Vec<BootService> {
+    let mut services = Vec::new();
+
+    let content = match read_text(fstree, "/etc/inittab") {
+        Some(content) => content,
+        None => return services,
+    };
+
+    for line in content.lines().map(|line| line.trim()) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.splitn(4, ':').collect();
+        if fields.len() != 4 || INITTAB_IGNORED_ACTIONS.contains(&fields[2]) {
+            continue;
+        }
+
+        // A leading '-' asks for a login shell
+        let process = fields[3].trim_start_matches('-');
+        let source = BootSource::Inittab { action: fields[2].to_string() };
+
+        if let Some(service) = new_service(fstree, source, "/etc/inittab", split_command(process), "root") {
+            services.push(service);
+        }
+    }
+    services
+}
+
+/*
+    Parse S<order><name> in a directory name, ignore K (kill) links
+*/
+fn start_order(name: &str) -> Option<u32> {
+    let rest = name.strip_prefix('S')?;
+    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+    if digits.is_empty() {
+        None
+    } else {
+        digits.parse().ok()
+    }
+}
+
+/*
+    List the S<order> scripts of the rc.d directories and of /etc/init.d
+*/
+fn rc_scripts(fstree: &FsTree) -> Vec<(BootSource, String)> {
+    let mut scripts: Vec<(BootSource, String)> = Vec::new();
+
+    for rc_dir in RC_DIRS {
+        let dir_node = match fstree.get_follow(rc_dir) {
+            Some(node) => node,
+            None => continue,
+        };
+
+        // Runlevel directories (rcS.d, rc3.d...) or the directory itself (OpenWrt /etc/rc.d)
+        let mut dirs: Vec<(String, String)> = Vec::new();
+        if rc_dir == "/etc/rc.d" {
+            dirs.push(("S".to_string(), rc_dir.to_string()));
+        }
+        for child in dir_node.childrens() {
+            let name = child.name();
+            let runlevel = name.strip_prefix("rc").and_then(|n| n.strip_suffix(".d"));
+            if let Some(runlevel) = runlevel.filter(|r| !r.is_empty()) {
+                dirs.push((runlevel.to_string(), format!("{}/{}", rc_dir, name)));
+            }
+        }
+
+        for (runlevel, dir) in dirs {
+            let dir_node = match fstree.get_follow(&dir) {
+                Some(node) => node,
+                None => continue,
+            };
+
+            let mut entries: Vec<(u32, String, String)> = Vec::new();
+            for child in dir_node.childrens() {
+                let name = child.name();
+                let order = match start_order(&name) {
+                    Some(order) => order,
+                    None => continue,
+                };
+
+                // Dangling links keep their lexical target to be reported as not found
+                let target = match child.symlink_data() {
+                    Some(symlink_data) => match symlink_data.resolved {
+                        Some(resolved) => resolved,
+                        None if symlink_data.target.starts_with('/') => normalize_path(&symlink_data.target),
+                        None => normalize_path(&format!("{}/{}", dir, symlink_data.target)),
+                    },
+                    None => format!("{}/{}", dir, name),
+                };
+                entries.push((order, name, target));
+            }
+            entries.sort();
+
+            for (order, _, target) in entries {
+                scripts.push((
+                    BootSource::RcLink {
+                        runlevel: runlevel.clone(),
+                        order,
+                    },
+                    target,
+                ));
+            }
+        }
+    }
+
+    // BusyBox rcS starts /etc/init.d/S??* directly
+    if let Some(init_d) = fstree.get_follow("/etc/init.d") {
+        let mut entries: Vec<(u32, String)> = init_d
+            .childrens()
+            .iter()
+            .filter_map(|child| {
+                let name = child.name();
+                start_order(&name).map(|order| (order, format!("/etc/init.d/{}", name)))
+            })
+            .collect();
+        entries.sort();
+
+        for (order, path) in entries {
+            scripts.push((BootSource::InitScript { order }, path));
+        }
+    }
+
+    // A script linked from several places is only started once
+    let mut seen: Vec<String> = Vec::new();
+    scripts.retain(|(_, path)| {
+        if seen.contains(path) {
+            false
+        } else {
+            seen.push(path.clone());
+            true
+        }
+    });
+    scripts
+}
+
+fn parse_init_scripts(fstree: &FsTree) -> Vec<BootService> {
+    let mut services = Vec::new();
+
+    for (source, script_path) in rc_scripts(fstree) {
+        let script_node = fstree.get_follow(&script_path);
+        let content = read_text(fstree, &script_path).unwrap_or_default();
+
+        let mut script_services = if content.contains("USE_PROCD=1") {
+            parse_procd_script(fstree, &script_path, &content)
+        } else {
+            parse_sysv_script(fstree, &source, &script_path, &content)
+        };
+
+        // Nothing more precise found, the script itself is what runs
+        if script_services.is_empty() {
+            script_services.push(BootService {
+                source,
+                origin: script_path.clone(),
+                command: script_path.clone(),
+                args: vec!["start".to_string()],
+                user: "root".to_string(),
+                node: script_node,
+            });
+        }
+        services.append(&mut script_services);
+    }
+    services
+}
+
+/*
+    Join lines ending with a backslash
+*/
+fn logical_lines(content: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        match line.strip_suffix('\\') {
+            Some(start) => {
+                current.push_str(start);
+                current.push(' ');
+            }
+            None => {
+                current.push_str(line);
+                lines.push(current.clone());
+                current.clear();
+            }
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+/*
+    procd: procd_set_param command <argv>, procd_append_param command <args>,
+    procd_set_param user <user>
+*/
+fn parse_procd_script(fstree: &FsTree, script_path: &str, content: &str) -> Vec<BootService> {
+    let mut instances: Vec<(Vec<String>, String)> = Vec::new();
+
+    for argv in script_commands(content) {
+        if argv.len() < 3 {
+            continue;
+        }
+
+        match (argv[0].as_str(), argv[1].as_str()) {
+            ("procd_set_param", "command") => {
+                instances.push((argv[2..].to_vec(), "root".to_string()));
+            }
+            ("procd_append_param", "command") => {
+                if let Some(instance) = instances.last_mut() {
+                    instance.0.extend_from_slice(&argv[2..]);
+                }
+            }
+            ("procd_set_param", "user") => {
+                if let Some(instance) = instances.last_mut() {
+                    instance.1 = argv[2].clone();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    instances
+        .into_iter()
+        .filter_map(|(argv, user)| new_service(fstree, BootSource::Procd, script_path, argv, &user))
+        .collect()
+}
+
+/*
+    SysV scripts: start-stop-daemon -S ... -x <exec> [-c <user>] -- <args>
+    or service_start <argv> (older OpenWrt)
+*/
+fn parse_sysv_script(fstree: &FsTree, source: &BootSource, script_path: &str, content: &str) -> Vec<BootService> {
+    let mut services = Vec::new();
+
+    for argv in script_commands(content) {
+        let pos = match argv.iter().position(|a| a == "start-stop-daemon" || a == "service_start") {
+            Some(pos) => pos,
+            None => continue,
+        };
+
+        if argv[pos] == "service_start" {
+            let command: Vec<String> = argv[pos + 1..].to_vec();
+            if let Some(service) = new_service(fstree, source.clone(), script_path, command, "root") {
+                services.push(service);
+            }
+            continue;
+        }
+
+        let mut starting = false;
+        let mut exec: Option<String> = None;
+        let mut user = "root".to_string();
+        let mut args: Vec<String> = Vec::new();
+
+        let mut it = argv[pos + 1..].iter();
+        while let Some(arg) = it.next() {
+            match arg.as_str() {
+                "-S" | "--start" => starting = true,
+                "-x" | "--exec" | "-a" | "--startas" => exec = it.next().cloned(),
+                "-c" | "--chuid" | "-u" | "--user" => {
+                    if let Some(u) = it.next() {
+                        // user[:group]
+                        user = u.split(':').next().unwrap_or(u).to_string();
+                    }
+                }
+                "--" => {
+                    args = it.by_ref().cloned().collect();
+                }
+                _ => {}
+            }
+        }
+
+        if let (true, Some(exec)) = (starting, exec) {
+            let mut command = vec![exec];
+            command.append(&mut args);
+            if let Some(service) = new_service(fstree, source.clone(), script_path, command, &user) {
+                services.push(service);
+            }
+        }
+    }
+    services
+}
+
+/*
+    Enabled units are linked from the <target>.wants/ directories of the
+    unit directories (/etc/systemd/system for the units enabled by the
+    administrator, /lib/systemd/system for the ones enabled by the package)
+*/
+fn parse_systemd(fstree: &FsTree) -> Vec<BootService> {
+    let mut services = Vec::new();
+    let mut units: Vec<String> = Vec::new();
+
+    for unit_dir in SYSTEMD_UNIT_DIRS {
+        let unit_dir = match fstree.get_follow(unit_dir) {
+            Some(node) => node,
+            None => continue,
+        };
+        for child in unit_dir.childrens() {
+            if !child.name().ends_with(".wants") {
+                continue;
+            }
+            // The .wants directory may itself be a link
+            let wants = match fstree.get_follow(&child.fs_path()) {
+                Some(node) => node,
+                None => continue,
+            };
+            for entry in wants.childrens() {
+                let name = entry.name();
+                if name.ends_with(".service") && !units.contains(&name) {
+                    units.push(name);
+                }
+            }
+        }
+    }
+    units.sort();
+
+    for unit in units {
+        let found = SYSTEMD_UNIT_DIRS.iter().find_map(|dir| {
+            let unit_path = format!("{}/{}", dir, unit);
+            fstree.get_follow(&unit_path).map(|node| (unit_path, node))
+        });
+        let (unit_path, unit_node) = match found {
+            Some(found) => found,
+            None => continue,
+        };
+        // Masked units are links to /dev/null and have no content
+        let content = read_text(fstree, &unit_path).unwrap_or_default();
+
+        let mut user = "root".to_string();
+        let mut exec_starts: Vec<String> = Vec::new();
+        let mut in_service = false;
+
+        for line in logical_lines(&content) {
+            if line.starts_with('[') {
+                in_service = line == "[Service]";
+            } else if in_service {
+                if let Some(value) = line.strip_prefix("User=") {
+                    user = value.trim().to_string();
+                } else if let Some(value) = line.strip_prefix("ExecStart=") {
+                    // Special executable prefixes: @ - : + !
+                    exec_starts.push(value.trim_start_matches(|c| "@-:+!".contains(c)).to_string());
+                }
+            }
+        }
+
+        let origin = unit_node.fs_path();
+        for exec_start in exec_starts {
+            let source = BootSource::Systemd { unit: unit.clone() };
+            if let Some(service) = new_service(fstree, source, &origin, split_command(&exec_start), &user) {
+                services.push(service);
+            }
+        }
+    }
+    services
+}
+
+fn parse_rc_local(fstree: &FsTree) -> Vec<BootService> {
+    let mut services = Vec::new();
+
+    let content = match read_text(fstree, "/etc/rc.local") {
+        Some(content) => content,
+        None => return services,
+    };
+
+    for line in logical_lines(&content) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with("exit") {
+            continue;
+        }
+        if let Some(service) = new_service(fstree, BootSource::RcLocal, "/etc/rc.local", split_command(&line), "root") {
+            services.push(service);
+        }
+    }
+    services
+}
+
+/*
+    Command lines of a shell script. The variables assigned by the script
+    (ex: DAEMON=/usr/sbin/dropbear) are expanded in the lines after the
+    assignment, the other variables are kept as is.
+*/
+fn script_commands(content: &str) -> Vec<Vec<String>> {
+    let mut vars: HashMap<String, String> = HashMap::new();
+    let mut commands = Vec::new();
+
+    for line in logical_lines(content) {
+        let line = expand_vars(&line, &vars);
+        match assignment(&line) {
+            Some((name, value)) => {
+                vars.insert(name, value);
+            }
+            None => commands.push(split_command(&line)),
+        }
+    }
+    commands
+}
+
+/*
+    NAME=value, or export/local/readonly NAME=value. A line with other words
+    is a command run with an environment variable (NAME=value command).
+*/
+fn assignment(line: &str) -> Option<(String, String)> {
+    let line = ["export ", "local ", "readonly "]
+        .iter()
+        .find_map(|keyword| line.strip_prefix(keyword))
+        .unwrap_or(line);
+    let mut words = split_words(line).into_iter();
+    let word = words.next()?;
+    if words.next().is_some() {
+        return None;
+    }
+
+    let (name, value) = word.split_once('=')?;
+    if !is_var_name(name) {
+        return None;
+    }
+    Some((name.to_string(), value.to_string()))
+}
+
+fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+/*
+    Replace $NAME and ${NAME} by the value of the known variables
+*/
+fn expand_vars(line: &str, vars: &HashMap<String, String>) -> String {
+    let mut expanded = String::new();
+    let mut rest = line;
+
+    while let Some(pos) = rest.find('$') {
+        expanded.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+
+        let (name, len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+
+        match vars.get(name) {
+            Some(value) if is_var_name(name) => {
+                expanded.push_str(value);
+                rest = &after[len..];
+            }
+            _ => {
+                expanded.push('$');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/*
+    Split a shell command line into words, handling quotes and dropping the
+    wrappers that do not change the started process
+*/
+pub fn split_command(line: &str) -> Vec<String> {
+    let mut words = split_words(line);
+
+    while let Some(first) = words.first() {
+        if first == "exec" || first == "nohup" || first == "/usr/bin/env" || first.contains('=') {
+            words.remove(0);
+        } else {
+            break;
+        }
+    }
+
+    // Path of a script given relative to the rc.local or init script is kept as is
+    if let Some(first) = words.first_mut() {
+        if Path::new(first.as_str()).is_absolute() {
+            *first = normalize_path(first);
+        }
+    }
+    words
+}
+
+/*
+    Words of a shell line: quotes are removed, a comment or a command
+    separator (; & |) ends the line
+*/
+fn split_words(line: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+
+    for c in line.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.push(c),
+            None => match c {
+                '\'' | '"' => {
+                    quote = Some(c);
+                    in_word = true;
+                }
+                '#' if !in_word => break,
+                ';' | '&' | '|' => {
+                    if in_word {
+                        words.push(word.clone());
+                        word.clear();
+                        in_word = false;
+                    }
+                    break;
+                }
+                c if c.is_whitespace() => {
+                    if in_word {
+                        words.push(word.clone());
+                        word.clear();
+                        in_word = false;
+                    }
+                }
+                _ => {
+                    word.push(c);
+                    in_word = true;
+                }
+            },
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::{image, remove};
+
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn linked_configs_are_read_from_the_image() {
+        let root = image(
+            "services_links",
+            &[
+                ("/usr/share/inittab", b"::sysinit:/sbin/helper start\n"),
+                ("/sbin/helper", b"#!/bin/sh\n"),
+                ("/usr/lib/systemd/system/food.service", b"[Service]\nUser=daemon\nExecStart=-/usr/bin/food -f\n"),
+                ("/usr/bin/food", b"\x7fELF"),
+            ],
+        );
+        fs::create_dir_all(root.join("etc/systemd/system/multi-user.target.wants")).unwrap();
+        symlink("/usr/share/inittab", root.join("etc/inittab")).unwrap();
+        symlink("usr/lib", root.join("lib")).unwrap();
+        symlink(
+            "/lib/systemd/system/food.service",
+            root.join("etc/systemd/system/multi-user.target.wants/food.service"),
+        )
+        .unwrap();
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+
+        let services = discover(&fstree);
+        assert_eq!(services.len(), 2);
+
+        assert_eq!(services[0].source, BootSource::Inittab { action: "sysinit".to_string() });
+        assert_eq!(services[0].command, "/sbin/helper");
+        assert_eq!(services[0].args, ["start"]);
+
+        assert_eq!(services[1].source, BootSource::Systemd { unit: "food.service".to_string() });
+        assert_eq!(services[1].command, "/usr/bin/food");
+        assert_eq!(services[1].user, "daemon");
+        assert_eq!(services[1].origin, "/usr/lib/systemd/system/food.service");
+        assert!(services[1].node.is_some());
+
+        remove(&root);
+    }
+
+    #[test]
+    fn init_script_variables() {
+        let root = image(
+            "services_vars",
+            &[
+                (
+                    "/etc/init.d/S50dropbear",
+                    b"#!/bin/sh\nNAME=dropbear\nDAEMON=/usr/sbin/$NAME\nexport RUNAS=\"nobody\"\n\
+                      PIDFILE=/var/run/${NAME}.pid\n[ -x $DAEMON ] || exit 0\n\
+                      start-stop-daemon -S -q -p $PIDFILE -c $RUNAS -x $DAEMON -- -P $PIDFILE $EXTRA\n",
+                ),
+                (
+                    "/etc/rc.d/S50uhttpd",
+                    b"#!/bin/sh /etc/rc.common\nUSE_PROCD=1\nPROG=/usr/sbin/uhttpd\n\
+                      start_service() {\n\tprocd_open_instance\n\tprocd_set_param command \"$PROG\" -f\n}\n",
+                ),
+                ("/usr/sbin/dropbear", b"\x7fELF"),
+                ("/usr/sbin/uhttpd", b"\x7fELF"),
+            ],
+        );
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+
+        let services = discover(&fstree);
+        let commands: Vec<(&str, &str, Vec<String>)> = services
+            .iter()
+            .map(|service| (service.command.as_str(), service.user.as_str(), service.args.clone()))
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                ("/usr/sbin/uhttpd", "root", vec!["-f".to_string()]),
+                (
+                    "/usr/sbin/dropbear",
+                    "nobody",
+                    vec!["-P".to_string(), "/var/run/dropbear.pid".to_string(), "$EXTRA".to_string()]
+                ),
+            ]
+        );
+        assert!(services.iter().all(|service| service.node.is_some()));
+
+        // An environment prefix is not an assignment
+        assert_eq!(
+            assignment("export DAEMON=/usr/sbin/dropbear"),
+            Some(("DAEMON".to_string(), "/usr/sbin/dropbear".to_string()))
+        );
+        assert_eq!(assignment("LANG=C /usr/sbin/dropbear"), None);
+        assert_eq!(assignment("2X=1"), None);
+
+        remove(&root);
+    }
+
+    #[test]
+    fn units_enabled_by_packages() {
+        let root = image(
+            "services_wants",
+            &[
+                ("/lib/systemd/system/sshd.service", b"[Service]\nExecStart=/usr/sbin/sshd -D\n"),
+                ("/lib/systemd/system/getty.service", b"[Service]\nExecStart=/sbin/agetty tty1\n"),
+                ("/etc/systemd/system/getty.service", b"[Service]\nExecStart=/sbin/agetty -a root tty1\n"),
+            ],
+        );
+        for (dir, unit) in [
+            ("lib/systemd/system/multi-user.target.wants", "sshd.service"),
+            ("lib/systemd/system/getty.target.wants", "getty.service"),
+            ("etc/systemd/system/getty.target.wants", "getty.service"),
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            symlink(format!("../{}", unit), root.join(dir).join(unit)).unwrap();
+        }
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+
+        let services = discover(&fstree);
+        assert_eq!(services.len(), 2);
+        // Enabled twice, started once, from the unit of /etc which overrides the one of /lib
+        assert_eq!(services[0].source, BootSource::Systemd { unit: "getty.service".to_string() });
+        assert_eq!(services[0].origin, "/etc/systemd/system/getty.service");
+        assert_eq!(services[0].args, ["-a", "root", "tty1"]);
+        assert_eq!(services[1].source, BootSource::Systemd { unit: "sshd.service".to_string() });
+        assert_eq!(services[1].command, "/usr/sbin/sshd");
+
+        remove(&root);
+    }
+}
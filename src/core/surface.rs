@@ -0,0 +1,479 @@
+use crate::core::config::uci_values;
+use crate::core::fstree::node::Node;
+use crate::core::fstree::FsTree;
+use crate::core::services::{self, BootService};
+
+use std::fmt;
+
+// Imports of a binary accepting connections
+const SERVER_IMPORTS: [&str; 4] = ["bind", "listen", "accept", "accept4"];
+
+// Imports of a binary reading from the network
+const RECV_IMPORTS: [&str; 4] = ["recv", "recvfrom", "recvmsg", "socket"];
+
+// Ports of the services found in inetd.conf when /etc/services is missing
+const WELL_KNOWN_PORTS: [(&str, u16); 12] = [
+    ("ftp", 21),
+    ("ssh", 22),
+    ("telnet", 23),
+    ("smtp", 25),
+    ("domain", 53),
+    ("tftp", 69),
+    ("http", 80),
+    ("pop3", 110),
+    ("netbios-ssn", 139),
+    ("snmp", 161),
+    ("https", 443),
+    ("microsoft-ds", 445),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Proto {
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Port {
+    pub proto: Proto,
+    pub number: u16,
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.proto {
+            Proto::Tcp => write!(f, "{}/tcp", self.number),
+            Proto::Udp => write!(f, "{}/udp", self.number),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SurfaceEntry {
+    // Name of the binary (or of the inetd service)
+    pub name: String,
+    // Node of the binary
+    pub node: Option<Node>,
+    // Ports the binary is expected to listen on
+    pub ports: Vec<Port>,
+    // Started at boot
+    pub at_boot: bool,
+    // User running the binary when started at boot
+    pub user: Option<String>,
+    // Network related imports
+    pub imports: Vec<String>,
+    // Why the binary is in the report
+    pub evidence: Vec<String>,
+    pub score: u32,
+}
+
+impl SurfaceEntry {
+    fn new(name: &str, node: Option<Node>) -> Self {
+        Self {
+            name: name.to_string(),
+            node,
+            ports: Vec::new(),
+            at_boot: false,
+            user: None,
+            imports: Vec::new(),
+            evidence: Vec::new(),
+            score: 0,
+        }
+    }
+
+    fn add_port(&mut self, proto: Proto, number: u16, evidence: String) {
+        let port = Port { proto, number };
+        if !self.ports.contains(&port) {
+            self.ports.push(port);
+            self.evidence.push(evidence);
+        }
+    }
+
+    fn is_server(&self) -> bool {
+        self.imports.iter().any(|i| SERVER_IMPORTS.contains(&i.as_str()))
+    }
+
+    /*
+        Started at boot and listening services rank first, root adds weight
+    */
+    fn compute_score(&mut self) {
+        let mut score = 0;
+
+        if self.at_boot {
+            score += 40;
+        }
+        if self.user.as_deref() == Some("root") {
+            score += 20;
+        }
+        if self.is_server() {
+            score += 20;
+        } else if !self.imports.is_empty() {
+            score += 10;
+        }
+        score += 15 * self.ports.len().min(4) as u32;
+
+        self.score = score;
+    }
+}
+
+impl fmt::Display for SurfaceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = match &self.node {
+            Some(node) => node.fs_path(),
+            None => "?".to_string(),
+        };
+        let ports: Vec<String> = self.ports.iter().map(|p| p.to_string()).collect();
+
+        write!(
+            f,
+            "{:>3} {} ({}) ports [{}] boot={} user={} imports [{}]",
+            self.score,
+            self.name,
+            path,
+            ports.join(", "),
+            self.at_boot,
+            self.user.as_deref().unwrap_or("-"),
+            self.imports.join(", "),
+        )
+    }
+}
+
+pub struct AttackSurface {
+    // Entries ranked by score
+    pub entries: Vec<SurfaceEntry>,
+}
+
+impl AttackSurface {
+    /*
+        ELF imports are only known once FsTree::analyse_binaries has run
+    */
+    pub fn new(fstree: &FsTree) -> Self {
+        let mut entries: Vec<SurfaceEntry> = Vec::new();
+        let boot_services = services::discover(fstree);
+
+        for service in &boot_services {
+            let name = basename(&service.command);
+            let entry = find_or_insert(&mut entries, &name, service.node.clone());
+            entry.at_boot = true;
+            entry.user = Some(service.user.clone());
+            entry.evidence.push(format!("started at boot from {}", service.origin));
+            add_daemon_ports(fstree, entry, &name, service);
+        }
+
+        add_inetd_services(fstree, &mut entries);
+
+        // Binaries using the socket API which are not started at boot
+        for node in fstree.elf_files() {
+            let imports = network_imports(&node);
+            if !imports.iter().any(|i| SERVER_IMPORTS.contains(&i.as_str())) {
+                continue;
+            }
+            // Already found as the binary of started commands (BusyBox applets)
+            let fs_path = node.fs_path();
+            let mut started = entries
+                .iter_mut()
+                .filter(|e| e.node.as_ref().is_some_and(|n| n.fs_path() == fs_path))
+                .peekable();
+            if started.peek().is_none() {
+                let entry = find_or_insert(&mut entries, &node.name(), Some(node.clone()));
+                entry.evidence.push("imports the socket server API".to_string());
+            } else {
+                started.for_each(|entry| entry.evidence.push("imports the socket server API".to_string()));
+            }
+        }
+
+        for entry in entries.iter_mut() {
+            if let Some(node) = &entry.node {
+                entry.imports = network_imports(node);
+            }
+            entry.ports.sort();
+            entry.compute_score();
+        }
+
+        // Keep only what can be reached from the network
+        entries.retain(|e| !e.ports.is_empty() || !e.imports.is_empty());
+        entries.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+
+        Self { entries }
+    }
+
+    pub fn display(&self) {
+        for entry in &self.entries {
+            println!("{}", entry);
+        }
+    }
+}
+
+fn basename(path: &str) -> String {
+    path.rsplit('/').next().unwrap_or(path).to_string()
+}
+
+fn network_imports(node: &Node) -> Vec<String> {
+    node.elf_imports()
+        .into_iter()
+        .filter(|i| SERVER_IMPORTS.contains(&i.as_str()) || RECV_IMPORTS.contains(&i.as_str()))
+        .collect()
+}
+
+/*
+    Entries are merged by command name and binary: the applets of a
+    multi-call binary (BusyBox telnetd and ftpd) are different entries
+*/
+fn find_or_insert<'a>(entries: &'a mut Vec<SurfaceEntry>, name: &str, node: Option<Node>) -> &'a mut SurfaceEntry {
+    let fs_path = node.as_ref().map(|n| n.fs_path());
+
+    let pos = entries
+        .iter()
+        .position(|e| e.name == name && e.node.as_ref().map(|n| n.fs_path()) == fs_path);
+
+    match pos {
+        Some(pos) => &mut entries[pos],
+        None => {
+            entries.push(SurfaceEntry::new(name, node));
+            entries.last_mut().unwrap()
+        }
+    }
+}
+
+/*
+    Value of a "-p <port>" argument, a port may be given as [addr:]port
+*/
+fn port_arg(args: &[String], flag: &str) -> Vec<u16> {
+    let mut ports = Vec::new();
+    let mut it = args.iter();
+
+    while let Some(arg) = it.next() {
+        let value = if arg == flag {
+            it.next().map(|v| v.as_str())
+        } else {
+            arg.strip_prefix(flag).filter(|v| !v.is_empty())
+        };
+
+        if let Some(port) = value.and_then(|v| v.rsplit(':').next()).and_then(|v| v.parse().ok()) {
+            ports.push(port);
+        }
+    }
+    ports
+}
+
+/*
+    Get the value of a "key = value" or "key=value" line
+*/
+fn config_values(content: &str, key: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let (k, v) = line.split_once('=')?;
+            if k.trim() == key {
+                Some(v.trim().trim_matches(|c| c == '\'' || c == '"').to_string())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn parse_port(value: &str) -> Option<u16> {
+    value.rsplit(':').next()?.trim().parse().ok()
+}
+
+fn add_daemon_ports(fstree: &FsTree, entry: &mut SurfaceEntry, name: &str, service: &BootService) {
+    let read = |path: &str| fstree.read_file(path).map(|b| String::from_utf8_lossy(&b).to_string());
+    let args = &service.args;
+
+    match name {
+        "dropbear" => {
+            let mut ports = port_arg(args, "-p");
+            if let Some(config) = read("/etc/config/dropbear") {
+                ports.extend(uci_values(&config, "Port").iter().filter_map(|p| parse_port(p)));
+            }
+            if let Some(config) = read("/etc/default/dropbear") {
+                ports.extend(config_values(&config, "DROPBEAR_PORT").iter().filter_map(|p| parse_port(p)));
+            }
+            if ports.is_empty() {
+                ports.push(22);
+            }
+            for port in ports {
+                entry.add_port(Proto::Tcp, port, format!("dropbear listens on {}", port));
+            }
+        }
+        "telnetd" | "utelnetd" => {
+            let mut ports = port_arg(args, "-p");
+            if ports.is_empty() {
+                ports.push(23);
+            }
+            for port in ports {
+                entry.add_port(Proto::Tcp, port, format!("telnetd listens on {}", port));
+            }
+        }
+        "lighttpd" => {
+            let config_path = args
+                .iter()
+                .position(|a| a == "-f")
+                .and_then(|i| args.get(i + 1))
+                .cloned()
+                .unwrap_or_else(|| "/etc/lighttpd/lighttpd.conf".to_string());
+            let config = read(&config_path).unwrap_or_default();
+
+            let mut ports: Vec<u16> = config_values(&config, "server.port")
+                .iter()
+                .filter_map(|p| parse_port(p))
+                .collect();
+            // $SERVER["socket"] == ":443"
+            for line in config.lines().filter(|l| l.contains("$SERVER[\"socket\"]")) {
+                if let Some(port) = line.rsplit('"').nth(1).and_then(parse_port) {
+                    ports.push(port);
+                }
+            }
+            if ports.is_empty() {
+                ports.push(80);
+            }
+            for port in ports {
+                entry.add_port(Proto::Tcp, port, format!("lighttpd listens on {} ({})", port, config_path));
+            }
+        }
+        "uhttpd" => {
+            let mut ports = port_arg(args, "-p");
+            ports.extend(port_arg(args, "-s"));
+            if let Some(config) = read("/etc/config/uhttpd") {
+                for option in ["listen_http", "listen_https"] {
+                    ports.extend(uci_values(&config, option).iter().filter_map(|p| parse_port(p)));
+                }
+            }
+            for port in ports {
+                entry.add_port(Proto::Tcp, port, format!("uhttpd listens on {}", port));
+            }
+        }
+        "dnsmasq" => {
+            let config = read("/etc/dnsmasq.conf").unwrap_or_default();
+            let mut dns_port = 53;
+            if let Some(port) = config_values(&config, "port").iter().find_map(|p| parse_port(p)) {
+                dns_port = port;
+            }
+            if let Some(port) = port_arg(args, "-p").first() {
+                dns_port = *port;
+            }
+
+            // Port 0 disables the DNS server
+            if dns_port != 0 {
+                entry.add_port(Proto::Udp, dns_port, format!("dnsmasq DNS on {}", dns_port));
+                entry.add_port(Proto::Tcp, dns_port, format!("dnsmasq DNS on {}", dns_port));
+            }
+            if config.lines().any(|l| l.trim_start().starts_with("dhcp-range")) {
+                entry.add_port(Proto::Udp, 67, "dnsmasq DHCP server".to_string());
+            }
+        }
+        _ => {}
+    }
+}
+
+/*
+    /etc/inetd.conf: <service> <socket type> <proto> <wait> <user> <server> <args>
+*/
+fn add_inetd_services(fstree: &FsTree, entries: &mut Vec<SurfaceEntry>) {
+    let content = match fstree.read_file("/etc/inetd.conf") {
+        Some(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        None => return,
+    };
+    let services_db = fstree
+        .read_file("/etc/services")
+        .map(|b| String::from_utf8_lossy(&b).to_string())
+        .unwrap_or_default();
+
+    for line in content.lines().map(|l| l.trim()) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() < 6 {
+            continue;
+        }
+
+        let proto = if words[2].starts_with("udp") { Proto::Udp } else { Proto::Tcp };
+        let server = words[5];
+        let node = services::resolve_command(fstree, server);
+
+        let entry = find_or_insert(entries, &basename(server), node);
+        entry.at_boot = true;
+        entry.user = Some(words[4].split(['.', ':']).next().unwrap_or(words[4]).to_string());
+
+        match service_port(&services_db, words[0], proto) {
+            Some(port) => entry.add_port(proto, port, format!("inetd service {}", words[0])),
+            None => entry.evidence.push(format!("inetd service {} (unknown port)", words[0])),
+        }
+    }
+}
+
+fn service_port(services_db: &str, service: &str, proto: Proto) -> Option<u16> {
+    if let Ok(port) = service.parse() {
+        return Some(port);
+    }
+
+    let proto_name = match proto {
+        Proto::Tcp => "tcp",
+        Proto::Udp => "udp",
+    };
+
+    // <name> <port>/<proto> [aliases...]
+    for line in services_db.lines() {
+        let words: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+        if words.len() < 2 || (words[0] != service && !words[2..].contains(&service)) {
+            continue;
+        }
+        if let Some((port, p)) = words[1].split_once('/') {
+            if p == proto_name {
+                return port.parse().ok();
+            }
+        }
+    }
+
+    WELL_KNOWN_PORTS.iter().find(|(name, _)| *name == service).map(|(_, port)| *port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::{image, remove};
+
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn busybox_applets_are_separate_entries() {
+        let root = image(
+            "surface_applets",
+            &[
+                ("bin/busybox", b"busybox\n"),
+                ("etc/inittab", b"::respawn:/usr/sbin/telnetd -F -p 2323\n"),
+                ("etc/inetd.conf", b"ftp stream tcp nowait root /usr/sbin/ftpd ftpd -w\n"),
+            ],
+        );
+        fs::create_dir_all(root.join("usr/sbin")).unwrap();
+        symlink("/bin/busybox", root.join("usr/sbin/telnetd")).unwrap();
+        symlink("../../bin/busybox", root.join("usr/sbin/ftpd")).unwrap();
+        let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+
+        let surface = AttackSurface::new(&fstree);
+        let mut entries: Vec<(&str, String, Vec<String>)> = surface
+            .entries
+            .iter()
+            .map(|e| {
+                let ports = e.ports.iter().map(|p| p.to_string()).collect();
+                (e.name.as_str(), e.node.as_ref().unwrap().fs_path(), ports)
+            })
+            .collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![
+                ("ftpd", "/bin/busybox".to_string(), vec!["21/tcp".to_string()]),
+                ("telnetd", "/bin/busybox".to_string(), vec!["2323/tcp".to_string()]),
+            ]
+        );
+
+        remove(&root);
+    }
+}
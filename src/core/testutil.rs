@@ -0,0 +1,34 @@
+use crate::core::fstree::pass::PassRegistry;
+use crate::core::fstree::FsTree;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/*
+    Directory of the system temp dir filled with files (path, content), the
+    parent directories are created
+*/
+pub fn image(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("fs_analyzer_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    for (path, content) in files {
+        let path = root.join(path.trim_start_matches('/'));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    root
+}
+
+/*
+    Tree of an image with the default passes run
+*/
+pub fn tree(root: &Path) -> FsTree {
+    let fstree = FsTree::build_from_path(root.to_str().unwrap()).unwrap();
+    fstree.run_passes(&mut PassRegistry::with_default_passes()).unwrap();
+    fstree
+}
+
+pub fn remove(root: &Path) {
+    let _ = fs::remove_dir_all(root);
+}
@@ -0,0 +1,551 @@
+use crate::core::config::uci_values;
+use crate::core::file::FileType;
+use crate::core::fstree::node::Node;
+use crate::core::fstree::FsTree;
+use crate::core::services;
+
+use regex::Regex;
+
+use std::fmt;
+use std::fs;
+use std::io::Read;
+
+// Web roots used when no server config gives one
+const DEFAULT_WEB_ROOTS: [&str; 6] = ["/www", "/var/www", "/usr/www", "/htdocs", "/web", "/home/httpd"];
+
+#[derive(Debug, Clone)]
+pub struct WebRoot {
+    // Server using the web root (lighttpd, uhttpd...)
+    pub server: String,
+    // Config file declaring the web root
+    pub source: String,
+    // In-image path of the document root
+    pub root: String,
+    // CGI directories: (URL prefix, in-image directory)
+    pub cgi_dirs: Vec<(String, String)>,
+}
+
+impl WebRoot {
+    fn new(server: &str, source: &str, root: &str) -> Self {
+        Self {
+            server: server.to_string(),
+            source: source.to_string(),
+            root: trim_dir(root),
+            cgi_dirs: Vec::new(),
+        }
+    }
+
+    fn add_cgi_dir(&mut self, url: &str, dir: &str) {
+        let entry = (trim_dir(url), trim_dir(dir));
+        if !self.cgi_dirs.contains(&entry) {
+            self.cgi_dirs.push(entry);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handler {
+    Static,
+    Php,
+    Lua,
+    // Shell script run as CGI
+    Shell,
+    // Native CGI binary
+    CgiBinary,
+    Asp,
+}
+
+#[derive(Debug)]
+pub struct WebRoute {
+    pub url: String,
+    pub node: Node,
+    pub handler: Handler,
+}
+
+#[derive(Debug)]
+pub struct SinkFinding {
+    pub node: Node,
+    pub url: Option<String>,
+    pub line: usize,
+    // Dangerous function called
+    pub sink: String,
+    // Request parameter or tainted variable reaching the sink
+    pub source: String,
+    pub code: String,
+}
+
+impl fmt::Display for SinkFinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{} ({}) {} <- {}: {}",
+            self.node.fs_path(),
+            self.line,
+            self.url.as_deref().unwrap_or("-"),
+            self.sink,
+            self.source,
+            self.code.trim(),
+        )
+    }
+}
+
+pub struct WebAnalysis {
+    pub roots: Vec<WebRoot>,
+    pub routes: Vec<WebRoute>,
+    pub findings: Vec<SinkFinding>,
+}
+
+impl WebAnalysis {
+    pub fn new(fstree: &FsTree) -> Self {
+        let roots = find_web_roots(fstree);
+
+        let mut routes: Vec<WebRoute> = Vec::new();
+        for root in &roots {
+            map_routes(fstree, root, &mut routes);
+        }
+
+        let mut findings = Vec::new();
+        for route in &routes {
+            let language = match route.handler {
+                Handler::Php => Language::php(),
+                Handler::Lua => Language::lua(),
+                Handler::Shell => Language::shell(),
+                _ => continue,
+            };
+            findings.append(&mut scan_handler(&route.node, Some(&route.url), &language));
+        }
+
+        Self {
+            roots,
+            routes,
+            findings,
+        }
+    }
+
+    pub fn display(&self) {
+        for root in &self.roots {
+            println!("{} root {} ({})", root.server, root.root, root.source);
+            for (url, dir) in &root.cgi_dirs {
+                println!("    cgi {} -> {}", url, dir);
+            }
+        }
+        for route in &self.routes {
+            if route.handler != Handler::Static {
+                println!("{:?} {} -> {}", route.handler, route.url, route.node.fs_path());
+            }
+        }
+        for finding in &self.findings {
+            println!("{}", finding);
+        }
+    }
+}
+
+fn trim_dir(path: &str) -> String {
+    let path = path.trim().trim_matches(|c| c == '"' || c == '\'' || c == ';');
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.is_empty() {
+        "/".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+fn read_string(fstree: &FsTree, path: &str) -> Option<String> {
+    fstree.read_file(path).map(|bytes| String::from_utf8_lossy(&bytes).to_string())
+}
+
+/*
+    Find the document roots and the CGI directories from the server configs
+*/
+pub fn find_web_roots(fstree: &FsTree) -> Vec<WebRoot> {
+    let mut roots: Vec<WebRoot> = Vec::new();
+
+    // lighttpd: server.document-root = "/www", alias.url += ( "/cgi-bin/" => "/www/cgi-bin/" )
+    let lighttpd_conf = "/etc/lighttpd/lighttpd.conf";
+    if let Some(config) = read_string(fstree, lighttpd_conf) {
+        let alias_re = Regex::new(r#""(/[^"]*)"\s*=>\s*"(/[^"]*)""#).unwrap();
+
+        for line in config.lines().map(|l| l.trim()).filter(|l| !l.starts_with('#')) {
+            if let Some((key, value)) = line.split_once('=') {
+                if key.trim() == "server.document-root" {
+                    roots.push(WebRoot::new("lighttpd", lighttpd_conf, value));
+                }
+            }
+        }
+        if let Some(root) = roots.iter_mut().find(|r| r.server == "lighttpd") {
+            for line in config.lines().filter(|l| l.contains("alias.url")) {
+                for cap in alias_re.captures_iter(line) {
+                    if cap[1].contains("cgi") {
+                        root.add_cgi_dir(&cap[1], &cap[2]);
+                    }
+                }
+            }
+        }
+    }
+
+    // uhttpd: option home '/www', option cgi_prefix '/cgi-bin', lua_prefix '/luci=...'
+    let uhttpd_conf = "/etc/config/uhttpd";
+    if let Some(config) = read_string(fstree, uhttpd_conf) {
+        for home in uci_values(&config, "home") {
+            let mut root = WebRoot::new("uhttpd", uhttpd_conf, &home);
+            for prefix in uci_values(&config, "cgi_prefix") {
+                let cgi_dir = format!("{}{}", root.root, prefix);
+                root.add_cgi_dir(&prefix, &cgi_dir);
+            }
+            roots.push(root);
+        }
+    }
+
+    // boa and apache: DocumentRoot /www, ScriptAlias /cgi-bin/ /www/cgi-bin/
+    let httpd_confs = [
+        ("boa", "/etc/boa/boa.conf"),
+        ("apache", "/etc/httpd/conf/httpd.conf"),
+        ("apache", "/etc/apache2/apache2.conf"),
+        ("apache", "/etc/apache2/sites-enabled/000-default.conf"),
+    ];
+    for (server, path) in httpd_confs {
+        let config = match read_string(fstree, path) {
+            Some(config) => config,
+            None => continue,
+        };
+
+        let mut root: Option<WebRoot> = None;
+        let mut script_aliases: Vec<(String, String)> = Vec::new();
+        for line in config.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["DocumentRoot", dir, ..] => root = Some(WebRoot::new(server, path, dir)),
+                ["ScriptAlias", url, dir, ..] => script_aliases.push((url.to_string(), dir.to_string())),
+                _ => {}
+            }
+        }
+        if let Some(mut root) = root {
+            for (url, dir) in script_aliases {
+                root.add_cgi_dir(&url, &dir);
+            }
+            roots.push(root);
+        }
+    }
+
+    // nginx: root /www;
+    let nginx_conf = "/etc/nginx/nginx.conf";
+    if let Some(config) = read_string(fstree, nginx_conf) {
+        for line in config.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if let ["root", dir, ..] = words.as_slice() {
+                roots.push(WebRoot::new("nginx", nginx_conf, dir));
+            }
+        }
+    }
+
+    // BusyBox httpd gets its home from the command line (httpd -h /www)
+    for service in services::discover(fstree) {
+        if !service.command.ends_with("httpd") || service.command.ends_with("uhttpd") {
+            continue;
+        }
+        if let Some(pos) = service.args.iter().position(|a| a == "-h") {
+            if let Some(dir) = service.args.get(pos + 1) {
+                roots.push(WebRoot::new("httpd", &service.origin, dir));
+            }
+        }
+    }
+
+    if roots.is_empty() {
+        for dir in DEFAULT_WEB_ROOTS {
+            if fstree.get_follow(dir).map(|n| n.is_dir()).unwrap_or(false) {
+                roots.push(WebRoot::new("unknown", "default location", dir));
+            }
+        }
+    }
+
+    // A conventional cgi-bin directory is served by most embedded servers
+    for root in roots.iter_mut() {
+        let cgi_bin = format!("{}/cgi-bin", root.root.trim_end_matches('/'));
+        if root.cgi_dirs.is_empty() && fstree.get_follow(&cgi_bin).is_some() {
+            root.add_cgi_dir("/cgi-bin", &cgi_bin);
+        }
+    }
+
+    // Several servers may share the same document root
+    let mut unique: Vec<WebRoot> = Vec::new();
+    for root in roots {
+        if !unique.iter().any(|r| r.root == root.root) {
+            unique.push(root);
+        }
+    }
+    unique
+}
+
+fn map_routes(fstree: &FsTree, root: &WebRoot, routes: &mut Vec<WebRoute>) {
+    if let Some(node) = fstree.get_follow(&root.root) {
+        map_dir_rec(fstree, &node, &root.root, "", root, routes, &mut Vec::new());
+    }
+
+    // CGI directories outside of the document root
+    for (url, dir) in &root.cgi_dirs {
+        if dir.starts_with(&format!("{}/", root.root)) {
+            continue;
+        }
+        if let Some(node) = fstree.get_follow(dir) {
+            map_dir_rec(fstree, &node, dir, url, root, routes, &mut Vec::new());
+        }
+    }
+}
+
+/*
+    Routes of the files of a directory, fs_dir is the path of the directory
+    as served (through the symlinks). Symlinks are followed inside the image,
+    parents are the directories being mapped, to stop on link loops.
+*/
+fn map_dir_rec(
+    fstree: &FsTree,
+    node: &Node,
+    fs_dir: &str,
+    url: &str,
+    root: &WebRoot,
+    routes: &mut Vec<WebRoute>,
+    parents: &mut Vec<String>,
+) {
+    let dir_path = node.fs_path();
+    if parents.contains(&dir_path) {
+        return;
+    }
+    parents.push(dir_path);
+
+    for child in node.childrens() {
+        let name = child.name();
+        let child_fs_path = format!("{}/{}", fs_dir.trim_end_matches('/'), name);
+        let child_url = format!("{}/{}", url, name);
+
+        let child = if child.is_symlink() {
+            match fstree.get_follow(&child.fs_path()) {
+                Some(target) => target,
+                None => continue,
+            }
+        } else {
+            child
+        };
+
+        if child.is_dir() {
+            map_dir_rec(fstree, &child, &child_fs_path, &child_url, root, routes, parents);
+        } else if child.is_file() {
+            let in_cgi_dir = root
+                .cgi_dirs
+                .iter()
+                .any(|(_, dir)| child_fs_path.starts_with(&format!("{}/", dir)));
+
+            routes.push(WebRoute {
+                url: child_url,
+                handler: handler_of(&child, in_cgi_dir),
+                node: child,
+            });
+        }
+    }
+    parents.pop();
+}
+
+/*
+    Find the handler of a file from its type and its location
+*/
+fn handler_of(node: &Node, in_cgi_dir: bool) -> Handler {
+    match node.file_type() {
+        Some(FileType::Php) => Handler::Php,
+        Some(FileType::Lua) => Handler::Lua,
+        Some(FileType::Asp) | Some(FileType::Aspx) => Handler::Asp,
+        Some(FileType::Elf(_)) if in_cgi_dir => Handler::CgiBinary,
+        Some(FileType::Sh) if in_cgi_dir => Handler::Shell,
+        Some(FileType::Cgi) => handler_from_content(node),
+        Some(FileType::Data) | None if in_cgi_dir => handler_from_content(node),
+        _ => Handler::Static,
+    }
+}
+
+/*
+    CGI without a known type: look at the ELF magic or the shebang
+*/
+fn handler_from_content(node: &Node) -> Handler {
+    let mut head = [0u8; 128];
+    let len = match fs::File::open(node.local_path()).and_then(|mut f| f.read(&mut head)) {
+        Ok(len) => len,
+        Err(_) => return Handler::Static,
+    };
+    let head = &head[..len];
+
+    if head.starts_with(b"\x7fELF") {
+        return Handler::CgiBinary;
+    }
+
+    let shebang = String::from_utf8_lossy(head).lines().next().unwrap_or("").to_string();
+    if !shebang.starts_with("#!") {
+        Handler::Static
+    } else if shebang.contains("lua") {
+        Handler::Lua
+    } else if shebang.contains("php") {
+        Handler::Php
+    } else {
+        Handler::Shell
+    }
+}
+
+/*
+    Patterns of a handler language
+*/
+pub struct Language {
+    // Request parameters
+    pub sources: Regex,
+    // Functions executing commands or code
+    pub sinks: Regex,
+    // Assignment of a variable, the variable name is the first group
+    pub assign: Regex,
+    // Format of a variable reference, {} is replaced by the variable name
+    pub var_ref: &'static str,
+}
+
+impl Language {
+    pub fn php() -> Self {
+        Self {
+            sources: Regex::new(r"\$_(GET|POST|REQUEST|COOKIE|SERVER|FILES)\b").unwrap(),
+            sinks: Regex::new(r"\b(system|exec|shell_exec|passthru|popen|proc_open|eval|assert|pcntl_exec)\s*\(|`").unwrap(),
+            assign: Regex::new(r"(\$\w+)\s*(\.?=)[^=]").unwrap(),
+            var_ref: r"{}\b",
+        }
+    }
+
+    pub fn lua() -> Self {
+        Self {
+            sources: Regex::new(r#"formvalue|formvaluetable|http\.content|getenv\s*\(\s*["'](QUERY_STRING|HTTP_\w+|REQUEST_\w+|CONTENT_\w+)"#).unwrap(),
+            sinks: Regex::new(r"\b(os\.execute|io\.popen|luci\.sys\.call|luci\.sys\.exec|loadstring|dofile|load)\s*\(").unwrap(),
+            assign: Regex::new(r"^\s*(?:local\s+)?(\w+)\s*(=)[^=]").unwrap(),
+            var_ref: r"\b{}\b",
+        }
+    }
+
+    pub fn shell() -> Self {
+        Self {
+            sources: Regex::new(r"QUERY_STRING|\$\{?(FORM|GET|POST)_\w+|\$\{?HTTP_\w+|REQUEST_URI|PATH_INFO|CONTENT_LENGTH").unwrap(),
+            sinks: Regex::new(r"\b(eval|sh\s+-c|bash\s+-c|system|exec)\b|\$\(|`").unwrap(),
+            assign: Regex::new(r"^\s*(?:export\s+|local\s+)?(\w+)(=)").unwrap(),
+            var_ref: r"\$\{?{}\b",
+        }
+    }
+}
+
+/*
+    Report lines where a request parameter, directly or through a variable
+    assigned from one, reaches a sink
+*/
+pub fn scan_handler(node: &Node, url: Option<&str>, language: &Language) -> Vec<SinkFinding> {
+    let mut findings = Vec::new();
+
+    let content = match fs::read(node.local_path()) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        Err(_) => return findings,
+    };
+
+    // Tainted variables and the regex matching their references
+    let mut tainted: Vec<(String, Regex)> = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let source = match language.sources.find(line) {
+            Some(m) => Some(m.as_str().to_string()),
+            None => tainted
+                .iter()
+                .find(|(_, re)| re.is_match(line))
+                .map(|(name, _)| name.clone()),
+        };
+
+        let source = match source {
+            Some(source) => source,
+            None => continue,
+        };
+
+        // Variable assigned from a tainted value (shell "read VAR" included)
+        let mut assigned: Option<String> = language.assign.captures(line).map(|cap| cap[1].to_string());
+        if assigned.is_none() && line.trim_start().starts_with("read ") {
+            assigned = line.split_whitespace().last().map(|v| v.to_string());
+        }
+        if let Some(var) = assigned {
+            if !tainted.iter().any(|(name, _)| *name == var) {
+                let pattern = language.var_ref.replace("{}", &regex::escape(&var));
+                if let Ok(re) = Regex::new(&pattern) {
+                    tainted.push((var, re));
+                }
+            }
+        }
+
+        if let Some(sink) = language.sinks.find(line) {
+            findings.push(SinkFinding {
+                node: node.clone(),
+                url: url.map(|u| u.to_string()),
+                line: i + 1,
+                sink: sink.as_str().trim_end_matches('(').trim().to_string(),
+                source,
+                code: line.to_string(),
+            });
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testutil::{image, remove, tree};
+
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn php_variables_are_matched_by_name() {
+        let php = b"<?php\n$cmd = $_GET['c'];\n$cmdline = 'ls';\nsystem($cmdline);\nsystem($cmd);\n";
+        let root = image("web_php_vars", &[("www/index.php", php)]);
+        let fstree = tree(&root);
+
+        let node = fstree.get("/www/index.php").unwrap();
+        let findings = scan_handler(&node, None, &Language::php());
+        let lines: Vec<(usize, &str)> = findings.iter().map(|f| (f.line, f.source.as_str())).collect();
+        assert_eq!(lines, vec![(5, "$cmd")]);
+
+        remove(&root);
+    }
+    #[test]
+    fn linked_roots_and_directories() {
+        let root = image(
+            "web_links",
+            &[
+                ("srv/www/index.html", b"<html></html>\n"),
+                ("usr/share/app/admin.php", b"<?php\nsystem($_GET['c']);\n"),
+                ("usr/lib/cgi-bin/status", b"#!/bin/sh\necho ok\n"),
+            ],
+        );
+        symlink("/srv/www", root.join("www")).unwrap();
+        symlink("../../usr/share/app/admin.php", root.join("srv/www/admin.php")).unwrap();
+        symlink("/usr/lib/cgi-bin", root.join("srv/www/cgi-bin")).unwrap();
+        symlink("/srv/www", root.join("srv/www/loop")).unwrap();
+        symlink("/missing", root.join("srv/www/dangling")).unwrap();
+        let fstree = tree(&root);
+
+        let analysis = WebAnalysis::new(&fstree);
+        assert_eq!(analysis.roots.len(), 1);
+        assert_eq!(analysis.roots[0].root, "/www");
+        assert_eq!(analysis.roots[0].cgi_dirs, vec![("/cgi-bin".to_string(), "/www/cgi-bin".to_string())]);
+
+        let mut routes: Vec<(&str, String, Handler)> = analysis
+            .routes
+            .iter()
+            .map(|route| (route.url.as_str(), route.node.fs_path(), route.handler))
+            .collect();
+        routes.sort_by(|a, b| a.0.cmp(b.0));
+        assert_eq!(
+            routes,
+            vec![
+                ("/admin.php", "/usr/share/app/admin.php".to_string(), Handler::Php),
+                ("/cgi-bin/status", "/usr/lib/cgi-bin/status".to_string(), Handler::Shell),
+                ("/index.html", "/srv/www/index.html".to_string(), Handler::Static),
+            ]
+        );
+        assert_eq!(analysis.findings.len(), 1);
+        assert_eq!(analysis.findings[0].url.as_deref(), Some("/admin.php"));
+
+        remove(&root);
+    }
+}
@@ -1,10 +1,12 @@
-use fs_analyzer_v2::core::analyzer;
-
+use fs_analyzer_v2::core::diff::series;
+use fs_analyzer_v2::core::diff::DiffOptions;
 use fs_analyzer_v2::core::fstree::FsTree;
+use fs_analyzer_v2::core::fstree::pass::PassRegistry;
 
 use std::env;
+use std::process;
 
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
 fn main() {
     env_logger::init();
@@ -16,42 +18,70 @@ fn main() {
     let start = Instant::now();
     
     
-    let fstree = FsTree::build_from_path(fs_0);
-    fstree.analyse_files_type();
-    fstree.calc_files_hash();
+    let fstree = match FsTree::build_from_path(fs_0) {
+        Ok(fstree) => fstree,
+        Err(err) => {
+            eprintln!("Can't build the tree: {}", err);
+            process::exit(1);
+        }
+    };
+    if let Err(err) = fstree.run_passes(&mut PassRegistry::with_default_passes()) {
+        eprintln!("Analysis failed: {}", err);
+    }
     //fstree.list_files();
-    fstree.analyse_binaries();
     println!("{} dirs and {} files in the tree", fstree.count_dirs(), fstree.count_files());
+    println!(
+        "{} unique files, {} symlinks and {} special files",
+        fstree.count_unique_files(),
+        fstree.count_symlinks(),
+        fstree.count_special_files()
+    );
     
+    if !fstree.errors().is_empty() {
+        fstree.display_errors();
+    }
     
     let duration = start.elapsed();
     println!("{:?} to build the fstree", duration);
 
-    /*let tree_data = analyzer::from_path(fs_0);
-    tree_data.analyse_tree();
-    //tree_data.display_files();
-
-    let duration = start.elapsed();
-    println!(
-        "{:?} to create the TreeData. {} files",
-        duration,
-        tree_data.count_files()
-    );
-
     let start = Instant::now();
-
-    let tree_data2 = analyzer::from_path(fs_1);
-    tree_data2.analyse_tree();
-    //tree_data.display_files();
-
+    
+    let fstree2 = build_tree(fs_1);
+    
     let duration = start.elapsed();
-    println!(
-        "{:?} to create the TreeData. {} files",
-        duration,
-        tree_data2.count_files()
-    );
-
-    let res = tree_data.compare_tree_data(tree_data2);
+    println!("{:?} to build the second fstree. {} files", duration, fstree2.count_files());
+    
+    let res = fstree.compare(&fstree2);
     res.display_data();
-    res.display_count();*/
+    res.display_count();
+    
+    // More than two trees: history of the whole series
+    if args.len() > 3 {
+        let mut trees = vec![fstree, fstree2];
+        for path in &args[3..] {
+            trees.push(build_tree(path));
+        }
+        
+        let series: Vec<(&str, &FsTree)> = args[1..].iter().map(|path| path.as_str()).zip(trees.iter()).collect();
+        let res = series::compare_series(&series, &DiffOptions::default());
+        res.display_timelines();
+        res.display_churn(2);
+    }
+}
+
+fn build_tree(path: &str) -> FsTree {
+    let fstree = match FsTree::build_from_path(path) {
+        Ok(fstree) => fstree,
+        Err(err) => {
+            eprintln!("Can't build the tree: {}", err);
+            process::exit(1);
+        }
+    };
+    if let Err(err) = fstree.run_passes(&mut PassRegistry::with_default_passes()) {
+        eprintln!("Analysis failed: {}", err);
+    }
+    if !fstree.errors().is_empty() {
+        fstree.display_errors();
+    }
+    fstree
 }